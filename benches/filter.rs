@@ -2,8 +2,9 @@
 #![allow(clippy::unit_arg)]
 extern crate test;
 
-use grcov::{CovResult, Function, FunctionMap};
+use grcov::{Contexts, CovResult, Function, FunctionMap};
 use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
 use test::{black_box, Bencher};
 
 #[bench]
@@ -13,20 +14,26 @@ fn bench_filter_covered(b: &mut Bencher) {
         "f1".to_string(),
         Function {
             start: 1,
+            end: None,
             executed: true,
+            count: 1,
         },
     );
     functions.insert(
         "f2".to_string(),
         Function {
             start: 2,
+            end: None,
             executed: false,
+            count: 0,
         },
     );
     let result = CovResult {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions,
+        regions: BTreeMap::new(),
+        contexts: Contexts::default(),
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -37,6 +44,8 @@ fn bench_filter_covered_no_functions(b: &mut Bencher) {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions: FxHashMap::default(),
+        regions: BTreeMap::new(),
+        contexts: Contexts::default(),
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -48,20 +57,26 @@ fn bench_filter_uncovered_no_lines_executed(b: &mut Bencher) {
         "f1".to_string(),
         Function {
             start: 1,
+            end: None,
             executed: true,
+            count: 1,
         },
     );
     functions.insert(
         "f2".to_string(),
         Function {
             start: 2,
+            end: None,
             executed: false,
+            count: 0,
         },
     );
     let result = CovResult {
         lines: [(1, 0), (2, 0), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions: FxHashMap::default(),
+        regions: BTreeMap::new(),
+        contexts: Contexts::default(),
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -73,20 +88,26 @@ fn bench_filter_covered_functions_executed(b: &mut Bencher) {
         "top-level".to_string(),
         Function {
             start: 1,
+            end: None,
             executed: true,
+            count: 1,
         },
     );
     functions.insert(
         "f".to_string(),
         Function {
             start: 2,
+            end: None,
             executed: true,
+            count: 1,
         },
     );
     let result = CovResult {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions,
+        regions: BTreeMap::new(),
+        contexts: Contexts::default(),
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -98,13 +119,17 @@ fn bench_filter_covered_toplevel_executed(b: &mut Bencher) {
         "top-level".to_string(),
         Function {
             start: 1,
+            end: None,
             executed: true,
+            count: 1,
         },
     );
     let result = CovResult {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions,
+        regions: BTreeMap::new(),
+        contexts: Contexts::default(),
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
@@ -116,20 +141,26 @@ fn bench_filter_uncovered_functions_not_executed(b: &mut Bencher) {
         "top-level".to_string(),
         Function {
             start: 1,
+            end: None,
             executed: true,
+            count: 1,
         },
     );
     functions.insert(
         "f".to_string(),
         Function {
             start: 7,
+            end: None,
             executed: false,
+            count: 0,
         },
     );
     let result = CovResult {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
         branches: [].iter().cloned().collect(),
         functions,
+        regions: BTreeMap::new(),
+        contexts: Contexts::default(),
     };
     b.iter(|| black_box(grcov::is_covered(&result)));
 }
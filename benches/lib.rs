@@ -5,6 +5,7 @@ extern crate test;
 use crossbeam::channel::unbounded;
 use grcov::{CovResult, Function, FunctionMap};
 use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -19,27 +20,29 @@ fn bench_lib_merge_results(b: &mut Bencher) {
         "f1".to_string(),
         Function {
             start: 1,
+            end: None,
             executed: false,
+            count: 0,
         },
     );
     functions1.insert(
         "f2".to_string(),
         Function {
             start: 2,
+            end: None,
             executed: false,
+            count: 0,
         },
     );
     let mut result = CovResult {
         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
-        branches: [
-            (1, vec![false, false]),
-            (2, vec![false, true]),
-            (4, vec![true]),
-        ]
-        .iter()
-        .cloned()
-        .collect(),
+        branches: [(1, vec![0, 0]), (2, vec![0, 1]), (4, vec![1])]
+            .iter()
+            .cloned()
+            .collect(),
         functions: functions1,
+        regions: BTreeMap::new(),
+        contexts: Contexts::default(),
     };
 
     let mut functions2: FunctionMap = FxHashMap::default();
@@ -47,14 +50,18 @@ fn bench_lib_merge_results(b: &mut Bencher) {
         "f1".to_string(),
         Function {
             start: 1,
+            end: None,
             executed: false,
+            count: 0,
         },
     );
     functions2.insert(
         "f2".to_string(),
         Function {
             start: 2,
+            end: None,
             executed: true,
+            count: 1,
         },
     );
     let result2 = CovResult {
@@ -62,15 +69,13 @@ fn bench_lib_merge_results(b: &mut Bencher) {
             .iter()
             .cloned()
             .collect(),
-        branches: [
-            (1, vec![false, false]),
-            (2, vec![false, true]),
-            (3, vec![true]),
-        ]
-        .iter()
-        .cloned()
-        .collect(),
+        branches: [(1, vec![0, 0]), (2, vec![0, 1]), (3, vec![1])]
+            .iter()
+            .cloned()
+            .collect(),
         functions: functions2,
+        regions: BTreeMap::new(),
+        contexts: Contexts::default(),
     };
 
     b.iter(|| black_box(grcov::merge_results(&mut result, result2.clone())));
@@ -3,10 +3,11 @@
 extern crate test;
 
 use grcov::{
-    output_activedata_etl, output_covdir, output_lcov, CovResult, CovResultIter, Function,
-    FunctionMap,
+    output_activedata_etl, output_covdir, output_lcov, Contexts, CovResult, CovResultIter,
+    Function, FunctionMap,
 };
 use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use tempfile::tempdir;
 use test::{black_box, Bencher};
@@ -27,19 +28,25 @@ fn generate_cov_result_iter() -> CovResultIter {
                                 "f1".to_string(),
                                 Function {
                                     start: 1,
+                                    end: None,
                                     executed: true,
+                                    count: 1,
                                 },
                             );
                             functions.insert(
                                 "f2".to_string(),
                                 Function {
                                     start: 2,
+                                    end: None,
                                     executed: false,
+                                    count: 0,
                                 },
                             );
                             functions
                         },
                         lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
+                        regions: BTreeMap::new(),
+                        contexts: Contexts::default(),
                     },
                 )
             }),
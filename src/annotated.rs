@@ -0,0 +1,252 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use symbolic_demangle::DemangleOptions;
+
+use crate::defs::*;
+use crate::error::GrcovError;
+use crate::html;
+
+/// Writes a source file annotated in the format of gcov's `.gcov` files: every line is prefixed
+/// with its execution count, `-` when it isn't instrumented or `#####` when it wasn't executed,
+/// the functions are listed before the line where they start and the branches after their line.
+fn write_source(
+    writer: &mut impl Write,
+    f: impl BufRead,
+    rel_path: &Path,
+    result: &CovResult,
+    branch_enabled: bool,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    let demangle_options = DemangleOptions::name_only();
+
+    let mut functions: Vec<(&String, &Function)> = result.functions.iter().collect();
+    functions.sort_by_key(|&(name, f)| (f.start, name));
+    let mut functions = functions.into_iter().peekable();
+
+    writeln!(writer, "{:>9}:{:>5}:Source:{}", "-", 0, rel_path.display())?;
+    for (i, line) in f.lines().enumerate() {
+        let line = line?;
+        let line_no = i as u32 + 1;
+
+        while let Some((name, function)) = functions.next_if(|(_, f)| f.start <= line_no) {
+            writeln!(
+                writer,
+                "function {} called {}",
+                demangle!(name, demangle, demangle_options),
+                function.count
+            )?;
+        }
+
+        let count = match result.lines.get(&line_no) {
+            Some(0) => "#####".to_string(),
+            Some(count) => count.to_string(),
+            None => "-".to_string(),
+        };
+        writeln!(writer, "{:>9}:{:>5}:{}", count, line_no, line)?;
+
+        if !branch_enabled {
+            continue;
+        }
+        if let Some(branches) = result.branches.get(&line_no) {
+            let executed = result.lines.get(&line_no).map(|&c| c > 0) == Some(true);
+            for (n, taken) in branches.iter().enumerate() {
+                if executed {
+                    writeln!(writer, "branch {:>2} taken {}", n, taken)?;
+                } else {
+                    writeln!(writer, "branch {:>2} never executed", n)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the annotated sources in a tree mirroring the source directory, with a `.gcov` file
+/// for each source (e.g. `src/main.rs.gcov`), or all of them to stdout when no output directory
+/// is given.
+pub fn output_annotated(
+    results: CovResultIter,
+    output_dir: Option<&str>,
+    branch_enabled: bool,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    let output = match output_dir {
+        Some(output_dir) => PathBuf::from(output_dir),
+        None => return write_annotated(results, std::io::stdout(), branch_enabled, demangle),
+    };
+    if output.exists() && !output.is_dir() {
+        return Err(GrcovError::Output(format!(
+            "{} is not a directory",
+            output.display()
+        )));
+    }
+
+    for (abs_path, rel_path, result) in results {
+        let f = match html::open_source(&abs_path, &rel_path) {
+            Some(f) => f,
+            None => continue,
+        };
+
+        let mut file_name = rel_path.file_name().unwrap().to_os_string();
+        file_name.push(".gcov");
+        let output_file = output.join(&rel_path).with_file_name(file_name);
+        let parent = output_file.parent().unwrap();
+        if fs::create_dir_all(parent).is_err() {
+            return Err(GrcovError::Output(format!(
+                "Cannot create directory {}",
+                parent.display()
+            )));
+        }
+        let mut writer = BufWriter::new(File::create(&output_file)?);
+        write_source(&mut writer, f, &rel_path, &result, branch_enabled, demangle)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes all the annotated sources in a single stream, one after the other.
+pub fn write_annotated(
+    results: CovResultIter,
+    output: impl Write,
+    branch_enabled: bool,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    let mut writer = BufWriter::new(output);
+    for (abs_path, rel_path, result) in results {
+        if let Some(f) = html::open_source(&abs_path, &rel_path) {
+            write_source(&mut writer, f, &rel_path, &result, branch_enabled, demangle)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHashMap;
+    use std::io::Cursor;
+
+    fn get_result() -> CovResult {
+        let mut functions = FxHashMap::default();
+        functions.insert(
+            "_ZN4test4mainE".to_string(),
+            Function {
+                start: 1,
+                end: None,
+                executed: true,
+                count: 1,
+            },
+        );
+        functions.insert(
+            "unused".to_string(),
+            Function {
+                start: 6,
+                end: None,
+                executed: false,
+                count: 0,
+            },
+        );
+        CovResult {
+            lines: [(1, 1), (2, 1), (3, 0), (6, 0), (7, 0)]
+                .iter()
+                .cloned()
+                .collect(),
+            branches: [(2, vec![1, 0]), (7, vec![0, 0])].iter().cloned().collect(),
+            functions,
+            ..Default::default()
+        }
+    }
+
+    const SOURCE: &str = "fn main() {
+    if true {
+        panic!();
+    }
+}
+fn unused() {
+    if false {}
+}
+";
+
+    #[test]
+    fn test_write_source() {
+        let mut output = Vec::new();
+        write_source(
+            &mut output,
+            Cursor::new(SOURCE),
+            Path::new("src/main.rs"),
+            &get_result(),
+            true,
+            true,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "        -:    0:Source:src/main.rs
+function test::main called 1
+        1:    1:fn main() {
+        1:    2:    if true {
+branch  0 taken 1
+branch  1 taken 0
+    #####:    3:        panic!();
+        -:    4:    }
+        -:    5:}
+function unused called 0
+    #####:    6:fn unused() {
+    #####:    7:    if false {}
+branch  0 never executed
+branch  1 never executed
+        -:    8:}
+"
+        );
+    }
+
+    #[test]
+    fn test_write_source_no_branch() {
+        let mut output = Vec::new();
+        write_source(
+            &mut output,
+            Cursor::new(SOURCE),
+            Path::new("src/main.rs"),
+            &get_result(),
+            false,
+            false,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("function _ZN4test4mainE called 1\n"));
+        assert!(!output.contains("branch"));
+    }
+
+    #[test]
+    fn test_output_annotated() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let source = tmp_dir.path().join("main.rs");
+        fs::write(&source, SOURCE).unwrap();
+        let output = tmp_dir.path().join("annotated");
+
+        let results = vec![
+            (source.clone(), PathBuf::from("src/main.rs"), get_result()),
+            // The files which can't be read are skipped.
+            (
+                tmp_dir.path().join("missing.rs"),
+                PathBuf::from("missing.rs"),
+                get_result(),
+            ),
+        ];
+        output_annotated(
+            Box::new(results.into_iter()),
+            Some(output.to_str().unwrap()),
+            false,
+            false,
+        )
+        .unwrap();
+
+        let annotated = fs::read_to_string(output.join("src").join("main.rs.gcov")).unwrap();
+        assert!(annotated.starts_with("        -:    0:Source:src"));
+        assert!(annotated.contains("    #####:    3:        panic!();\n"));
+        assert!(!output.join("missing.rs.gcov").exists());
+    }
+}
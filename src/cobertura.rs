@@ -8,24 +8,12 @@ use std::{
     collections::BTreeSet,
     io::{BufWriter, Cursor, Write},
 };
-use symbolic_common::Name;
-use symbolic_demangle::{Demangle, DemangleOptions};
+use symbolic_demangle::DemangleOptions;
 
 use crate::defs::CovResultIter;
+use crate::error::GrcovError;
 use crate::output::get_target_output_writable;
 
-macro_rules! demangle {
-    ($name: expr, $demangle: expr, $options: expr) => {{
-        if $demangle {
-            Name::from($name)
-                .demangle($options)
-                .unwrap_or_else(|| $name.clone())
-        } else {
-            $name.clone()
-        }
-    }};
-}
-
 // http://cobertura.sourceforge.net/xml/coverage-04.dtd
 
 struct Coverage {
@@ -262,7 +250,7 @@ fn get_coverage(
                         .enumerate()
                         .map(|(i, b)| Condition {
                             cond_type: ConditionType::Jump,
-                            coverage: if *b { 1.0 } else { 0.0 },
+                            coverage: if *b > 0 { 1.0 } else { 0.0 },
                             number: i,
                         })
                         .collect::<Vec<_>>();
@@ -281,10 +269,16 @@ fn get_coverage(
                 .map(|(name, function)| {
                     let mut func_end = end;
 
-                    for start in &start_indexes {
-                        if *start > function.start {
-                            func_end = *start;
-                            break;
+                    // When the end of the function is unknown, it is assumed to be the start of
+                    // the following one.
+                    if let Some(function_end) = function.end {
+                        func_end = function_end + 1;
+                    } else {
+                        for start in &start_indexes {
+                            if *start > function.start {
+                                func_end = *start;
+                                break;
+                            }
                         }
                     }
 
@@ -303,7 +297,7 @@ fn get_coverage(
                         .collect();
 
                     Method {
-                        name: demangle!(name, demangle, demangle_options),
+                        name: demangle!(name, demangle, demangle_options).to_string(),
                         signature: String::new(),
                         lines,
                     }
@@ -337,7 +331,21 @@ pub fn output_cobertura(
     results: CovResultIter,
     output_file: Option<&str>,
     demangle: bool,
-) {
+) -> Result<(), GrcovError> {
+    write_cobertura(
+        source_dir,
+        results,
+        get_target_output_writable(output_file)?,
+        demangle,
+    )
+}
+
+pub fn write_cobertura(
+    source_dir: &str,
+    results: CovResultIter,
+    output: impl Write,
+    demangle: bool,
+) -> Result<(), GrcovError> {
     let demangle_options = DemangleOptions::name_only();
     let sources = vec![match source_dir {
         "" => ".",
@@ -497,8 +505,10 @@ pub fn output_cobertura(
         .unwrap();
 
     let result = writer.into_inner().into_inner();
-    let mut file = BufWriter::new(get_target_output_writable(output_file));
-    file.write_all(&result).unwrap();
+    let mut file = BufWriter::new(output);
+    file.write_all(&result)?;
+    file.flush()?;
+    Ok(())
 }
 
 fn write_lines(writer: &mut Writer<Cursor<Vec<u8>>>, lines: &[Line]) {
@@ -565,7 +575,7 @@ fn write_lines(writer: &mut Writer<Cursor<Vec<u8>>>, lines: &[Line]) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{CovResult, Function};
+    use crate::{Contexts, CovResult, Function};
     use std::io::Read;
     use std::{collections::BTreeMap, path::PathBuf};
     use std::{fs::File, path::Path};
@@ -604,8 +614,8 @@ mod tests {
                 .collect(),
                 branches: {
                     let mut map = BTreeMap::new();
-                    map.insert(3, vec![true, false]);
-                    map.insert(5, vec![false, false]);
+                    map.insert(3, vec![1, 0]);
+                    map.insert(5, vec![0, 0]);
                     map
                 },
                 functions: {
@@ -614,11 +624,15 @@ mod tests {
                         "_ZN8cov_test4main17h7eb435a3fb3e6f20E".to_string(),
                         Function {
                             start: 1,
+                            end: None,
                             executed: true,
+                            count: 1,
                         },
                     );
                     map
                 },
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
             Result::Test => CovResult {
                 /* main.rs
@@ -649,7 +663,7 @@ mod tests {
                 .collect(),
                 branches: {
                     let mut map = BTreeMap::new();
-                    map.insert(8, vec![true, false]);
+                    map.insert(8, vec![1, 0]);
                     map
                 },
                 functions: {
@@ -658,7 +672,9 @@ mod tests {
                         "_ZN8cov_test7test_fn17hbf19ec7bfabe8524E".to_string(),
                         Function {
                             start: 6,
+                            end: None,
                             executed: true,
+                            count: 1,
                         },
                     );
 
@@ -666,7 +682,9 @@ mod tests {
                         "_ZN8cov_test4main17h7eb435a3fb3e6f20E".to_string(),
                         Function {
                             start: 1,
+                            end: None,
                             executed: false,
+                            count: 0,
                         },
                     );
 
@@ -674,7 +692,9 @@ mod tests {
                         "_ZN8cov_test4main17h29b45b3d7d8851d2E".to_string(),
                         Function {
                             start: 1,
+                            end: None,
                             executed: true,
+                            count: 1,
                         },
                     );
 
@@ -683,7 +703,9 @@ mod tests {
                             .to_string(),
                         Function {
                             start: 6,
+                            end: None,
                             executed: true,
+                            count: 1,
                         },
                     );
 
@@ -691,11 +713,15 @@ mod tests {
                         "_ZN8cov_test4main17h679717cd8503f8adE".to_string(),
                         Function {
                             start: 1,
+                            end: None,
                             executed: false,
+                            count: 0,
                         },
                     );
                     map
                 },
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         }
     }
@@ -721,7 +747,7 @@ mod tests {
         )];
 
         let results = Box::new(results.into_iter());
-        output_cobertura("", results, Some(file_path.to_str().unwrap()), true);
+        output_cobertura("", results, Some(file_path.to_str().unwrap()), true).unwrap();
 
         let results = read_file(&file_path);
 
@@ -756,7 +782,7 @@ mod tests {
         )];
 
         let results = Box::new(results.into_iter());
-        output_cobertura("", results, Some(file_path.to_str().unwrap()), true);
+        output_cobertura("", results, Some(file_path.to_str().unwrap()), true).unwrap();
 
         let results = read_file(&file_path);
 
@@ -776,6 +802,38 @@ mod tests {
         assert!(results.contains(r#"branch-rate="0.5""#));
     }
 
+    #[test]
+    fn test_cobertura_function_end() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_name = "test_cobertura.xml";
+        let file_path = tmp_dir.path().join(&file_name);
+
+        let mut result = coverage_result(Result::Main);
+        result
+            .functions
+            .get_mut("_ZN8cov_test4main17h7eb435a3fb3e6f20E")
+            .unwrap()
+            .end = Some(4);
+        let results = vec![(
+            PathBuf::from("src/main.rs"),
+            PathBuf::from("src/main.rs"),
+            result,
+        )];
+
+        let results = Box::new(results.into_iter());
+        output_cobertura("", results, Some(file_path.to_str().unwrap()), true).unwrap();
+
+        let results = read_file(&file_path);
+
+        // The lines after the end of the function are not part of the method.
+        let start = results.find("<method ").unwrap();
+        let end = results.find("</method>").unwrap();
+        let method = &results[start..end];
+        assert!(method.contains(r#"line number="4""#));
+        assert!(!method.contains(r#"line number="5""#));
+        assert!(results.contains(r#"line number="5""#));
+    }
+
     #[test]
     fn test_cobertura_multiple_files() {
         let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
@@ -796,7 +854,7 @@ mod tests {
         ];
 
         let results = Box::new(results.into_iter());
-        output_cobertura("", results, Some(file_path.to_str().unwrap()), true);
+        output_cobertura("", results, Some(file_path.to_str().unwrap()), true).unwrap();
 
         let results = read_file(&file_path);
 
@@ -829,7 +887,7 @@ mod tests {
         )];
 
         let results = Box::new(results.into_iter());
-        output_cobertura("", results, Some(file_path.to_str().unwrap()), true);
+        output_cobertura("", results, Some(file_path.to_str().unwrap()), true).unwrap();
 
         let results = read_file(&file_path);
 
@@ -850,7 +908,7 @@ mod tests {
         )];
 
         let results = Box::new(results.into_iter());
-        output_cobertura("src", results, Some(file_path.to_str().unwrap()), true);
+        output_cobertura("src", results, Some(file_path.to_str().unwrap()), true).unwrap();
 
         let results = read_file(&file_path);
 
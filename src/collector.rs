@@ -0,0 +1,517 @@
+use crossbeam::channel::bounded;
+use log::{error, warn};
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::defs::*;
+use crate::error::GrcovError;
+use crate::intermediate::{is_intermediate_file, IntermediateCoverage};
+use crate::{canonicalize_path, consumer, producer, rewrite_paths, set_context, FileFilter};
+
+/// Builds the coverage collection pipeline: the input files are found by a producer thread,
+/// parsed by consumer threads, aggregated, and their paths rewritten.
+///
+/// ```no_run
+/// use grcov::CoverageCollector;
+///
+/// let results = CoverageCollector::new(&["target/debug"])
+///     .binary_path("target/debug")
+///     .source_dir(".")
+///     .ignore_not_existing(true)
+///     .ignore_dirs(&["/*"])
+///     .collect()
+///     .unwrap();
+/// for (_, rel_path, result) in results {
+///     println!("{}: {} lines", rel_path.display(), result.lines.len());
+/// }
+/// ```
+pub struct CoverageCollector {
+    paths: Vec<String>,
+    binary_path: Option<PathBuf>,
+    source_dir: Option<PathBuf>,
+    prefix_dir: Option<PathBuf>,
+    path_mapping_file: Option<PathBuf>,
+    ignore_not_existing: bool,
+    ignore_dirs: Vec<String>,
+    keep_dirs: Vec<String>,
+    filter: Option<bool>,
+    file_filter: FileFilter,
+    branch_enabled: bool,
+    is_llvm: bool,
+    guess_directory: bool,
+    num_threads: usize,
+    strict: bool,
+    contexts: Vec<(String, Vec<String>)>,
+}
+
+impl CoverageCollector {
+    /// Creates a collector reading the coverage files in the given paths (directories, zip
+    /// archives or files).
+    pub fn new<S: AsRef<str>>(paths: &[S]) -> Self {
+        CoverageCollector {
+            paths: paths.iter().map(|p| p.as_ref().to_string()).collect(),
+            binary_path: None,
+            source_dir: None,
+            prefix_dir: None,
+            path_mapping_file: None,
+            ignore_not_existing: false,
+            ignore_dirs: Vec::new(),
+            keep_dirs: Vec::new(),
+            filter: None,
+            file_filter: FileFilter::default(),
+            branch_enabled: false,
+            is_llvm: false,
+            guess_directory: false,
+            num_threads: 1.max(num_cpus::get() - 1),
+            strict: false,
+            contexts: Vec::new(),
+        }
+    }
+
+    /// The path to the compiled binary, needed for source-based coverage.
+    pub fn binary_path<P: Into<PathBuf>>(mut self, binary_path: P) -> Self {
+        self.binary_path = Some(binary_path.into());
+        self
+    }
+
+    /// The root directory of the source files.
+    pub fn source_dir<P: Into<PathBuf>>(mut self, source_dir: P) -> Self {
+        self.source_dir = Some(source_dir.into());
+        self
+    }
+
+    /// The prefix removed from the absolute paths, defaults to the source directory.
+    pub fn prefix_dir<P: Into<PathBuf>>(mut self, prefix_dir: P) -> Self {
+        self.prefix_dir = Some(prefix_dir.into());
+        self
+    }
+
+    /// A JSON file mapping the paths of the build directory to the source directory.
+    pub fn path_mapping_file<P: Into<PathBuf>>(mut self, path_mapping_file: P) -> Self {
+        self.path_mapping_file = Some(path_mapping_file.into());
+        self
+    }
+
+    /// Ignores the source files which can't be found on disk.
+    pub fn ignore_not_existing(mut self, ignore_not_existing: bool) -> Self {
+        self.ignore_not_existing = ignore_not_existing;
+        self
+    }
+
+    /// Globs of the files to ignore.
+    pub fn ignore_dirs<S: AsRef<str>>(mut self, ignore_dirs: &[S]) -> Self {
+        self.ignore_dirs = ignore_dirs.iter().map(|d| d.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Globs of the files to keep, the other ones are ignored.
+    pub fn keep_dirs<S: AsRef<str>>(mut self, keep_dirs: &[S]) -> Self {
+        self.keep_dirs = keep_dirs.iter().map(|d| d.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Only keeps the covered files (`Some(true)`) or the uncovered ones (`Some(false)`).
+    pub fn filter(mut self, filter: Option<bool>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// The exclusion markers of lines and branches in the source files.
+    pub fn file_filter(mut self, file_filter: FileFilter) -> Self {
+        self.file_filter = file_filter;
+        self
+    }
+
+    /// Collects branch coverage.
+    pub fn branch(mut self, branch_enabled: bool) -> Self {
+        self.branch_enabled = branch_enabled;
+        self
+    }
+
+    /// Parses the gcno/gcda files directly instead of running gcov.
+    pub fn llvm(mut self, is_llvm: bool) -> Self {
+        self.is_llvm = is_llvm;
+        self
+    }
+
+    /// Guesses the source directory of relative paths.
+    pub fn guess_directory(mut self, guess_directory: bool) -> Self {
+        self.guess_directory = guess_directory;
+        self
+    }
+
+    /// The number of threads parsing the coverage files.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// In strict mode, the collection fails when some inputs can't be processed, with all their
+    /// errors. Otherwise, they are logged and skipped.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    fn source_root(&self) -> Result<Option<PathBuf>, GrcovError> {
+        match self.source_dir {
+            Some(ref source_dir) if !source_dir.as_os_str().is_empty() => {
+                Ok(Some(canonicalize_path(source_dir).map_err(|_| {
+                    GrcovError::Input(
+                        source_dir.display().to_string(),
+                        "Source directory does not exist".to_string(),
+                    )
+                })?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // Parses the coverage files with the producer and consumer threads.
+    fn parse(
+        &self,
+        paths: Vec<String>,
+        source_root: Option<PathBuf>,
+        failures: &mut Vec<GrcovError>,
+    ) -> Result<IntermediateCoverage, GrcovError> {
+        let tmp_dir = tempfile::tempdir()?;
+        let tmp_path = tmp_dir.path().to_owned();
+
+        let num_threads = self.num_threads;
+        let result_map: Arc<SyncCovResultMap> = Arc::new(Mutex::new(
+            FxHashMap::with_capacity_and_hasher(20_000, Default::default()),
+        ));
+        let (sender, receiver) = bounded(2 * num_threads);
+
+        // The working directories of the consumers are created upfront, so that a failure doesn't
+        // leave the producer waiting for them.
+        for i in 0..num_threads {
+            fs::create_dir(tmp_path.join(format!("{}", i)))?;
+        }
+
+        let producer = {
+            let sender: JobSender = sender.clone();
+            let tmp_path = tmp_path.clone();
+            let filter = self.filter;
+            let is_llvm = self.is_llvm;
+
+            thread::Builder::new()
+                .name(String::from("Producer"))
+                .spawn(move || {
+                    let mut failures = Vec::new();
+                    let result = producer(
+                        &tmp_path,
+                        &paths,
+                        &sender,
+                        filter == Some(true),
+                        is_llvm,
+                        &mut failures,
+                    );
+                    (result, failures)
+                })?
+        };
+
+        let mut parsers = Vec::new();
+
+        for i in 0..num_threads {
+            let receiver = receiver.clone();
+            let result_map = Arc::clone(&result_map);
+            let working_dir = tmp_path.join(format!("{}", i));
+            let source_root = source_root.clone();
+            let binary_path = self.binary_path.clone();
+            let branch_enabled = self.branch_enabled;
+            let guess_directory = self.guess_directory;
+
+            let t = thread::Builder::new()
+                .name(format!("Consumer {}", i))
+                .spawn(move || {
+                    consumer(
+                        &working_dir,
+                        source_root.as_deref(),
+                        &result_map,
+                        receiver,
+                        branch_enabled,
+                        guess_directory,
+                        binary_path.as_deref(),
+                    )
+                })?;
+
+            parsers.push(t);
+        }
+
+        let producer_result = producer.join();
+
+        // Poison the receiver, now that the producer is finished.
+        for _ in 0..num_threads {
+            sender.send(None).unwrap();
+        }
+
+        let mut consumers_failed = false;
+        for parser in parsers {
+            match parser.join() {
+                Ok(mut errors) => failures.append(&mut errors),
+                Err(_) => consumers_failed = true,
+            }
+        }
+
+        let (producer_path_mapping_buf, mut producer_failures) = producer_result
+            .map_err(|_| GrcovError::Other("The producer thread panicked".to_string()))?;
+        failures.append(&mut producer_failures);
+        let producer_path_mapping_buf = producer_path_mapping_buf?;
+        if consumers_failed {
+            return Err(GrcovError::Other("A consumer thread panicked".to_string()));
+        }
+
+        let path_mapping = producer_path_mapping_buf
+            .map(|buf| serde_json::from_slice(&buf))
+            .transpose()
+            .map_err(|e| GrcovError::Input("linked-files-map.json".to_string(), e.to_string()))?;
+        let results = Arc::try_unwrap(result_map).unwrap().into_inner().unwrap();
+
+        Ok(IntermediateCoverage {
+            results,
+            path_mapping,
+        })
+    }
+
+    // Parses the coverage files and merges the intermediate coverage files of a set of inputs.
+    fn collect_paths(
+        &self,
+        paths: &[String],
+        failures: &mut Vec<GrcovError>,
+    ) -> Result<IntermediateCoverage, GrcovError> {
+        let (intermediate_paths, paths): (Vec<String>, Vec<String>) = paths
+            .iter()
+            .cloned()
+            .partition(|path| is_intermediate_file(Path::new(path)));
+
+        let mut coverage = if paths.is_empty() && !intermediate_paths.is_empty() {
+            IntermediateCoverage::default()
+        } else {
+            self.parse(paths, self.source_root()?, failures)?
+        };
+
+        let intermediates: Vec<_> = intermediate_paths
+            .par_iter()
+            .map(|path| IntermediateCoverage::read_file(Path::new(path)))
+            .collect();
+        let mut warn_overflow = false;
+        for intermediate in intermediates {
+            match intermediate {
+                Ok(intermediate) => warn_overflow |= coverage.merge(intermediate),
+                Err(e) => failures.push(e),
+            }
+        }
+        if warn_overflow {
+            warn!("Execution count overflow detected.");
+        }
+
+        Ok(coverage)
+    }
+
+    /// Adds inputs whose coverage is labelled with a test context (e.g. "unit" or "integration").
+    pub fn context<S: AsRef<str>>(mut self, label: &str, paths: &[S]) -> Self {
+        let paths = paths.iter().map(|p| p.as_ref().to_string()).collect();
+        self.contexts.push((label.to_string(), paths));
+        self
+    }
+
+    /// Runs the pipeline and returns the aggregated results, before their paths are rewritten.
+    /// The intermediate coverage files among the inputs are merged with the other results.
+    pub fn collect_intermediate(&self) -> Result<IntermediateCoverage, GrcovError> {
+        let mut failures = Vec::new();
+        let mut coverage = if self.paths.is_empty() && !self.contexts.is_empty() {
+            IntermediateCoverage::default()
+        } else {
+            self.collect_paths(&self.paths, &mut failures)?
+        };
+
+        let mut warn_overflow = false;
+        for (label, paths) in &self.contexts {
+            let mut context_coverage = self.collect_paths(paths, &mut failures)?;
+            for result in context_coverage.results.values_mut() {
+                set_context(result, label);
+            }
+            warn_overflow |= coverage.merge(context_coverage);
+        }
+        if warn_overflow {
+            warn!("Execution count overflow detected.");
+        }
+
+        if !failures.is_empty() {
+            if self.strict {
+                return Err(GrcovError::Failures(failures));
+            }
+            for failure in failures {
+                error!("{}", failure);
+            }
+        }
+
+        Ok(coverage)
+    }
+
+    /// Rewrites the paths of aggregated results and filters them, returns tuples of the absolute
+    /// path, the relative path and the coverage of every source file.
+    pub fn rewrite(
+        self,
+        coverage: IntermediateCoverage,
+    ) -> Result<Vec<(PathBuf, PathBuf, CovResult)>, GrcovError> {
+        let source_root = self.source_root()?;
+        let prefix_dir = self.prefix_dir.or_else(|| source_root.clone());
+
+        let path_mapping: Option<Value> = match self.path_mapping_file {
+            Some(path_mapping_file) => {
+                let input_error = |e: &dyn std::fmt::Display| {
+                    GrcovError::Input(path_mapping_file.display().to_string(), e.to_string())
+                };
+                let file = File::open(&path_mapping_file).map_err(|e| input_error(&e))?;
+                Some(serde_json::from_reader(file).map_err(|e| input_error(&e))?)
+            }
+            None => coverage.path_mapping,
+        };
+
+        let ignore_dirs: Vec<&str> = self.ignore_dirs.iter().map(String::as_str).collect();
+        let keep_dirs: Vec<&str> = self.keep_dirs.iter().map(String::as_str).collect();
+        let results = rewrite_paths(
+            coverage.results,
+            path_mapping,
+            source_root.as_deref(),
+            prefix_dir.as_deref(),
+            self.ignore_not_existing,
+            &ignore_dirs,
+            &keep_dirs,
+            self.filter,
+            self.file_filter,
+        );
+
+        Ok(results.collect())
+    }
+
+    /// Runs the pipeline and returns the aggregated results, as tuples of the absolute path,
+    /// the relative path and the coverage of every source file.
+    pub fn collect(self) -> Result<Vec<(PathBuf, PathBuf, CovResult)>, GrcovError> {
+        let coverage = self.collect_intermediate()?;
+        self.rewrite(coverage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collector() {
+        let results = CoverageCollector::new(&["test/llvm-cov/sample.info"])
+            .num_threads(2)
+            .collect()
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        let (_, rel_path, result) = &results[0];
+        assert_eq!(rel_path, &PathBuf::from("main.rs"));
+        assert_eq!(result.lines.get(&2), Some(&0));
+    }
+
+    #[test]
+    fn test_collector_ignore_dirs() {
+        let results = CoverageCollector::new(&["test/llvm-cov/sample.info"])
+            .ignore_dirs(&["*.rs"])
+            .collect()
+            .unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn test_collector_strict() {
+        let paths = ["test/llvm-cov/sample.info", "test/invalid_DA_record.info"];
+        // In lenient mode, the invalid file is skipped.
+        let results = CoverageCollector::new(&paths).collect().unwrap();
+        assert_eq!(results.len(), 1);
+
+        match CoverageCollector::new(&paths).strict(true).collect() {
+            Err(GrcovError::Failures(failures)) => {
+                assert_eq!(failures.len(), 1);
+                assert!(matches!(failures[0], GrcovError::Parse(..)));
+            }
+            _ => panic!("The collection should fail in strict mode"),
+        }
+    }
+
+    #[test]
+    fn test_collector_strict_invalid_inputs() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let zip_path = tmp_dir.path().join("corrupt.zip");
+        fs::write(&zip_path, b"not a zip file").unwrap();
+        let paths = [zip_path.to_str().unwrap(), "test/llvm-cov/sample.info"];
+        // In lenient mode, the corrupt archive is skipped.
+        let results = CoverageCollector::new(&paths).collect().unwrap();
+        assert_eq!(results.len(), 1);
+
+        match CoverageCollector::new(&paths).strict(true).collect() {
+            Err(GrcovError::Failures(failures)) => {
+                assert_eq!(failures.len(), 1);
+                assert!(matches!(failures[0], GrcovError::Input(..)));
+            }
+            _ => panic!("The collection should fail in strict mode"),
+        }
+    }
+
+    #[test]
+    fn test_collector_intermediate() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let shard_path = tmp_dir.path().join("shard.grcov");
+        let coverage = CoverageCollector::new(&["test/llvm-cov/sample.info"])
+            .collect_intermediate()
+            .unwrap();
+        coverage.write(File::create(&shard_path).unwrap()).unwrap();
+
+        // The intermediate files are merged with the other inputs.
+        let shard_path = shard_path.to_str().unwrap();
+        let results = CoverageCollector::new(&[shard_path, "test/llvm-cov/sample.info"])
+            .collect()
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].2.lines.get(&5), Some(&4));
+
+        let results = CoverageCollector::new(&[shard_path, shard_path])
+            .collect()
+            .unwrap();
+        assert_eq!(results[0].2.lines.get(&5), Some(&4));
+    }
+
+    #[test]
+    fn test_collector_contexts() {
+        let results = CoverageCollector::new(&["test/llvm-cov/sample.info"])
+            .context("unit", &["test/llvm-cov/sample.info"])
+            .context("integration", &["test/llvm-cov/sample.info"])
+            .collect()
+            .unwrap();
+        let result = &results[0].2;
+        assert_eq!(result.lines.get(&5), Some(&6));
+        let counts = &result.contexts.lines[&5];
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["unit"], 2);
+        assert_eq!(counts["integration"], 2);
+        assert!(!result.contexts.lines.contains_key(&2));
+        assert_eq!(
+            result.contexts.functions["_RNvCs32gj3y4fnmh_4main4main"]["unit"],
+            1
+        );
+        assert!(!result
+            .contexts
+            .functions
+            .contains_key("_RNvCs32gj3y4fnmh_4main6unused"));
+    }
+
+    #[test]
+    fn test_collector_source_dir_not_existing() {
+        assert!(CoverageCollector::new(&["test/llvm-cov/sample.info"])
+            .source_dir("this/does/not/exist")
+            .collect()
+            .is_err());
+    }
+}
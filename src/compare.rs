@@ -0,0 +1,545 @@
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::defs::*;
+use crate::error::GrcovError;
+use crate::html::get_stats;
+use crate::output::get_target_output_writable;
+use crate::parser::{parse_lcov, ParserError};
+
+/// The stats of the files of a report, by path.
+pub type ReportStats = FxHashMap<String, HtmlStats>;
+
+fn parse_covdir_node(
+    node: &Value,
+    path: &str,
+    results: &mut ReportStats,
+) -> Result<(), ParserError> {
+    let invalid = || ParserError::InvalidData(format!("Invalid covdir node '{}'", path));
+
+    if let Some(children) = node.get("children") {
+        let children = children.as_object().ok_or_else(invalid)?;
+        for (name, child) in children {
+            let child_path = if path.is_empty() {
+                name.clone()
+            } else if path.ends_with('/') {
+                format!("{}{}", path, name)
+            } else {
+                format!("{}/{}", path, name)
+            };
+            parse_covdir_node(child, &child_path, results)?;
+        }
+    } else if let Some(coverage) = node.get("coverage") {
+        let coverage = coverage.as_array().ok_or_else(invalid)?;
+        let mut stats = HtmlStats::default();
+        for count in coverage {
+            let count = count.as_i64().ok_or_else(invalid)?;
+            // -1 is used for the lines which are not instrumented.
+            if count >= 0 {
+                stats.total_lines += 1;
+                if count > 0 {
+                    stats.covered_lines += 1;
+                }
+            }
+        }
+        // The reports of older versions don't have the function and branch totals.
+        let get_total = |name: &str| match node.get(name) {
+            Some(value) => value.as_u64().map(|v| v as usize).ok_or_else(invalid),
+            None => Ok(0),
+        };
+        stats.total_funs = get_total("functionsTotal")?;
+        stats.covered_funs = get_total("functionsCovered")?;
+        stats.total_branches = get_total("branchesTotal")?;
+        stats.covered_branches = get_total("branchesCovered")?;
+        results.insert(path.to_string(), stats);
+    } else {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
+/// Parses a covdir report, as generated by the `covdir` output type.
+pub fn parse_covdir(buffer: &[u8]) -> Result<ReportStats, ParserError> {
+    let root: Value = serde_json::from_slice(buffer)
+        .map_err(|e| ParserError::InvalidData(format!("Invalid covdir report: {}", e)))?;
+    let mut results = FxHashMap::default();
+    parse_covdir_node(&root, "", &mut results)?;
+    Ok(results)
+}
+
+/// Loads the stats of the files of a previously generated lcov or covdir report.
+pub fn load_report(path: &Path) -> Result<ReportStats, ParserError> {
+    let mut buffer = Vec::new();
+    File::open(path)?.read_to_end(&mut buffer)?;
+
+    let is_json = buffer
+        .iter()
+        .find(|c| !c.is_ascii_whitespace())
+        .map_or(false, |&c| c == b'{');
+    if is_json {
+        return parse_covdir(&buffer);
+    }
+
+    let mut results: CovResultMap = FxHashMap::default();
+    for (path, result) in parse_lcov(buffer, true)? {
+        // The same file might be present in several records.
+        match results.get_mut(&path) {
+            Some(r) => {
+                crate::merge_results(r, result);
+            }
+            None => {
+                results.insert(path, result);
+            }
+        }
+    }
+    Ok(results
+        .iter()
+        .map(|(path, result)| (path.clone(), get_stats(result)))
+        .collect())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Added,
+    Removed,
+    Modified,
+    Unchanged,
+}
+
+/// Difference, in percentage points, between the coverage of two reports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct CoverageDelta {
+    pub lines: Option<f64>,
+    pub functions: Option<f64>,
+    pub branches: Option<f64>,
+}
+
+impl CoverageDelta {
+    fn new(base: &HtmlStats, current: &HtmlStats) -> Self {
+        fn delta(base: (usize, usize), current: (usize, usize)) -> Option<f64> {
+            Some(get_percent(current.0, current.1)? - get_percent(base.0, base.1)?)
+        }
+
+        Self {
+            lines: delta(
+                (base.covered_lines, base.total_lines),
+                (current.covered_lines, current.total_lines),
+            ),
+            functions: delta(
+                (base.covered_funs, base.total_funs),
+                (current.covered_funs, current.total_funs),
+            ),
+            branches: delta(
+                (base.covered_branches, base.total_branches),
+                (current.covered_branches, current.total_branches),
+            ),
+        }
+    }
+
+    fn is_regression(&self) -> bool {
+        [self.lines, self.functions, self.branches]
+            .iter()
+            .any(|d| d.map_or(false, |d| d < 0.))
+    }
+
+    fn is_zero(&self) -> bool {
+        [self.lines, self.functions, self.branches]
+            .iter()
+            .all(|d| d.map_or(true, |d| d == 0.))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CoverageChange {
+    pub base: Option<HtmlStats>,
+    pub current: Option<HtmlStats>,
+    pub delta: Option<CoverageDelta>,
+}
+
+impl CoverageChange {
+    fn new(base: Option<HtmlStats>, current: Option<HtmlStats>) -> Self {
+        let delta = match (&base, &current) {
+            (Some(base), Some(current)) => Some(CoverageDelta::new(base, current)),
+            _ => None,
+        };
+        Self {
+            base,
+            current,
+            delta,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FileChange {
+    pub status: FileStatus,
+    #[serde(flatten)]
+    pub change: CoverageChange,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CoverageDiff {
+    pub total: CoverageChange,
+    pub directories: BTreeMap<String, CoverageChange>,
+    pub files: BTreeMap<String, FileChange>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub regressed: Vec<String>,
+}
+
+fn get_percent(covered: usize, total: usize) -> Option<f64> {
+    if total != 0 {
+        Some(covered as f64 / total as f64 * 100.)
+    } else {
+        None
+    }
+}
+
+fn add_to_dirs(
+    dirs: &mut BTreeMap<String, (HtmlStats, HtmlStats)>,
+    path: &str,
+    stats: &HtmlStats,
+    is_base: bool,
+) {
+    for dir in Path::new(path).ancestors().skip(1) {
+        let dir = dir.to_str().unwrap();
+        if dir.is_empty() {
+            continue;
+        }
+        let entry = dirs.entry(dir.to_string()).or_default();
+        if is_base {
+            entry.0.add(stats);
+        } else {
+            entry.1.add(stats);
+        }
+    }
+}
+
+/// Computes the per-file, per-directory and global differences between two reports.
+pub fn compare_reports(base: &ReportStats, current: &ReportStats) -> CoverageDiff {
+    let paths: BTreeSet<&String> = base.keys().chain(current.keys()).collect();
+
+    let mut total_base = HtmlStats::default();
+    let mut total_current = HtmlStats::default();
+    let mut dirs = BTreeMap::new();
+    let mut files = BTreeMap::new();
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut regressed = Vec::new();
+
+    for path in paths {
+        let base_stats = base.get(path).cloned();
+        let current_stats = current.get(path).cloned();
+
+        if let Some(ref stats) = base_stats {
+            total_base.add(stats);
+            add_to_dirs(&mut dirs, path, stats, true);
+        }
+        if let Some(ref stats) = current_stats {
+            total_current.add(stats);
+            add_to_dirs(&mut dirs, path, stats, false);
+        }
+
+        let change = CoverageChange::new(base_stats, current_stats);
+        let status = match change.delta {
+            None if change.base.is_none() => {
+                added.push(path.clone());
+                FileStatus::Added
+            }
+            None => {
+                removed.push(path.clone());
+                FileStatus::Removed
+            }
+            Some(delta) => {
+                if delta.is_regression() {
+                    regressed.push(path.clone());
+                }
+                if delta.is_zero() {
+                    FileStatus::Unchanged
+                } else {
+                    FileStatus::Modified
+                }
+            }
+        };
+
+        files.insert(path.clone(), FileChange { status, change });
+    }
+
+    let directories = dirs
+        .into_iter()
+        .map(|(dir, (base, current))| {
+            let base = if base.total_lines != 0 || base.total_funs != 0 {
+                Some(base)
+            } else {
+                None
+            };
+            let current = if current.total_lines != 0 || current.total_funs != 0 {
+                Some(current)
+            } else {
+                None
+            };
+            (dir, CoverageChange::new(base, current))
+        })
+        .collect();
+
+    CoverageDiff {
+        total: CoverageChange::new(Some(total_base), Some(total_current)),
+        directories,
+        files,
+        added,
+        removed,
+        regressed,
+    }
+}
+
+fn format_change(change: &CoverageChange) -> String {
+    let format_kind = |name: &str, base: Option<f64>, current: Option<f64>, delta: Option<f64>| {
+        let percent = |p: Option<f64>| p.map_or("-".to_string(), |p| format!("{:.2}%", p));
+        match delta {
+            Some(delta) => format!(
+                "{} {} -> {} ({:+.2})",
+                name,
+                percent(base),
+                percent(current),
+                delta
+            ),
+            None => format!("{} {} -> {}", name, percent(base), percent(current)),
+        }
+    };
+
+    let base = change.base.as_ref();
+    let current = change.current.as_ref();
+    let delta = change.delta.unwrap_or_default();
+    [
+        format_kind(
+            "lines",
+            base.and_then(|s| get_percent(s.covered_lines, s.total_lines)),
+            current.and_then(|s| get_percent(s.covered_lines, s.total_lines)),
+            delta.lines,
+        ),
+        format_kind(
+            "functions",
+            base.and_then(|s| get_percent(s.covered_funs, s.total_funs)),
+            current.and_then(|s| get_percent(s.covered_funs, s.total_funs)),
+            delta.functions,
+        ),
+        format_kind(
+            "branches",
+            base.and_then(|s| get_percent(s.covered_branches, s.total_branches)),
+            current.and_then(|s| get_percent(s.covered_branches, s.total_branches)),
+            delta.branches,
+        ),
+    ]
+    .join(", ")
+}
+
+/// Writes a human readable summary of the differences between two reports, only the files and
+/// directories whose coverage changed are listed.
+pub fn output_coverage_diff_text(
+    diff: &CoverageDiff,
+    output_file: Option<&str>,
+) -> Result<(), GrcovError> {
+    let mut writer = BufWriter::new(get_target_output_writable(output_file)?);
+
+    writeln!(writer, "Total: {}", format_change(&diff.total))?;
+
+    let changed_dirs: Vec<_> = diff
+        .directories
+        .iter()
+        .filter(|(_, c)| c.delta.map_or(true, |d| !d.is_zero()))
+        .collect();
+    if !changed_dirs.is_empty() {
+        writeln!(writer, "\nDirectories:")?;
+        for (dir, change) in changed_dirs {
+            writeln!(writer, "{}: {}", dir, format_change(change))?;
+        }
+    }
+
+    let changed_files: Vec<_> = diff
+        .files
+        .iter()
+        .filter(|(_, f)| f.status == FileStatus::Modified)
+        .collect();
+    if !changed_files.is_empty() {
+        writeln!(writer, "\nFiles:")?;
+        for (file, change) in changed_files {
+            writeln!(writer, "{}: {}", file, format_change(&change.change))?;
+        }
+    }
+
+    for (title, files) in &[
+        ("Added files", &diff.added),
+        ("Removed files", &diff.removed),
+        ("Regressed files", &diff.regressed),
+    ] {
+        if !files.is_empty() {
+            writeln!(writer, "\n{}:", title)?;
+            for file in files.iter() {
+                writeln!(writer, "{}", file)?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn output_coverage_diff_json(
+    diff: &CoverageDiff,
+    output_file: Option<&str>,
+) -> Result<(), GrcovError> {
+    let mut writer = BufWriter::new(get_target_output_writable(output_file)?);
+    serde_json::to_writer(&mut writer, diff)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn result(lines: &[(u32, u64)], branches: &[(u32, Vec<u64>)]) -> HtmlStats {
+        get_stats(&CovResult {
+            lines: lines.iter().cloned().collect(),
+            branches: branches.iter().cloned().collect(),
+            functions: FxHashMap::default(),
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
+        })
+    }
+
+    #[test]
+    fn test_parse_covdir() {
+        let results =
+            load_report(&PathBuf::from("./test/test_covdir.json")).expect("Invalid report");
+        assert_eq!(results.len(), 4);
+        assert_eq!(results["foo/bar/b.cpp"].total_lines, 3);
+        assert_eq!(results["foo/bar/b.cpp"].covered_lines, 1);
+        assert_eq!(results["/foo/d.cpp"].total_lines, 2);
+        assert_eq!(results["/foo/d.cpp"].covered_lines, 1);
+    }
+
+    #[test]
+    fn test_compare_covdir_functions_branches() {
+        let report = |functions_covered: usize, branches_covered: usize| {
+            let report = json!({
+                "name": "",
+                "children": {
+                    "a.rs": {
+                        "name": "a.rs",
+                        "coverage": [1, -1, 0],
+                        "functionsTotal": 2,
+                        "functionsCovered": functions_covered,
+                        "branchesTotal": 4,
+                        "branchesCovered": branches_covered,
+                    },
+                },
+            });
+            parse_covdir(report.to_string().as_bytes()).unwrap()
+        };
+        let base = report(2, 1);
+        assert_eq!(base["a.rs"].total_lines, 2);
+        assert_eq!(base["a.rs"].total_funs, 2);
+        assert_eq!(base["a.rs"].covered_branches, 1);
+
+        let diff = compare_reports(&base, &report(1, 3));
+        assert_eq!(
+            diff.files["a.rs"].change.delta,
+            Some(CoverageDelta {
+                lines: Some(0.),
+                functions: Some(-50.),
+                branches: Some(50.),
+            })
+        );
+        assert_eq!(diff.regressed, vec!["a.rs"]);
+    }
+
+    #[test]
+    fn test_load_lcov() {
+        let results = load_report(&PathBuf::from("./test/prova_fn_with_commas.info"))
+            .expect("Invalid report");
+        assert_eq!(results.len(), 1);
+        assert!(results.contains_key("aFile.js"));
+    }
+
+    #[test]
+    fn test_compare_reports() {
+        let mut base: ReportStats = FxHashMap::default();
+        base.insert(
+            "src/a.rs".to_string(),
+            result(&[(1, 1), (2, 1)], &[(1, vec![1, 1])]),
+        );
+        base.insert("src/b.rs".to_string(), result(&[(1, 1), (2, 0)], &[]));
+        base.insert("src/old.rs".to_string(), result(&[(1, 1)], &[]));
+
+        let mut current: ReportStats = FxHashMap::default();
+        current.insert(
+            "src/a.rs".to_string(),
+            result(&[(1, 1), (2, 0)], &[(1, vec![1, 0])]),
+        );
+        current.insert("src/b.rs".to_string(), result(&[(1, 1), (2, 0)], &[]));
+        current.insert("src/sub/new.rs".to_string(), result(&[(1, 0)], &[]));
+
+        let diff = compare_reports(&base, &current);
+
+        assert_eq!(diff.added, vec!["src/sub/new.rs"]);
+        assert_eq!(diff.removed, vec!["src/old.rs"]);
+        assert_eq!(diff.regressed, vec!["src/a.rs"]);
+
+        assert_eq!(diff.files["src/a.rs"].status, FileStatus::Modified);
+        assert_eq!(
+            diff.files["src/a.rs"].change.delta,
+            Some(CoverageDelta {
+                lines: Some(-50.),
+                functions: None,
+                branches: Some(-50.),
+            })
+        );
+        assert_eq!(diff.files["src/b.rs"].status, FileStatus::Unchanged);
+
+        let total = diff.total.delta.unwrap();
+        assert_eq!(total.lines, Some(40. - 80.));
+        assert!(diff.directories["src"].delta.is_some());
+        assert!(diff.directories["src/sub"].base.is_none());
+        assert!(diff.directories["src/sub"].delta.is_none());
+    }
+
+    #[test]
+    fn test_output_coverage_diff_text() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_path = tmp_dir.path().join("test_coverage_diff.txt");
+
+        let mut base: ReportStats = FxHashMap::default();
+        base.insert("a.rs".to_string(), result(&[(1, 1), (2, 1)], &[]));
+        let mut current: ReportStats = FxHashMap::default();
+        current.insert("a.rs".to_string(), result(&[(1, 1), (2, 0)], &[]));
+
+        let diff = compare_reports(&base, &current);
+        output_coverage_diff_text(&diff, Some(file_path.to_str().unwrap())).unwrap();
+
+        let mut output = String::new();
+        File::open(&file_path)
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(
+            output,
+            "Total: lines 100.00% -> 50.00% (-50.00), functions - -> -, branches - -> -
+
+Files:
+a.rs: lines 100.00% -> 50.00% (-50.00), functions - -> -, branches - -> -
+
+Regressed files:
+a.rs
+"
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+use crate::error::GrcovError;
+
+/// The name of the grcov configuration file.
+pub const CONFIG_FILE_NAME: &str = "grcov.toml";
+
+/// The options which are paths, resolved against the directory of the configuration file.
+const PATH_OPTIONS: &[&str] = &[
+    "paths",
+    "binary-path",
+    "output-path",
+    "source-dir",
+    "path-mapping",
+    "patch",
+    "log",
+    "html-template-dir",
+];
+
+/// The value of an option in the configuration file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigValue {
+    Flag(bool),
+    Value(String),
+    Values(Vec<String>),
+}
+
+impl ConfigValue {
+    fn from_toml(value: &Value) -> Result<ConfigValue, String> {
+        fn scalar(value: &Value) -> Result<String, String> {
+            match value {
+                Value::String(s) => Ok(s.clone()),
+                Value::Integer(i) => Ok(i.to_string()),
+                Value::Float(f) => Ok(f.to_string()),
+                _ => Err(format!("unexpected value {}", value)),
+            }
+        }
+
+        match value {
+            Value::Boolean(b) => Ok(ConfigValue::Flag(*b)),
+            Value::Array(values) => Ok(ConfigValue::Values(
+                values.iter().map(scalar).collect::<Result<_, _>>()?,
+            )),
+            value => Ok(ConfigValue::Value(scalar(value)?)),
+        }
+    }
+
+    /// Returns the command line arguments setting the option `name` to this value.
+    pub fn to_args(&self, name: &str) -> Vec<String> {
+        match self {
+            ConfigValue::Flag(true) => vec![format!("--{}", name)],
+            ConfigValue::Flag(false) => Vec::new(),
+            ConfigValue::Value(value) => vec![format!("--{}={}", name, value)],
+            ConfigValue::Values(values) => values
+                .iter()
+                .map(|value| format!("--{}={}", name, value))
+                .collect(),
+        }
+    }
+}
+
+pub type ConfigOptions = BTreeMap<String, ConfigValue>;
+
+/// The options read from a `grcov.toml` file or from the `[package.metadata.grcov]` table of a
+/// `Cargo.toml`, named after the long command line flags:
+///
+/// ```toml
+/// branch = true
+/// ignore = ["/*", "tests/*"]
+/// output-type = "html"
+///
+/// [profile.ci]
+/// output-type = ["lcov", "cobertura"]
+/// output-path = ["lcov.info", "cobertura.xml"]
+/// ```
+#[derive(Debug, Default)]
+pub struct ProjectConfig {
+    pub path: PathBuf,
+    options: ConfigOptions,
+    profiles: BTreeMap<String, ConfigOptions>,
+}
+
+fn parse_options(table: &toml::value::Table) -> Result<ConfigOptions, String> {
+    table
+        .iter()
+        .map(|(name, value)| {
+            ConfigValue::from_toml(value)
+                .map(|value| (name.clone(), value))
+                .map_err(|e| format!("Invalid option '{}': {}", name, e))
+        })
+        .collect()
+}
+
+impl ProjectConfig {
+    fn from_table(path: &Path, mut table: toml::value::Table) -> Result<ProjectConfig, String> {
+        let mut profiles = BTreeMap::new();
+        if let Some(profile_table) = table.remove("profile") {
+            let profile_table = match profile_table {
+                Value::Table(t) => t,
+                _ => return Err("'profile' must be a table".to_string()),
+            };
+            for (name, profile) in profile_table {
+                match profile {
+                    Value::Table(t) => {
+                        profiles.insert(name, parse_options(&t)?);
+                    }
+                    _ => return Err(format!("The profile '{}' must be a table", name)),
+                }
+            }
+        }
+
+        Ok(ProjectConfig {
+            path: path.to_path_buf(),
+            options: parse_options(&table)?,
+            profiles,
+        })
+    }
+
+    fn parse(path: &Path, content: &str) -> Result<Option<ProjectConfig>, String> {
+        let table = match toml::from_str::<Value>(content).map_err(|e| e.to_string())? {
+            Value::Table(t) => t,
+            _ => return Err("Invalid TOML document".to_string()),
+        };
+        if path.file_name() != Some(OsStr::new("Cargo.toml")) {
+            return ProjectConfig::from_table(path, table).map(Some);
+        }
+
+        match table
+            .get("package")
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("grcov"))
+        {
+            Some(Value::Table(t)) => ProjectConfig::from_table(path, t.clone()).map(Some),
+            Some(_) => Err("[package.metadata.grcov] must be a table".to_string()),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a configuration file, the grcov options of a `Cargo.toml` are read from its
+    /// `[package.metadata.grcov]` table.
+    pub fn load(path: &Path) -> Result<Option<ProjectConfig>, GrcovError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| GrcovError::Input(path.display().to_string(), e.to_string()))?;
+        ProjectConfig::parse(path, &content)
+            .map_err(|e| GrcovError::Input(path.display().to_string(), e))
+    }
+
+    /// Looks for a `grcov.toml`, then for a `Cargo.toml` with grcov options, in each of the given
+    /// directories.
+    pub fn find(dirs: &[&Path]) -> Result<Option<ProjectConfig>, GrcovError> {
+        for dir in dirs {
+            for file_name in &[CONFIG_FILE_NAME, "Cargo.toml"] {
+                let path = dir.join(file_name);
+                if path.is_file() {
+                    if let Some(config) = ProjectConfig::load(&path)? {
+                        return Ok(Some(config));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns a path of the configuration, the relative ones being resolved against the
+    /// directory of the configuration file.
+    fn resolve_path(&self, path: &str) -> String {
+        match self.path.parent() {
+            Some(dir) if Path::new(path).is_relative() => {
+                dir.join(path).to_string_lossy().into_owned()
+            }
+            _ => path.to_string(),
+        }
+    }
+
+    /// Returns a value of the option `name`, resolving the paths it contains.
+    fn resolve_value(&self, name: &str, value: &str) -> String {
+        match name {
+            "log" if value == "stderr" || value == "stdout" => value.to_string(),
+            // The path of a context is given after its label, as LABEL=PATH.
+            "context" => match value.find('=') {
+                Some(pos) => format!("{}={}", &value[..pos], self.resolve_path(&value[pos + 1..])),
+                None => value.to_string(),
+            },
+            name if PATH_OPTIONS.contains(&name) => self.resolve_path(value),
+            _ => value.to_string(),
+        }
+    }
+
+    /// Returns the options of the configuration, the ones of the profile take precedence over
+    /// the top-level ones. The relative paths are resolved against the directory of the
+    /// configuration file.
+    pub fn options(&self, profile: Option<&str>) -> Result<ConfigOptions, GrcovError> {
+        let mut options = self.options.clone();
+        if let Some(profile) = profile {
+            let profile_options = self.profiles.get(profile).ok_or_else(|| {
+                GrcovError::Input(
+                    self.path.display().to_string(),
+                    format!("Unknown profile '{}'", profile),
+                )
+            })?;
+            options.extend(profile_options.clone());
+        }
+        Ok(options
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    ConfigValue::Value(value) => {
+                        ConfigValue::Value(self.resolve_value(&name, &value))
+                    }
+                    ConfigValue::Values(values) => ConfigValue::Values(
+                        values
+                            .iter()
+                            .map(|value| self.resolve_value(&name, value))
+                            .collect(),
+                    ),
+                    flag => flag,
+                };
+                (name, value)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    const CONFIG: &str = r#"
+branch = true
+ignore = ["/*", "tests/*"]
+threads = 4
+output-type = "html"
+
+[profile.ci]
+branch = false
+output-type = ["lcov", "cobertura"]
+"#;
+
+    #[test]
+    fn test_config_profiles() {
+        let config = ProjectConfig::parse(Path::new(CONFIG_FILE_NAME), CONFIG)
+            .unwrap()
+            .unwrap();
+
+        let options = config.options(None).unwrap();
+        assert_eq!(options["branch"], ConfigValue::Flag(true));
+        assert_eq!(options["threads"], ConfigValue::Value("4".to_string()));
+        assert_eq!(
+            options["output-type"].to_args("output-type"),
+            ["--output-type=html"]
+        );
+        assert_eq!(
+            options["ignore"].to_args("ignore"),
+            ["--ignore=/*", "--ignore=tests/*"]
+        );
+
+        let options = config.options(Some("ci")).unwrap();
+        assert!(options["branch"].to_args("branch").is_empty());
+        assert_eq!(
+            options["output-type"],
+            ConfigValue::Values(vec!["lcov".to_string(), "cobertura".to_string()])
+        );
+        assert_eq!(options["threads"], ConfigValue::Value("4".to_string()));
+
+        assert!(config.options(Some("local")).is_err());
+    }
+
+    #[test]
+    fn test_config_cargo_metadata() {
+        let manifest = r#"
+[package]
+name = "foo"
+
+[package.metadata.grcov]
+source-dir = "."
+
+[package.metadata.grcov.profile.local]
+output-type = "html"
+"#;
+        let config = ProjectConfig::parse(Path::new("Cargo.toml"), manifest)
+            .unwrap()
+            .unwrap();
+        let options = config.options(Some("local")).unwrap();
+        assert_eq!(options["source-dir"], ConfigValue::Value(".".to_string()));
+        assert_eq!(
+            options["output-type"],
+            ConfigValue::Value("html".to_string())
+        );
+
+        let manifest = "[package]\nname = \"foo\"\n";
+        assert!(ProjectConfig::parse(Path::new("Cargo.toml"), manifest)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_config_resolve_path() {
+        let config = ProjectConfig {
+            path: Path::new("project").join(CONFIG_FILE_NAME),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.resolve_path("src"),
+            Path::new("project").join("src").to_str().unwrap()
+        );
+        let abs_path = env::temp_dir().join("src");
+        assert_eq!(
+            config.resolve_path(abs_path.to_str().unwrap()),
+            abs_path.to_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_config_resolve_options() {
+        let config = r#"
+paths = ["target", "build"]
+path-mapping = "map.json"
+patch = "changes.diff"
+log = "stdout"
+context = ["unit=unit.info", "invalid"]
+ignore = ["tests/*"]
+"#;
+        let config = ProjectConfig::parse(&Path::new("project").join(CONFIG_FILE_NAME), config)
+            .unwrap()
+            .unwrap();
+        let options = config.options(None).unwrap();
+        let resolved = |path: &str| {
+            Path::new("project")
+                .join(path)
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            options["paths"],
+            ConfigValue::Values(vec![resolved("target"), resolved("build")])
+        );
+        assert_eq!(
+            options["path-mapping"],
+            ConfigValue::Value(resolved("map.json"))
+        );
+        assert_eq!(
+            options["patch"],
+            ConfigValue::Value(resolved("changes.diff"))
+        );
+        assert_eq!(options["log"], ConfigValue::Value("stdout".to_string()));
+        assert_eq!(
+            options["context"],
+            ConfigValue::Values(vec![
+                format!("unit={}", resolved("unit.info")),
+                "invalid".to_string()
+            ])
+        );
+        // The globs aren't paths.
+        assert_eq!(
+            options["ignore"],
+            ConfigValue::Values(vec!["tests/*".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_config_invalid() {
+        assert!(ProjectConfig::parse(Path::new(CONFIG_FILE_NAME), "ignore = [[1]]").is_err());
+        assert!(ProjectConfig::parse(Path::new(CONFIG_FILE_NAME), "profile = 1").is_err());
+        assert!(ProjectConfig::parse(Path::new(CONFIG_FILE_NAME), "branch = ").is_err());
+    }
+
+    #[test]
+    fn test_config_find() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        assert!(ProjectConfig::find(&[tmp_dir.path()]).unwrap().is_none());
+
+        fs::write(
+            tmp_dir.path().join("Cargo.toml"),
+            "[package]\nname = \"foo\"\n",
+        )
+        .unwrap();
+        assert!(ProjectConfig::find(&[tmp_dir.path()]).unwrap().is_none());
+
+        fs::write(tmp_dir.path().join(CONFIG_FILE_NAME), "llvm = true\n").unwrap();
+        let config = ProjectConfig::find(&[tmp_dir.path()]).unwrap().unwrap();
+        assert_eq!(config.path, tmp_dir.path().join(CONFIG_FILE_NAME));
+        assert_eq!(
+            config.options(None).unwrap()["llvm"],
+            ConfigValue::Flag(true)
+        );
+    }
+}
@@ -1,4 +1,5 @@
-use serde_json::{json, map::Map};
+use rustc_hash::FxHashMap;
+use serde_json::{json, map::Map, Value};
 use std::collections::BTreeMap;
 
 pub use crate::defs::*;
@@ -36,13 +37,37 @@ impl CDStats {
     }
 }
 
+/// Adds the function and branch stats to the JSON of a file or a directory, next to the line
+/// stats which are kept as they are for the existing consumers.
+fn add_functions_and_branches(json: &mut Value, functions: &CDStats, branches: &CDStats) {
+    json["functionsTotal"] = json!(functions.total);
+    json["functionsCovered"] = json!(functions.covered);
+    json["functionsMissed"] = json!(functions.missed);
+    json["functionsPercent"] = json!(functions.percent);
+    json["branchesTotal"] = json!(branches.total);
+    json["branchesCovered"] = json!(branches.covered);
+    json["branchesMissed"] = json!(branches.missed);
+    json["branchesPercent"] = json!(branches.percent);
+}
+
 impl CDFileStats {
-    pub fn new(name: String, coverage: BTreeMap<u32, u64>) -> Self {
+    pub fn new(
+        name: String,
+        coverage: BTreeMap<u32, u64>,
+        functions: &FxHashMap<String, Function>,
+        branches: &BTreeMap<u32, Vec<u64>>,
+    ) -> Self {
         let (total, covered, lines) = Self::get_coverage(coverage);
+        let covered_functions = functions.values().filter(|f| f.executed).count();
+        let branches = branches.values().flatten();
+        let covered_branches = branches.clone().filter(|&&count| count > 0).count();
         Self {
             name,
             stats: CDStats::new(total, covered),
+            functions: CDStats::new(functions.len(), covered_functions),
+            branches: CDStats::new(branches.count(), covered_branches),
             coverage: lines,
+            contexts: BTreeMap::new(),
         }
     }
 
@@ -62,14 +87,19 @@ impl CDFileStats {
     }
 
     pub fn to_json(&self) -> serde_json::Value {
-        json!({
+        let mut json = json!({
             "name": self.name,
             "linesTotal": self.stats.total,
             "linesCovered": self.stats.covered,
             "linesMissed": self.stats.missed,
             "coveragePercent": self.stats.percent,
             "coverage": self.coverage,
-        })
+        });
+        add_functions_and_branches(&mut json, &self.functions, &self.branches);
+        if !self.contexts.is_empty() {
+            json["contexts"] = json!(self.contexts);
+        }
+        json
     }
 }
 
@@ -80,19 +110,27 @@ impl CDDirStats {
             files: Vec::new(),
             dirs: Vec::new(),
             stats: Default::default(),
+            functions: Default::default(),
+            branches: Default::default(),
         }
     }
 
     pub fn set_stats(&mut self) {
         for file in self.files.iter() {
             self.stats.add(&file.stats);
+            self.functions.add(&file.functions);
+            self.branches.add(&file.branches);
         }
         for dir in self.dirs.iter() {
             let mut dir = dir.borrow_mut();
             dir.set_stats();
             self.stats.add(&dir.stats);
+            self.functions.add(&dir.functions);
+            self.branches.add(&dir.branches);
         }
         self.stats.set_percent();
+        self.functions.set_percent();
+        self.branches.set_percent();
     }
 
     pub fn into_json(self) -> serde_json::Value {
@@ -104,13 +142,15 @@ impl CDDirStats {
             let dir = dir.take();
             children.insert(dir.name.clone(), dir.into_json());
         }
-        json!({
+        let mut json = json!({
             "name": self.name,
             "linesTotal": self.stats.total,
             "linesCovered": self.stats.covered,
             "linesMissed": self.stats.missed,
             "coveragePercent": self.stats.percent,
             "children": children,
-        })
+        });
+        add_functions_and_branches(&mut json, &self.functions, &self.branches);
+        json
     }
 }
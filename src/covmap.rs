@@ -0,0 +1,864 @@
+use flate2::read::ZlibDecoder;
+use log::warn;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::collections::{btree_map, hash_map, BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::Path;
+
+use crate::defs::{Contexts, CovResult, Function, RegionSpan};
+use crate::parser::ParserError;
+use crate::path_rewriting::normalize_path;
+use crate::profdata::{compute_llvm_hash, ByteReader, IndexedProfile};
+
+// Reader for the coverage mapping embedded by LLVM in instrumented binaries (built with
+// `-fprofile-instr-generate -fcoverage-mapping` or `-C instrument-coverage`), see
+// https://llvm.org/docs/CoverageMappingFormat.html
+
+// Coverage mapping format versions, starting from 0 for LLVM's Version1. We only support the
+// formats where the function records are stored in their own section (LLVM 11+).
+const MIN_SUPPORTED_VERSION: u32 = 3;
+const MAX_SUPPORTED_VERSION: u32 = 6;
+// From this version, the first filename is the compilation directory.
+const COMPILATION_DIR_VERSION: u32 = 5;
+
+const SHT_NOBITS: u32 = 8;
+
+#[derive(Default)]
+struct Sections<'a> {
+    covmap: Vec<&'a [u8]>,
+    covfun: Vec<&'a [u8]>,
+    names: Vec<&'a [u8]>,
+    big_endian: bool,
+}
+
+fn read_elf_sections(data: &[u8]) -> Result<Sections<'_>, String> {
+    if !data.starts_with(b"\x7fELF") || data.len() < 6 {
+        return Err("Not an ELF file".to_string());
+    }
+    let is_64 = match data[4] {
+        1 => false,
+        2 => true,
+        class => return Err(format!("Invalid ELF class {}", class)),
+    };
+    let big_endian = data[5] == 2;
+
+    let mut reader = ByteReader::new(data, big_endian);
+    let (shoff, shentsize, shnum, shstrndx) = if is_64 {
+        reader.seek(0x28)?;
+        let shoff = reader.read_u64()? as usize;
+        reader.seek(0x3a)?;
+        (
+            shoff,
+            reader.read_u16()?,
+            reader.read_u16()?,
+            reader.read_u16()?,
+        )
+    } else {
+        reader.seek(0x20)?;
+        let shoff = reader.read_u32()? as usize;
+        reader.seek(0x2e)?;
+        (
+            shoff,
+            reader.read_u16()?,
+            reader.read_u16()?,
+            reader.read_u16()?,
+        )
+    };
+
+    // (name offset, type, file offset, size)
+    let mut headers = Vec::with_capacity(shnum as usize);
+    for i in 0..shnum as usize {
+        reader.seek(shoff + i * shentsize as usize)?;
+        let name = reader.read_u32()?;
+        let sh_type = reader.read_u32()?;
+        let (offset, size) = if is_64 {
+            let _flags = reader.read_u64()?;
+            let _addr = reader.read_u64()?;
+            (reader.read_u64()? as usize, reader.read_u64()? as usize)
+        } else {
+            let _flags = reader.read_u32()?;
+            let _addr = reader.read_u32()?;
+            (reader.read_u32()? as usize, reader.read_u32()? as usize)
+        };
+        headers.push((name as usize, sh_type, offset, size));
+    }
+
+    let get_data = |offset: usize, size: usize| {
+        offset
+            .checked_add(size)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| "Invalid ELF section".to_string())
+    };
+
+    let mut sections = Sections {
+        big_endian,
+        ..Default::default()
+    };
+    let strtab = match headers.get(shstrndx as usize) {
+        Some(&(_, _, offset, size)) => get_data(offset, size)?,
+        None => return Ok(sections),
+    };
+    for &(name, sh_type, offset, size) in &headers {
+        if sh_type == SHT_NOBITS {
+            continue;
+        }
+        let name = match strtab.get(name..) {
+            Some(name) => &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())],
+            None => continue,
+        };
+        match name {
+            b"__llvm_covmap" => sections.covmap.push(get_data(offset, size)?),
+            b"__llvm_covfun" => sections.covfun.push(get_data(offset, size)?),
+            b"__llvm_prf_names" => sections.names.push(get_data(offset, size)?),
+            _ => {}
+        }
+    }
+
+    Ok(sections)
+}
+
+fn decompress(data: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let mut decompressed = Vec::with_capacity(size);
+    ZlibDecoder::new(data)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| format!("Failed to decompress data: {}", e))?;
+    if decompressed.len() != size {
+        return Err("Unexpected size of decompressed data".to_string());
+    }
+    Ok(decompressed)
+}
+
+fn read_names(data: &[u8], names: &mut FxHashMap<u64, String>) -> Result<(), String> {
+    let mut reader = ByteReader::new(data, false);
+    while !reader.is_empty() {
+        let uncompressed_size = reader.read_uleb128()? as usize;
+        let compressed_size = reader.read_uleb128()? as usize;
+        let decompressed;
+        let buf = if compressed_size != 0 {
+            decompressed = decompress(reader.read_bytes(compressed_size)?, uncompressed_size)?;
+            &decompressed[..]
+        } else {
+            reader.read_bytes(uncompressed_size)?
+        };
+        for name in buf.split(|&c| c == 1) {
+            names.insert(
+                compute_llvm_hash(name),
+                String::from_utf8_lossy(name).into_owned(),
+            );
+        }
+        // Skip the padding.
+        while !reader.is_empty() {
+            if reader.read_u8()? != 0 {
+                reader.seek(reader.pos() - 1)?;
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_filenames(data: &[u8], version: u32) -> Result<Vec<String>, String> {
+    let mut reader = ByteReader::new(data, false);
+    let num_filenames = reader.read_uleb128()? as usize;
+    let uncompressed_size = reader.read_uleb128()? as usize;
+    let compressed_size = reader.read_uleb128()? as usize;
+    let decompressed;
+    let mut reader = if compressed_size != 0 {
+        decompressed = decompress(reader.read_bytes(compressed_size)?, uncompressed_size)?;
+        ByteReader::new(&decompressed, false)
+    } else {
+        reader
+    };
+
+    let mut filenames = Vec::with_capacity(num_filenames);
+    for _ in 0..num_filenames {
+        let len = reader.read_uleb128()? as usize;
+        let filename = String::from_utf8_lossy(reader.read_bytes(len)?).into_owned();
+        if version >= COMPILATION_DIR_VERSION
+            && !filenames.is_empty()
+            && Path::new(&filename).is_relative()
+        {
+            // Relative paths are relative to the compilation directory.
+            let path = Path::new(&filenames[0]).join(&filename);
+            let path = normalize_path(&path).unwrap_or(path);
+            filenames.push(path.to_str().unwrap().to_string());
+        } else {
+            filenames.push(filename);
+        }
+    }
+    Ok(filenames)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Counter {
+    Zero,
+    Reference(usize),
+    Subtract(usize),
+    Add(usize),
+}
+
+impl Counter {
+    fn decode(value: u64) -> Counter {
+        let id = (value >> 2) as usize;
+        match value & 3 {
+            0 => Counter::Zero,
+            1 => Counter::Reference(id),
+            2 => Counter::Subtract(id),
+            _ => Counter::Add(id),
+        }
+    }
+
+    fn evaluate(
+        self,
+        expressions: &[(Counter, Counter)],
+        counts: &[u64],
+        depth: usize,
+    ) -> Result<u64, String> {
+        // A chain of expressions longer than their number means that there is a cycle.
+        if depth > expressions.len() {
+            return Err("Invalid counter expression".to_string());
+        }
+        let get_expression = |id: usize| {
+            expressions
+                .get(id)
+                .ok_or_else(|| "Invalid counter expression".to_string())
+        };
+        Ok(match self {
+            Counter::Zero => 0,
+            Counter::Reference(id) => counts.get(id).cloned().unwrap_or(0),
+            Counter::Subtract(id) => {
+                let (lhs, rhs) = get_expression(id)?;
+                lhs.evaluate(expressions, counts, depth + 1)?
+                    .saturating_sub(rhs.evaluate(expressions, counts, depth + 1)?)
+            }
+            Counter::Add(id) => {
+                let (lhs, rhs) = get_expression(id)?;
+                lhs.evaluate(expressions, counts, depth + 1)?
+                    .saturating_add(rhs.evaluate(expressions, counts, depth + 1)?)
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum RegionKind {
+    Code,
+    Expansion(usize),
+    Skipped,
+    Gap,
+    Branch,
+    Decision,
+}
+
+#[derive(Debug, Clone)]
+struct Region<C> {
+    file_id: usize,
+    kind: RegionKind,
+    count: C,
+    false_count: C,
+    line_start: u32,
+    col_start: u32,
+    line_end: u32,
+    col_end: u32,
+}
+
+struct FunctionMapping {
+    // Indexes of the files of the function in the filenames of its translation unit.
+    files: Vec<usize>,
+    expressions: Vec<(Counter, Counter)>,
+    regions: Vec<Region<Counter>>,
+}
+
+fn read_function_mapping(data: &[u8]) -> Result<FunctionMapping, String> {
+    let mut reader = ByteReader::new(data, false);
+
+    let num_files = reader.read_uleb128()? as usize;
+    let mut files = Vec::new();
+    for _ in 0..num_files {
+        files.push(reader.read_uleb128()? as usize);
+    }
+
+    let num_expressions = reader.read_uleb128()? as usize;
+    let mut expressions = Vec::new();
+    for _ in 0..num_expressions {
+        let lhs = Counter::decode(reader.read_uleb128()?);
+        let rhs = Counter::decode(reader.read_uleb128()?);
+        expressions.push((lhs, rhs));
+    }
+
+    let mut regions = Vec::new();
+    for file_id in 0..num_files {
+        let num_regions = reader.read_uleb128()?;
+        let mut line_start = 0u32;
+        for _ in 0..num_regions {
+            let encoded = reader.read_uleb128()?;
+            let mut kind = RegionKind::Code;
+            let mut count = Counter::decode(encoded);
+            let mut false_count = Counter::Zero;
+            if count == Counter::Zero {
+                if encoded & 4 != 0 {
+                    let expanded_file_id = (encoded >> 3) as usize;
+                    if expanded_file_id >= num_files {
+                        return Err("Invalid expansion region".to_string());
+                    }
+                    kind = RegionKind::Expansion(expanded_file_id);
+                } else {
+                    match encoded >> 3 {
+                        0 => {}
+                        2 => kind = RegionKind::Skipped,
+                        4 | 6 => {
+                            kind = RegionKind::Branch;
+                            count = Counter::decode(reader.read_uleb128()?);
+                            false_count = Counter::decode(reader.read_uleb128()?);
+                            if encoded >> 3 == 6 {
+                                // MC/DC condition ID and IDs of the next conditions.
+                                for _ in 0..3 {
+                                    reader.read_uleb128()?;
+                                }
+                            }
+                        }
+                        5 => {
+                            // MC/DC decision, bitmap index and number of conditions.
+                            kind = RegionKind::Decision;
+                            reader.read_uleb128()?;
+                            reader.read_uleb128()?;
+                        }
+                        k => return Err(format!("Unknown region kind {}", k)),
+                    }
+                }
+            }
+
+            line_start = line_start
+                .checked_add(reader.read_uleb128()? as u32)
+                .ok_or_else(|| "Invalid region".to_string())?;
+            let mut col_start = reader.read_uleb128()? as u32;
+            let num_lines = reader.read_uleb128()? as u32;
+            let mut col_end = reader.read_uleb128()? as u32;
+            if kind == RegionKind::Code && col_end & (1 << 31) != 0 {
+                kind = RegionKind::Gap;
+                col_end &= !(1 << 31);
+            }
+            if col_start == 0 && col_end == 0 {
+                // The region covers whole lines.
+                col_start = 1;
+                col_end = u32::MAX;
+            }
+
+            regions.push(Region {
+                file_id,
+                kind,
+                count,
+                false_count,
+                line_start,
+                col_start,
+                line_end: line_start.saturating_add(num_lines),
+                col_end,
+            });
+        }
+    }
+
+    // The counter of an expansion region is the counter of the first region of the expanded
+    // file. Expansions can be nested, so this needs several passes.
+    for _ in 1..num_files {
+        let mut expansions: Vec<Option<usize>> = vec![None; num_files];
+        for (i, region) in regions.iter().enumerate() {
+            if let RegionKind::Expansion(expanded_file_id) = region.kind {
+                expansions[expanded_file_id] = Some(i);
+            }
+        }
+        for i in 0..regions.len() {
+            if let Some(expansion) = expansions[regions[i].file_id].take() {
+                regions[expansion].count = regions[i].count;
+            }
+        }
+    }
+
+    Ok(FunctionMapping {
+        files,
+        expressions,
+        regions,
+    })
+}
+
+// Computes the line counts of a file from its regions, the same way llvm-cov does: the count of
+// a line is the maximum of the counts of the regions starting on the line and of the region
+// which was active at the end of the previous line.
+fn compute_line_counts(regions: &[Region<u64>]) -> BTreeMap<u32, u64> {
+    let mut regions: Vec<&Region<u64>> = regions
+        .iter()
+        .filter(|r| !matches!(r.kind, RegionKind::Branch | RegionKind::Decision))
+        .collect();
+    // Sort outer regions before the regions they contain.
+    regions.sort_by(|a, b| {
+        (a.line_start, a.col_start)
+            .cmp(&(b.line_start, b.col_start))
+            .then((b.line_end, b.col_end).cmp(&(a.line_end, a.col_end)))
+    });
+
+    let lines: BTreeSet<u32> = regions
+        .iter()
+        .flat_map(|r| r.line_start..=r.line_end)
+        .collect();
+
+    let mut line_counts = BTreeMap::new();
+    let mut active: Vec<&Region<u64>> = Vec::new();
+    let mut next = 0;
+    for line in lines {
+        // The regions starting before this line and still active at its beginning.
+        while next < regions.len() && regions[next].line_start < line {
+            active.push(regions[next]);
+            next += 1;
+        }
+        active.retain(|r| r.line_end >= line);
+        let wrapped = active.last();
+
+        let starting = regions[next..].iter().take_while(|r| r.line_start == line);
+        if starting
+            .clone()
+            .next()
+            .map_or(false, |r| r.kind == RegionKind::Skipped)
+        {
+            continue;
+        }
+        let entry_count = starting
+            .filter(|r| matches!(r.kind, RegionKind::Code | RegionKind::Expansion(_)))
+            .map(|r| r.count)
+            .max();
+        let wrapped_count = wrapped
+            .filter(|r| r.kind != RegionKind::Skipped)
+            .map(|r| r.count);
+        let count = match (entry_count, wrapped_count) {
+            (None, None) => continue,
+            (entry_count, wrapped_count) => entry_count.max(wrapped_count).unwrap(),
+        };
+        line_counts.insert(line, count);
+    }
+
+    line_counts
+}
+
+#[derive(Default)]
+struct FileCoverage {
+    regions: Vec<Region<u64>>,
+    // Indexes of the regions by their kind and location.
+    region_indexes: FxHashMap<(RegionKind, u32, u32, u32, u32), usize>,
+    branches: BTreeMap<(u32, u32), Vec<u64>>,
+    functions: FxHashMap<String, Function>,
+}
+
+impl FileCoverage {
+    fn add_region(&mut self, region: Region<u64>) {
+        if region.kind == RegionKind::Branch {
+            let counts = vec![region.count, region.false_count];
+            // Instantiations of the same function have branches in the same place.
+            match self.branches.entry((region.line_start, region.col_start)) {
+                btree_map::Entry::Occupied(mut entry) => {
+                    for (c, count) in entry.get_mut().iter_mut().zip(counts) {
+                        *c = c.saturating_add(count);
+                    }
+                }
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(counts);
+                }
+            }
+            return;
+        }
+
+        // Instantiations of the same function have the same regions, we sum their counts.
+        let key = (
+            region.kind,
+            region.line_start,
+            region.col_start,
+            region.line_end,
+            region.col_end,
+        );
+        match self.region_indexes.entry(key) {
+            hash_map::Entry::Occupied(entry) => {
+                let existing = &mut self.regions[*entry.get()];
+                existing.count = existing.count.saturating_add(region.count);
+            }
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(self.regions.len());
+                self.regions.push(region);
+            }
+        }
+    }
+
+    fn into_result(self, branch_enabled: bool) -> CovResult {
+        let mut branches: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
+        if branch_enabled {
+            for ((line, _), counts) in self.branches {
+                branches.entry(line).or_default().extend(counts);
+            }
+        }
+        let mut regions: BTreeMap<RegionSpan, u64> = BTreeMap::new();
+        for region in &self.regions {
+            if let RegionKind::Code | RegionKind::Expansion(_) = region.kind {
+                let span = RegionSpan {
+                    start_line: region.line_start,
+                    start_col: region.col_start,
+                    end_line: region.line_end,
+                    end_col: region.col_end,
+                };
+                let count = regions.entry(span).or_insert(0);
+                *count = (*count).max(region.count);
+            }
+        }
+        CovResult {
+            lines: compute_line_counts(&self.regions),
+            branches,
+            functions: self.functions,
+            regions,
+            contexts: Contexts::default(),
+        }
+    }
+}
+
+fn add_function(
+    files: &mut BTreeMap<String, FileCoverage>,
+    name: String,
+    regions: Vec<(&String, Region<u64>)>,
+) {
+    // The first region is at the start of the function and has its entry count.
+    if let Some((filename, region)) = regions.first() {
+        let count = region.count;
+        let end = regions
+            .iter()
+            .filter(|(f, _)| f == filename)
+            .map(|(_, r)| r.line_end)
+            .max();
+        files
+            .entry((*filename).clone())
+            .or_default()
+            .functions
+            .entry(name)
+            .and_modify(|f| {
+                f.executed |= count > 0;
+                f.count = f.count.saturating_add(count);
+            })
+            .or_insert(Function {
+                start: region.line_start,
+                end,
+                executed: count > 0,
+                count,
+            });
+    }
+
+    for (filename, region) in regions {
+        files
+            .entry(filename.clone())
+            .or_default()
+            .add_region(region);
+    }
+}
+
+fn into_results(
+    files: BTreeMap<String, FileCoverage>,
+    branch_enabled: bool,
+) -> Vec<(String, CovResult)> {
+    files
+        .into_iter()
+        .map(|(filename, file)| (filename, file.into_result(branch_enabled)))
+        .collect()
+}
+
+/// Computes the coverage of the source files of a binary, using the coverage mapping it embeds
+/// and the counters from an indexed profile.
+pub fn get_coverage(
+    binary: &[u8],
+    profile: &IndexedProfile,
+    branch_enabled: bool,
+) -> Result<Vec<(String, CovResult)>, String> {
+    let sections = read_elf_sections(binary)?;
+
+    let mut names = FxHashMap::default();
+    for data in &sections.names {
+        read_names(data, &mut names)?;
+    }
+
+    // The filenames of every translation unit, by their hash.
+    let mut translation_units: FxHashMap<u64, Vec<String>> = FxHashMap::default();
+    for data in &sections.covmap {
+        let mut reader = ByteReader::new(data, sections.big_endian);
+        while !reader.is_empty() {
+            let _num_records = reader.read_u32()?;
+            let filenames_size = reader.read_u32()? as usize;
+            let coverage_size = reader.read_u32()? as usize;
+            let version = reader.read_u32()?;
+            if !(MIN_SUPPORTED_VERSION..=MAX_SUPPORTED_VERSION).contains(&version) {
+                return Err(format!(
+                    "Unsupported coverage mapping version {}",
+                    version + 1
+                ));
+            }
+            let filenames = reader.read_bytes(filenames_size)?;
+            reader.read_bytes(coverage_size)?;
+            reader.align(8)?;
+            translation_units.insert(
+                compute_llvm_hash(filenames),
+                read_filenames(filenames, version)?,
+            );
+        }
+    }
+
+    let mut files: BTreeMap<String, FileCoverage> = BTreeMap::new();
+    let mut seen_functions = BTreeSet::new();
+    let mut mismatched_functions = 0;
+    for data in &sections.covfun {
+        let mut reader = ByteReader::new(data, sections.big_endian);
+        while !reader.is_empty() {
+            let name_hash = reader.read_u64()?;
+            let data_size = reader.read_u32()? as usize;
+            let hash = reader.read_u64()?;
+            let filenames_hash = reader.read_u64()?;
+            let mapping = reader.read_bytes(data_size)?;
+            reader.align(8)?;
+
+            // The same function can be emitted in several translation units (e.g. inline
+            // functions), in which case we only consider the first one.
+            if !seen_functions.insert((filenames_hash, name_hash)) {
+                continue;
+            }
+            let filenames = match translation_units.get(&filenames_hash) {
+                Some(filenames) => filenames,
+                None => return Err("Missing filenames for a function record".to_string()),
+            };
+
+            let counts = match profile.get(name_hash, hash) {
+                Ok(Some(record)) => &record.counts[..],
+                // Functions which are not in the profile were never executed.
+                Ok(None) => &[],
+                Err(_) => {
+                    mismatched_functions += 1;
+                    continue;
+                }
+            };
+
+            let mapping = read_function_mapping(mapping)?;
+            let mut regions = Vec::with_capacity(mapping.regions.len());
+            for region in mapping.regions {
+                let filename = mapping
+                    .files
+                    .get(region.file_id)
+                    .and_then(|&i| filenames.get(i))
+                    .ok_or_else(|| "Invalid file ID".to_string())?;
+                regions.push((
+                    filename,
+                    Region {
+                        file_id: region.file_id,
+                        kind: region.kind,
+                        count: region.count.evaluate(&mapping.expressions, counts, 0)?,
+                        false_count: region.false_count.evaluate(
+                            &mapping.expressions,
+                            counts,
+                            0,
+                        )?,
+                        line_start: region.line_start,
+                        col_start: region.col_start,
+                        line_end: region.line_end,
+                        col_end: region.col_end,
+                    },
+                ));
+            }
+
+            let name = names
+                .get(&name_hash)
+                .map(String::as_str)
+                .or_else(|| profile.get_name(name_hash))
+                .map_or_else(
+                    || format!("{:#018x}", name_hash),
+                    |name| {
+                        // Local functions are prefixed by the name of their file.
+                        name.rsplit(';').next().unwrap().to_string()
+                    },
+                );
+            add_function(&mut files, name, regions);
+        }
+    }
+
+    if mismatched_functions > 0 {
+        warn!(
+            "{} functions have mismatched data and were ignored",
+            mismatched_functions
+        );
+    }
+
+    Ok(into_results(files, branch_enabled))
+}
+
+#[derive(Deserialize)]
+struct LlvmCovExport {
+    #[serde(rename = "type")]
+    export_type: String,
+    data: Vec<LlvmCovData>,
+}
+
+#[derive(Deserialize)]
+struct LlvmCovData {
+    functions: Vec<LlvmCovFunction>,
+}
+
+#[derive(Deserialize)]
+struct LlvmCovFunction {
+    name: String,
+    // [line start, column start, line end, column end, count, file ID, expanded file ID, kind]
+    regions: Vec<Vec<u64>>,
+    // [line start, column start, line end, column end, true count, false count, file ID,
+    // expanded file ID, kind]
+    #[serde(default)]
+    branches: Vec<Vec<u64>>,
+    filenames: Vec<String>,
+}
+
+/// Parses the JSON export of llvm-cov (`llvm-cov export --format=text`).
+pub fn parse_llvm_cov_json(
+    buffer: &[u8],
+    branch_enabled: bool,
+) -> Result<Vec<(String, CovResult)>, ParserError> {
+    let export: LlvmCovExport =
+        serde_json::from_slice(buffer).map_err(|e| ParserError::InvalidData(e.to_string()))?;
+    if export.export_type != "llvm.coverage.json.export" {
+        return Err(ParserError::InvalidData(format!(
+            "Unexpected export type {}",
+            export.export_type
+        )));
+    }
+
+    let mut files: BTreeMap<String, FileCoverage> = BTreeMap::new();
+    for function in export.data.into_iter().flat_map(|data| data.functions) {
+        let mut regions = Vec::with_capacity(function.regions.len() + function.branches.len());
+        for values in function.regions.iter().chain(function.branches.iter()) {
+            let is_branch = values.len() == 9;
+            if values.len() != 8 && !is_branch {
+                return Err(ParserError::InvalidRecord(format!("{:?}", values)));
+            }
+            let (count, false_count, file_id) = if is_branch {
+                (values[4], values[5], values[6] as usize)
+            } else {
+                (values[4], 0, values[5] as usize)
+            };
+            let kind = match values[values.len() - 1] {
+                0 => RegionKind::Code,
+                1 => RegionKind::Expansion(values[values.len() - 2] as usize),
+                2 => RegionKind::Skipped,
+                3 => RegionKind::Gap,
+                4 | 6 => RegionKind::Branch,
+                _ => RegionKind::Decision,
+            };
+            let filename = function
+                .filenames
+                .get(file_id)
+                .ok_or_else(|| ParserError::InvalidRecord(format!("{:?}", values)))?;
+            regions.push((
+                filename,
+                Region {
+                    file_id,
+                    kind,
+                    count,
+                    false_count,
+                    line_start: values[0] as u32,
+                    col_start: values[1] as u32,
+                    line_end: values[2] as u32,
+                    col_end: values[3] as u32,
+                },
+            ));
+        }
+        add_function(&mut files, function.name, regions);
+    }
+
+    Ok(into_results(files, branch_enabled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_lcov;
+    use std::fs;
+
+    #[test]
+    fn test_counter_evaluate() {
+        let expressions = vec![
+            (Counter::Reference(0), Counter::Reference(1)),
+            (Counter::Add(0), Counter::Reference(2)),
+        ];
+        let counts = [5, 2, 7];
+        assert_eq!(Counter::decode(0), Counter::Zero);
+        assert_eq!(Counter::decode(5), Counter::Reference(1));
+        assert_eq!(Counter::decode(6), Counter::Subtract(1));
+        assert_eq!(Counter::decode(3), Counter::Add(0));
+        assert_eq!(
+            Counter::Subtract(0).evaluate(&expressions, &counts, 0),
+            Ok(3)
+        );
+        assert_eq!(Counter::Add(0).evaluate(&expressions, &counts, 0), Ok(7));
+        assert_eq!(
+            Counter::Subtract(1).evaluate(&expressions, &counts, 0),
+            Ok(0)
+        );
+        assert!(Counter::Add(2).evaluate(&expressions, &counts, 0).is_err());
+
+        let cyclic = vec![(Counter::Add(0), Counter::Zero)];
+        assert!(Counter::Add(0).evaluate(&cyclic, &counts, 0).is_err());
+    }
+
+    #[test]
+    fn test_get_coverage() {
+        let binary = fs::read("test/llvm-cov/sample").unwrap();
+        let profile =
+            IndexedProfile::parse(&fs::read("test/llvm-cov/sample.profdata").unwrap()).unwrap();
+        let results = get_coverage(&binary, &profile, true).unwrap();
+
+        // Output of `llvm-cov export --format lcov` for the same binary and profile.
+        let expected = parse_lcov(fs::read("test/llvm-cov/sample.info").unwrap(), true).unwrap();
+        assert_eq!(results.len(), expected.len());
+        for ((filename, result), (expected_filename, expected)) in results.iter().zip(expected) {
+            assert_eq!(filename, &expected_filename);
+            assert_eq!(result.lines, expected.lines);
+            assert_eq!(result.branches, expected.branches);
+            // The lcov format doesn't contain the end of the functions.
+            let mut functions = result.functions.clone();
+            for function in functions.values_mut() {
+                function.end = None;
+            }
+            assert_eq!(functions, expected.functions);
+            assert!(!result.regions.is_empty());
+        }
+
+        let main = &results[0].1.functions["_RNvCs32gj3y4fnmh_4main4main"];
+        assert_eq!((main.start, main.end), (9, Some(25)));
+    }
+
+    #[test]
+    fn test_parse_llvm_cov_json() {
+        let results =
+            parse_llvm_cov_json(&fs::read("test/llvm-cov/sample.json").unwrap(), true).unwrap();
+        assert_eq!(results.len(), 1);
+        let (ref filename, ref result) = results[0];
+        assert_eq!(filename, "main.rs");
+
+        // The body of the unused function.
+        let span = RegionSpan {
+            start_line: 2,
+            start_col: 5,
+            end_line: 2,
+            end_col: 10,
+        };
+        assert_eq!(result.regions.get(&span), Some(&0));
+
+        // The JSON export contains the same information as the coverage mapping.
+        let binary = fs::read("test/llvm-cov/sample").unwrap();
+        let profile =
+            IndexedProfile::parse(&fs::read("test/llvm-cov/sample.profdata").unwrap()).unwrap();
+        assert_eq!(results, get_coverage(&binary, &profile, true).unwrap());
+
+        assert!(parse_llvm_cov_json(b"{}", true).is_err());
+        assert!(parse_llvm_cov_json(br#"{"type": "other", "data": []}"#, true).is_err());
+    }
+
+    #[test]
+    fn test_get_coverage_not_elf() {
+        let profile = IndexedProfile::default();
+        assert!(get_coverage(b"#!/bin/sh", &profile, true).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use crossbeam::channel::{Receiver, Sender};
 use rustc_hash::FxHashMap;
 use serde::ser::{Serialize, Serializer};
 use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 use std::rc::Rc;
@@ -11,14 +11,69 @@ use std::sync::Mutex;
 #[derive(Debug, Clone, PartialEq)]
 pub struct Function {
     pub start: u32,
+    // The last line of the function, when the format provides it.
+    pub end: Option<u32>,
     pub executed: bool,
+    // The number of times the function was called, formats without call counts use 0 or 1.
+    pub count: u64,
+}
+
+/// The location of a region of code, columns are 1-based and the end column is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RegionSpan {
+    pub start_line: u32,
+    pub start_col: u32,
+    pub end_line: u32,
+    pub end_col: u32,
+}
+
+/// The execution counts in each test context (e.g. "unit" or "integration"), by label.
+pub type ContextCounts = BTreeMap<String, u64>;
+
+/// The test contexts which executed the lines and the functions of a file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Contexts {
+    pub lines: BTreeMap<u32, ContextCounts>,
+    pub functions: BTreeMap<String, ContextCounts>,
+}
+
+impl Contexts {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.functions.is_empty()
+    }
+
+    /// Returns the labels of all the contexts.
+    pub fn labels(&self) -> BTreeSet<&str> {
+        self.lines
+            .values()
+            .chain(self.functions.values())
+            .flat_map(|counts| counts.keys().map(String::as_str))
+            .collect()
+    }
+
+    /// Returns the lines covered in each context.
+    pub fn covered_lines(&self) -> BTreeMap<String, Vec<u32>> {
+        let mut covered: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for (&line, counts) in &self.lines {
+            for (label, &count) in counts {
+                if count > 0 {
+                    covered.entry(label.clone()).or_default().push(line);
+                }
+            }
+        }
+        covered
+    }
 }
 
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct CovResult {
     pub lines: BTreeMap<u32, u64>,
-    pub branches: BTreeMap<u32, Vec<bool>>,
+    pub branches: BTreeMap<u32, Vec<u64>>,
     pub functions: FunctionMap,
+    // The execution counts of the code regions, only available with LLVM source-based coverage.
+    pub regions: BTreeMap<RegionSpan, u64>,
+    // The execution counts in each test context, only available when the inputs are labelled.
+    pub contexts: Contexts,
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -27,6 +82,7 @@ pub enum ItemFormat {
     Profraw,
     Info,
     JacocoXml,
+    LlvmJson,
 }
 
 #[derive(Debug)]
@@ -72,7 +128,11 @@ pub struct CDStats {
 pub struct CDFileStats {
     pub name: String,
     pub stats: CDStats,
+    pub functions: CDStats,
+    pub branches: CDStats,
     pub coverage: Vec<i64>,
+    // The lines covered in each test context.
+    pub contexts: BTreeMap<String, Vec<u32>>,
 }
 
 #[derive(Debug, Default)]
@@ -81,6 +141,8 @@ pub struct CDDirStats {
     pub files: Vec<CDFileStats>,
     pub dirs: Vec<Rc<RefCell<CDDirStats>>>,
     pub stats: CDStats,
+    pub functions: CDStats,
+    pub branches: CDStats,
 }
 
 #[derive(Debug)]
@@ -105,8 +167,10 @@ pub struct HtmlFileStats {
     pub stats: HtmlStats,
 }
 
-#[derive(Clone, Debug, serde::Serialize)]
+#[derive(Clone, Debug, Default, serde::Serialize)]
 pub struct HtmlDirStats {
+    // The subdirectories, with the stats of all the files they contain.
+    pub dirs: BTreeMap<String, HtmlStats>,
     pub files: BTreeMap<String, HtmlFileStats>,
     pub stats: HtmlStats,
 }
@@ -148,5 +212,5 @@ impl<'a> Serialize for StringOrRef<'a> {
 
 pub struct JacocoReport {
     pub lines: BTreeMap<u32, u64>,
-    pub branches: BTreeMap<u32, Vec<bool>>,
+    pub branches: BTreeMap<u32, Vec<u64>>,
 }
@@ -0,0 +1,92 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::parser::ParserError;
+
+/// The errors which can happen while collecting coverage data or writing reports.
+#[derive(Debug)]
+pub enum GrcovError {
+    Io(io::Error),
+    /// An input path which can't be read, e.g. a corrupt zip archive or a file of an unknown type.
+    Input(String, String),
+    NoInputFiles,
+    /// A coverage file which couldn't be parsed.
+    Parse(String, ParserError),
+    /// An external tool (gcov, llvm-profdata, llvm-cov) which failed on an input.
+    Tool(String, String),
+    /// The inputs which couldn't be processed in strict mode.
+    Failures(Vec<GrcovError>),
+    Output(String),
+    Other(String),
+}
+
+impl From<io::Error> for GrcovError {
+    fn from(err: io::Error) -> GrcovError {
+        GrcovError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for GrcovError {
+    fn from(err: serde_json::Error) -> GrcovError {
+        GrcovError::Output(err.to_string())
+    }
+}
+
+impl From<quick_xml::Error> for GrcovError {
+    fn from(err: quick_xml::Error) -> GrcovError {
+        match err {
+            quick_xml::Error::Io(e) => GrcovError::Io(e),
+            _ => GrcovError::Output(format!("{:?}", err)),
+        }
+    }
+}
+
+impl fmt::Display for GrcovError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            GrcovError::Io(ref err) => write!(f, "IO error: {}", err),
+            GrcovError::Input(ref path, ref reason) => {
+                write!(f, "Cannot load '{}': {}", path, reason)
+            }
+            GrcovError::NoInputFiles => write!(f, "No input files found"),
+            GrcovError::Parse(ref name, ref err) => {
+                write!(f, "Error parsing file {}: {}", name, err)
+            }
+            GrcovError::Tool(ref name, ref err) => write!(f, "Error processing {}: {}", name, err),
+            GrcovError::Failures(ref errors) => {
+                write!(f, "{} input(s) could not be processed:", errors.len())?;
+                for err in errors {
+                    write!(f, "\n  - {}", err)?;
+                }
+                Ok(())
+            }
+            GrcovError::Output(ref s) => write!(f, "Error writing the output: {}", s),
+            GrcovError::Other(ref s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Error for GrcovError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failures_display() {
+        let err = GrcovError::Failures(vec![
+            GrcovError::Parse(
+                "a.info".to_string(),
+                ParserError::InvalidRecord("FNDA at line 3".to_string()),
+            ),
+            GrcovError::Tool("b.gcno".to_string(), "gcov failed".to_string()),
+        ]);
+        assert_eq!(
+            err.to_string(),
+            "2 input(s) could not be processed:\n  \
+             - Error parsing file a.info: Invalid record: 'FNDA at line 3'\n  \
+             - Error processing b.gcno: gcov failed"
+        );
+    }
+}
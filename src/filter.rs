@@ -24,6 +24,7 @@ pub fn is_covered(result: &CovResult) -> bool {
 mod tests {
     use super::*;
     use rustc_hash::FxHashMap;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_covered() {
@@ -32,20 +33,26 @@ mod tests {
             "f1".to_string(),
             Function {
                 start: 1,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
         functions.insert(
             "f2".to_string(),
             Function {
                 start: 2,
+                end: None,
                 executed: false,
+                count: 0,
             },
         );
         let result = CovResult {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions,
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
         };
 
         assert!(is_covered(&result));
@@ -57,6 +64,8 @@ mod tests {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions: FxHashMap::default(),
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
         };
 
         assert!(is_covered(&result));
@@ -69,20 +78,26 @@ mod tests {
             "f1".to_string(),
             Function {
                 start: 1,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
         functions.insert(
             "f2".to_string(),
             Function {
                 start: 2,
+                end: None,
                 executed: false,
+                count: 0,
             },
         );
         let result = CovResult {
             lines: [(1, 0), (2, 0), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions: FxHashMap::default(),
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
         };
 
         assert!(!is_covered(&result));
@@ -95,20 +110,26 @@ mod tests {
             "top-level".to_string(),
             Function {
                 start: 1,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
         functions.insert(
             "f".to_string(),
             Function {
                 start: 2,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
         let result = CovResult {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions,
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
         };
 
         assert!(is_covered(&result));
@@ -121,13 +142,17 @@ mod tests {
             "top-level".to_string(),
             Function {
                 start: 1,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
         let result = CovResult {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions,
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
         };
 
         assert!(is_covered(&result));
@@ -140,20 +165,26 @@ mod tests {
             "top-level".to_string(),
             Function {
                 start: 1,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
         functions.insert(
             "f".to_string(),
             Function {
                 start: 7,
+                end: None,
                 executed: false,
+                count: 0,
             },
         );
         let result = CovResult {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
             branches: [].iter().cloned().collect(),
             functions,
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
         };
 
         assert!(!is_covered(&result));
@@ -0,0 +1,759 @@
+use std::path::Path;
+
+/// The kinds of tokens which are highlighted, their names are the suffixes of the CSS classes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword,
+    Type,
+    String,
+    Number,
+    Comment,
+    Macro,
+}
+
+impl TokenKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "keyword",
+            TokenKind::Type => "type",
+            TokenKind::String => "string",
+            TokenKind::Number => "number",
+            TokenKind::Comment => "comment",
+            TokenKind::Macro => "macro",
+        }
+    }
+}
+
+struct Language {
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    // The delimiters of the strings, the ones which are multiline can span several lines.
+    strings: &'static [&'static str],
+    multiline_strings: &'static [&'static str],
+    // Whether the identifiers starting with an uppercase letter are types.
+    capitalized_types: bool,
+    // Whether the lines starting with '#' are preprocessor directives.
+    preprocessor: bool,
+    // Whether '!' after an identifier and '#[...]' are macros and attributes, as in Rust.
+    rust_macros: bool,
+}
+
+static RUST: Language = Language {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+        "true", "type", "unsafe", "use", "where", "while", "yield",
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32",
+        "i64", "i128", "isize", "f32", "f64",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    strings: &[],
+    multiline_strings: &["\""],
+    capitalized_types: true,
+    preprocessor: false,
+    rust_macros: true,
+};
+
+static C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "const", "continue", "default", "do", "else", "enum", "extern", "for",
+    "goto", "if", "inline", "register", "restrict", "return", "sizeof", "static", "struct",
+    "switch", "typedef", "union", "volatile", "while", "NULL",
+];
+
+static C_TYPES: &[&str] = &[
+    "char",
+    "double",
+    "float",
+    "int",
+    "long",
+    "short",
+    "signed",
+    "unsigned",
+    "void",
+    "bool",
+    "size_t",
+    "ssize_t",
+    "int8_t",
+    "int16_t",
+    "int32_t",
+    "int64_t",
+    "uint8_t",
+    "uint16_t",
+    "uint32_t",
+    "uint64_t",
+    "intptr_t",
+    "uintptr_t",
+];
+
+static C: Language = Language {
+    keywords: C_KEYWORDS,
+    types: C_TYPES,
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    strings: &["\"", "'"],
+    multiline_strings: &[],
+    capitalized_types: false,
+    preprocessor: true,
+    rust_macros: false,
+};
+
+static CPP: Language = Language {
+    keywords: &[
+        "alignas",
+        "alignof",
+        "auto",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "constexpr",
+        "const_cast",
+        "continue",
+        "decltype",
+        "default",
+        "delete",
+        "do",
+        "dynamic_cast",
+        "else",
+        "enum",
+        "explicit",
+        "export",
+        "extern",
+        "false",
+        "final",
+        "for",
+        "friend",
+        "goto",
+        "if",
+        "inline",
+        "mutable",
+        "namespace",
+        "new",
+        "noexcept",
+        "nullptr",
+        "operator",
+        "override",
+        "private",
+        "protected",
+        "public",
+        "register",
+        "reinterpret_cast",
+        "return",
+        "sizeof",
+        "static",
+        "static_assert",
+        "static_cast",
+        "struct",
+        "switch",
+        "template",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "typedef",
+        "typeid",
+        "typename",
+        "union",
+        "using",
+        "virtual",
+        "volatile",
+        "while",
+        "NULL",
+    ],
+    types: C_TYPES,
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    strings: &["\"", "'"],
+    multiline_strings: &[],
+    capitalized_types: false,
+    preprocessor: true,
+    rust_macros: false,
+};
+
+static JAVA: Language = Language {
+    keywords: &[
+        "abstract",
+        "assert",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "default",
+        "do",
+        "else",
+        "enum",
+        "extends",
+        "false",
+        "final",
+        "finally",
+        "for",
+        "goto",
+        "if",
+        "implements",
+        "import",
+        "instanceof",
+        "interface",
+        "native",
+        "new",
+        "null",
+        "package",
+        "private",
+        "protected",
+        "public",
+        "return",
+        "static",
+        "strictfp",
+        "super",
+        "switch",
+        "synchronized",
+        "this",
+        "throw",
+        "throws",
+        "transient",
+        "true",
+        "try",
+        "var",
+        "volatile",
+        "while",
+    ],
+    types: &[
+        "boolean", "byte", "char", "double", "float", "int", "long", "short", "void",
+    ],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    strings: &["\"", "'"],
+    multiline_strings: &["\"\"\""],
+    capitalized_types: true,
+    preprocessor: false,
+    rust_macros: false,
+};
+
+static JAVASCRIPT: Language = Language {
+    keywords: &[
+        "async",
+        "await",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "debugger",
+        "default",
+        "delete",
+        "do",
+        "else",
+        "export",
+        "extends",
+        "false",
+        "finally",
+        "for",
+        "function",
+        "if",
+        "import",
+        "in",
+        "instanceof",
+        "let",
+        "new",
+        "null",
+        "of",
+        "return",
+        "static",
+        "super",
+        "switch",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "typeof",
+        "undefined",
+        "var",
+        "void",
+        "while",
+        "with",
+        "yield",
+    ],
+    types: &[],
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    strings: &["\"", "'"],
+    multiline_strings: &["`"],
+    capitalized_types: false,
+    preprocessor: false,
+    rust_macros: false,
+};
+
+static PYTHON: Language = Language {
+    keywords: &[
+        "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
+        "continue", "def", "del", "elif", "else", "except", "finally", "for", "from", "global",
+        "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return",
+        "try", "while", "with", "yield",
+    ],
+    types: &[
+        "bool", "bytes", "dict", "float", "int", "list", "object", "set", "str", "tuple",
+    ],
+    line_comments: &["#"],
+    block_comment: None,
+    strings: &["\"", "'"],
+    multiline_strings: &["\"\"\"", "'''"],
+    capitalized_types: false,
+    preprocessor: false,
+    rust_macros: false,
+};
+
+fn get_language(path: &Path) -> Option<&'static Language> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match extension.as_str() {
+        "rs" => &RUST,
+        "c" | "h" => &C,
+        "cc" | "cpp" | "cxx" | "c++" | "hh" | "hpp" | "hxx" | "h++" | "ipp" | "tcc" | "inl" => &CPP,
+        "java" => &JAVA,
+        "js" | "jsm" | "mjs" | "cjs" | "jsx" => &JAVASCRIPT,
+        "py" | "pyw" => &PYTHON,
+        _ => return None,
+    })
+}
+
+// The constructs which can span several lines, with the delimiter which ends them.
+enum State {
+    Normal,
+    Comment(&'static str),
+    String(String, bool),
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Highlights the lines of a source file, one after the other.
+pub struct Highlighter {
+    language: &'static Language,
+    state: State,
+}
+
+impl Highlighter {
+    /// Returns a highlighter for the language of a file, from its extension.
+    pub fn for_path(path: &Path) -> Option<Highlighter> {
+        get_language(path).map(|language| Highlighter {
+            language,
+            state: State::Normal,
+        })
+    }
+
+    /// Splits the next line of the file into tokens, with their kind when they are highlighted.
+    pub fn highlight_line(&mut self, line: &str) -> Vec<(String, Option<TokenKind>)> {
+        let mut tokens: Vec<(usize, usize, TokenKind)> = Vec::new();
+        let mut i = 0;
+
+        // The end of the string or comment started on a previous line.
+        match std::mem::replace(&mut self.state, State::Normal) {
+            State::Normal => {}
+            State::Comment(end) => {
+                i = self.end_comment(line, 0, end);
+                tokens.push((0, i, TokenKind::Comment));
+            }
+            State::String(end, escapes) => {
+                i = self.end_string(line, 0, end, escapes);
+                tokens.push((0, i, TokenKind::String));
+            }
+        }
+
+        let language = self.language;
+        if i == 0 && language.preprocessor && line.trim_start().starts_with('#') {
+            // The comments at the end of the directives are highlighted as well.
+            let end = ["//", "/*"]
+                .iter()
+                .filter_map(|start| line.find(start))
+                .min()
+                .unwrap_or(line.len());
+            tokens.push((0, end, TokenKind::Macro));
+            i = end;
+        }
+
+        while i < line.len() {
+            let rest = &line[i..];
+            let c = rest.chars().next().unwrap();
+            let prev_is_ident = line[..i].chars().next_back().map(is_ident_char) == Some(true);
+
+            if language
+                .line_comments
+                .iter()
+                .any(|start| rest.starts_with(start))
+            {
+                tokens.push((i, line.len(), TokenKind::Comment));
+                break;
+            }
+            if let Some((start, end)) = language.block_comment {
+                if rest.starts_with(start) {
+                    let comment_end = self.end_comment(line, i + start.len(), end);
+                    tokens.push((i, comment_end, TokenKind::Comment));
+                    i = comment_end;
+                    continue;
+                }
+            }
+            if let Some((start_len, end, escapes)) = self.get_string_start(rest, prev_is_ident) {
+                let string_end = self.end_string(line, i + start_len, end, escapes);
+                tokens.push((i, string_end, TokenKind::String));
+                i = string_end;
+                continue;
+            }
+            if language.rust_macros && c == '\'' {
+                // A character literal, or a lifetime which isn't highlighted.
+                if let Some(len) = get_char_literal_len(rest) {
+                    tokens.push((i, i + len, TokenKind::String));
+                    i += len;
+                    continue;
+                }
+            }
+            if language.rust_macros && (rest.starts_with("#[") || rest.starts_with("#![")) {
+                let end = rest.find(']').map_or(line.len(), |end| i + end + 1);
+                tokens.push((i, end, TokenKind::Macro));
+                i = end;
+                continue;
+            }
+            if c.is_ascii_digit() && !prev_is_ident {
+                let len = rest
+                    .find(|c: char| !is_ident_char(c) && c != '.')
+                    .unwrap_or(rest.len());
+                // A range like 0..10 isn't a single number.
+                let len = rest[..len].find("..").unwrap_or(len);
+                tokens.push((i, i + len, TokenKind::Number));
+                i += len;
+                continue;
+            }
+            if is_ident_char(c) && !prev_is_ident {
+                let len = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+                let ident = &rest[..len];
+                let kind = if language.keywords.contains(&ident) {
+                    Some(TokenKind::Keyword)
+                } else if language.types.contains(&ident)
+                    || (language.capitalized_types
+                        && ident.starts_with(|c: char| c.is_ascii_uppercase()))
+                {
+                    Some(TokenKind::Type)
+                } else if language.rust_macros && rest[len..].starts_with('!') {
+                    tokens.push((i, i + len + 1, TokenKind::Macro));
+                    i += len + 1;
+                    continue;
+                } else {
+                    None
+                };
+                if let Some(kind) = kind {
+                    tokens.push((i, i + len, kind));
+                }
+                i += len;
+                continue;
+            }
+            i += c.len_utf8();
+        }
+
+        // The text between the highlighted tokens isn't highlighted.
+        let mut result = Vec::with_capacity(2 * tokens.len() + 1);
+        let mut last = 0;
+        for (start, end, kind) in tokens {
+            if start > last {
+                result.push((line[last..start].to_string(), None));
+            }
+            if end > start {
+                result.push((line[start..end].to_string(), Some(kind)));
+            }
+            last = end;
+        }
+        if last < line.len() || result.is_empty() {
+            result.push((line[last..].to_string(), None));
+        }
+        result
+    }
+
+    /// Returns the length of the delimiter of the string starting at the beginning of `rest`, if
+    /// any, with the delimiter which ends it and whether backslashes escape characters in it.
+    fn get_string_start(&self, rest: &str, prev_is_ident: bool) -> Option<(usize, String, bool)> {
+        let language = self.language;
+        if language.rust_macros && !prev_is_ident {
+            // The raw strings, e.g. r#"..."# or br"...".
+            let prefix_len = if rest.starts_with("br") {
+                2
+            } else if rest.starts_with('r') {
+                1
+            } else {
+                0
+            };
+            if prefix_len > 0 {
+                let hashes = rest[prefix_len..]
+                    .find(|c| c != '#')
+                    .unwrap_or_else(|| rest.len() - prefix_len);
+                if rest[prefix_len + hashes..].starts_with('"') {
+                    let end = format!("\"{}", "#".repeat(hashes));
+                    return Some((prefix_len + hashes + 1, end, false));
+                }
+            }
+        }
+        language
+            .multiline_strings
+            .iter()
+            .chain(language.strings.iter())
+            .find(|delimiter| rest.starts_with(*delimiter))
+            .map(|delimiter| (delimiter.len(), delimiter.to_string(), true))
+    }
+
+    fn end_comment(&mut self, line: &str, start: usize, end: &'static str) -> usize {
+        match line[start..].find(end) {
+            Some(pos) => start + pos + end.len(),
+            None => {
+                self.state = State::Comment(end);
+                line.len()
+            }
+        }
+    }
+
+    fn end_string(&mut self, line: &str, start: usize, end: String, escapes: bool) -> usize {
+        let mut i = start;
+        while i < line.len() {
+            let rest = &line[i..];
+            if escapes && rest.starts_with('\\') {
+                i += 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+            } else if rest.starts_with(end.as_str()) {
+                return i + end.len();
+            } else {
+                i += rest.chars().next().unwrap().len_utf8();
+            }
+        }
+        // Raw strings can span several lines, like the strings ending lines with a backslash.
+        if !escapes
+            || line.ends_with('\\')
+            || self.language.multiline_strings.contains(&end.as_str())
+        {
+            self.state = State::String(end, escapes);
+        }
+        line.len()
+    }
+}
+
+/// Returns the length of the Rust character literal at the beginning of `rest`, if any.
+fn get_char_literal_len(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    let (_, c) = chars.next()?;
+    if c == '\\' {
+        // An escaped character, e.g. '\n' or '\u{1F600}'.
+        return rest[2..].find('\'').map(|end| end + 3);
+    }
+    match chars.next() {
+        Some((pos, '\'')) => Some(pos + 1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the highlighted tokens of the lines, with their kind.
+    fn highlight(file_name: &str, lines: &[&str]) -> Vec<Vec<(String, &'static str)>> {
+        let mut highlighter = Highlighter::for_path(Path::new(file_name)).unwrap();
+        lines
+            .iter()
+            .map(|line| {
+                let tokens = highlighter.highlight_line(line);
+                assert_eq!(
+                    tokens
+                        .iter()
+                        .map(|(text, _)| text.as_str())
+                        .collect::<String>(),
+                    *line
+                );
+                tokens
+                    .into_iter()
+                    .filter_map(|(text, kind)| kind.map(|kind| (text, kind.as_str())))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn tokens(tokens: &[(&str, &'static str)]) -> Vec<(String, &'static str)> {
+        tokens
+            .iter()
+            .map(|&(text, kind)| (text.to_string(), kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_highlight_rust() {
+        let lines = highlight(
+            "src/main.rs",
+            &[
+                "#[derive(Debug)]",
+                "pub fn main<'a>(x: &'a str) -> Option<u32> {",
+                "    let c = '\\n'; // Done",
+                "    println!(\"{} /* {}\", r#\"raw \" string\"#, 0x1F..10);",
+                "    /* A comment",
+                "       on two lines */ let s = \"a",
+                "b\";",
+            ],
+        );
+        assert_eq!(lines[0], tokens(&[("#[derive(Debug)]", "macro")]));
+        assert_eq!(
+            lines[1],
+            tokens(&[
+                ("pub", "keyword"),
+                ("fn", "keyword"),
+                ("str", "type"),
+                ("Option", "type"),
+                ("u32", "type"),
+            ])
+        );
+        assert_eq!(
+            lines[2],
+            tokens(&[
+                ("let", "keyword"),
+                ("'\\n'", "string"),
+                ("// Done", "comment")
+            ])
+        );
+        assert_eq!(
+            lines[3],
+            tokens(&[
+                ("println!", "macro"),
+                ("\"{} /* {}\"", "string"),
+                ("r#\"raw \" string\"#", "string"),
+                ("0x1F", "number"),
+                ("10", "number"),
+            ])
+        );
+        assert_eq!(lines[4], tokens(&[("/* A comment", "comment")]));
+        assert_eq!(
+            lines[5],
+            tokens(&[
+                ("       on two lines */", "comment"),
+                ("let", "keyword"),
+                ("\"a", "string"),
+            ])
+        );
+        assert_eq!(lines[6], tokens(&[("b\"", "string")]));
+    }
+
+    #[test]
+    fn test_highlight_c() {
+        for file_name in &["main.c", "main.cpp", "main.HPP"] {
+            let lines = highlight(
+                file_name,
+                &[
+                    "#include <stdio.h> // IO",
+                    "int main(void) { return printf(\"%d\\n\", 'a') > 1.5e3; }",
+                    "char *s = \"unterminated",
+                    "int x;",
+                ],
+            );
+            assert_eq!(
+                lines[0],
+                tokens(&[("#include <stdio.h> ", "macro"), ("// IO", "comment")])
+            );
+            assert_eq!(
+                lines[1],
+                tokens(&[
+                    ("int", "type"),
+                    ("void", "type"),
+                    ("return", "keyword"),
+                    ("\"%d\\n\"", "string"),
+                    ("'a'", "string"),
+                    ("1.5e3", "number"),
+                ])
+            );
+            // The strings which aren't terminated end with their line.
+            assert_eq!(lines[3], tokens(&[("int", "type")]));
+        }
+
+        let lines = highlight("main.cpp", &["template <typename T> class A {};"]);
+        assert_eq!(
+            lines[0],
+            tokens(&[
+                ("template", "keyword"),
+                ("typename", "keyword"),
+                ("class", "keyword"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_highlight_java_js() {
+        let lines = highlight(
+            "Main.java",
+            &["public static void main(String[] args) { int i = 0; }"],
+        );
+        assert_eq!(
+            lines[0],
+            tokens(&[
+                ("public", "keyword"),
+                ("static", "keyword"),
+                ("void", "type"),
+                ("String", "type"),
+                ("int", "type"),
+                ("0", "number"),
+            ])
+        );
+
+        let lines = highlight(
+            "app.js",
+            &["const s = `multi", "line ${x}`; /* done */ return null;"],
+        );
+        assert_eq!(
+            lines[0],
+            tokens(&[("const", "keyword"), ("`multi", "string")])
+        );
+        assert_eq!(
+            lines[1],
+            tokens(&[
+                ("line ${x}`", "string"),
+                ("/* done */", "comment"),
+                ("return", "keyword"),
+                ("null", "keyword"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_highlight_python() {
+        let lines = highlight(
+            "main.py",
+            &[
+                "def f(x: int) -> None:  # Comment",
+                "    \"\"\"Docstring",
+                "    'still'\"\"\" if x else 'no'",
+            ],
+        );
+        assert_eq!(
+            lines[0],
+            tokens(&[
+                ("def", "keyword"),
+                ("int", "type"),
+                ("None", "keyword"),
+                ("# Comment", "comment"),
+            ])
+        );
+        assert_eq!(lines[1], tokens(&[("\"\"\"Docstring", "string")]));
+        assert_eq!(
+            lines[2],
+            tokens(&[
+                ("    'still'\"\"\"", "string"),
+                ("if", "keyword"),
+                ("else", "keyword"),
+                ("'no'", "string"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_highlight_unknown_language() {
+        assert!(Highlighter::for_path(Path::new("README.md")).is_none());
+        assert!(Highlighter::for_path(Path::new("Makefile")).is_none());
+    }
+}
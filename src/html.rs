@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use rustc_hash::FxHashMap;
 use serde::Serialize;
 use serde_json::value::{from_value, to_value, Value};
 use std::array;
@@ -8,9 +9,12 @@ use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use symbolic_demangle::DemangleOptions;
 use tera::try_get_value;
 
 use crate::defs::*;
+use crate::error::GrcovError;
+use crate::highlight::{Highlighter, TokenKind};
 
 impl HtmlStats {
     #[inline(always)]
@@ -24,6 +28,35 @@ impl HtmlStats {
     }
 }
 
+/// The options of the HTML report. The limits are the coverage percentages from which the
+/// coverage of the lines, functions and branches is shown as high or medium.
+#[derive(Clone, Debug)]
+pub struct HtmlOptions {
+    /// A directory containing templates which override the built-in ones, with the same names
+    /// (e.g. `file.html`, `badges/flat.svg` or the `grcov.css` stylesheet).
+    pub template_dir: Option<PathBuf>,
+    pub hi_limit: f64,
+    pub med_limit: f64,
+    pub fn_hi_limit: f64,
+    pub fn_med_limit: f64,
+    pub branch_hi_limit: f64,
+    pub branch_med_limit: f64,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        HtmlOptions {
+            template_dir: None,
+            hi_limit: 90.,
+            med_limit: 75.,
+            fn_hi_limit: 90.,
+            fn_med_limit: 75.,
+            branch_hi_limit: 90.,
+            branch_med_limit: 75.,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Config {
     hi_limit: f64,
@@ -32,19 +65,80 @@ pub struct Config {
     fn_med_limit: f64,
     branch_hi_limit: f64,
     branch_med_limit: f64,
+    stylesheet: String,
     date: DateTime<Utc>,
 }
 
-static BULMA_VERSION: &str = "0.9.1";
+/// The stylesheet of the reports, written next to them so that they can be viewed offline.
+static STYLESHEET: &str = include_str!("templates/grcov.css");
+static STYLESHEET_NAME: &str = "grcov.css";
+
+/// Returns the message of a Tera error, with the errors which caused it.
+fn get_error_message(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+    message
+}
+
+/// Reads the file of a template from the template directory, if it contains one.
+fn read_template(
+    template_dir: Option<&Path>,
+    file_name: &str,
+) -> Result<Option<String>, GrcovError> {
+    let path = match template_dir {
+        Some(template_dir) => template_dir.join(file_name),
+        None => return Ok(None),
+    };
+    if !path.is_file() {
+        return Ok(None);
+    }
+    fs::read_to_string(&path)
+        .map(Some)
+        .map_err(|e| GrcovError::Input(path.display().to_string(), e.to_string()))
+}
+
+pub fn get_config(options: &HtmlOptions) -> Result<(Tera, Config), GrcovError> {
+    for (kind, hi, med) in &[
+        ("lines", options.hi_limit, options.med_limit),
+        ("functions", options.fn_hi_limit, options.fn_med_limit),
+        (
+            "branches",
+            options.branch_hi_limit,
+            options.branch_med_limit,
+        ),
+    ] {
+        if !(0. ..=100.).contains(med) || !(*med..=100.).contains(hi) {
+            return Err(GrcovError::Other(format!(
+                "Invalid HTML limits for the {}: the medium limit ({}) and the high limit ({}) must be percentages, in increasing order",
+                kind, med, hi
+            )));
+        }
+    }
+
+    let template_dir = options.template_dir.as_deref();
+    if let Some(template_dir) = template_dir {
+        if !template_dir.is_dir() {
+            return Err(GrcovError::Input(
+                template_dir.display().to_string(),
+                "The template directory doesn't exist".to_string(),
+            ));
+        }
+    }
 
-pub fn get_config() -> (Tera, Config) {
     let conf = Config {
-        hi_limit: 90.,
-        med_limit: 75.,
-        fn_hi_limit: 90.,
-        fn_med_limit: 75.,
-        branch_hi_limit: 90.,
-        branch_med_limit: 75.,
+        hi_limit: options.hi_limit,
+        med_limit: options.med_limit,
+        fn_hi_limit: options.fn_hi_limit,
+        fn_med_limit: options.fn_med_limit,
+        branch_hi_limit: options.branch_hi_limit,
+        branch_med_limit: options.branch_med_limit,
+        stylesheet: read_template(template_dir, STYLESHEET_NAME)?
+            .unwrap_or_else(|| STYLESHEET.to_string()),
         date: Utc::now(),
     };
 
@@ -53,35 +147,101 @@ pub fn get_config() -> (Tera, Config) {
     tera.register_filter("severity", conf.clone());
     tera.register_function("percent", &percent);
 
-    tera.add_raw_templates(vec![
-        ("macros.html", include_str!("templates/macros.html")),
-        ("base.html", include_str!("templates/base.html")),
-        ("index.html", include_str!("templates/index.html")),
-        ("file.html", include_str!("templates/file.html")),
+    // The built-in templates, by file name, with the name they are registered with.
+    let builtin_templates = vec![
         (
+            "macros.html",
+            "macros.html",
+            include_str!("templates/macros.html"),
+        ),
+        (
+            "base.html",
+            "base.html",
+            include_str!("templates/base.html"),
+        ),
+        (
+            "index.html",
+            "index.html",
+            include_str!("templates/index.html"),
+        ),
+        (
+            "file.html",
+            "file.html",
+            include_str!("templates/file.html"),
+        ),
+        (
+            "single.html",
+            "single.html",
+            include_str!("templates/single.html"),
+        ),
+        (
+            "badges/flat.svg",
             BadgeStyle::Flat.template_name(),
             include_str!("templates/badges/flat.svg"),
         ),
         (
+            "badges/flat_square.svg",
             BadgeStyle::FlatSquare.template_name(),
             include_str!("templates/badges/flat_square.svg"),
         ),
         (
+            "badges/for_the_badge.svg",
             BadgeStyle::ForTheBadge.template_name(),
             include_str!("templates/badges/for_the_badge.svg"),
         ),
         (
+            "badges/plastic.svg",
             BadgeStyle::Plastic.template_name(),
             include_str!("templates/badges/plastic.svg"),
         ),
         (
+            "badges/social.svg",
             BadgeStyle::Social.template_name(),
             include_str!("templates/badges/social.svg"),
         ),
-    ])
-    .unwrap();
+    ];
+    let mut templates = Vec::with_capacity(builtin_templates.len());
+    for (file_name, name, builtin) in builtin_templates {
+        let template =
+            read_template(template_dir, file_name)?.unwrap_or_else(|| builtin.to_string());
+        templates.push((name, template));
+    }
+    tera.add_raw_templates(templates).map_err(|e| {
+        let message = get_error_message(&e);
+        match template_dir {
+            Some(template_dir) => GrcovError::Input(template_dir.display().to_string(), message),
+            None => GrcovError::Other(message),
+        }
+    })?;
+
+    Ok((tera, conf))
+}
 
-    (tera, conf)
+/// Renders a template, the errors are only reported since they are caused by custom templates.
+fn render(tera: &Tera, name: &str, ctx: &Context) -> Option<String> {
+    match tera.render(name, ctx) {
+        Ok(out) => Some(out),
+        Err(e) => {
+            eprintln!(
+                "Cannot render the template {}: {}",
+                name,
+                get_error_message(&e)
+            );
+            None
+        }
+    }
+}
+
+/// Returns the severity of a coverage percentage for the given high and medium limits: "success",
+/// "warning" or "danger".
+pub fn get_severity(hi: f64, medium: f64, rate: f64) -> &'static str {
+    if hi <= rate && rate <= 100. {
+        "success"
+    } else if medium <= rate && rate < hi {
+        "warning"
+    } else {
+        "danger"
+    }
 }
 
 impl tera::Filter for Config {
@@ -94,14 +254,7 @@ impl tera::Filter for Config {
         };
 
         fn severity(hi: f64, medium: f64, rate: f64) -> Value {
-            to_value(if hi <= rate && rate <= 100. {
-                "success"
-            } else if medium <= rate && rate < hi {
-                "warning"
-            } else {
-                "danger"
-            })
-            .unwrap()
+            to_value(get_severity(hi, medium, rate)).unwrap()
         }
 
         match kind.as_ref() {
@@ -130,7 +283,7 @@ fn add_html_ext(path: &Path) -> PathBuf {
     }
 }
 
-fn get_stats(result: &CovResult) -> HtmlStats {
+pub fn get_stats(result: &CovResult) -> HtmlStats {
     let total_lines = result.lines.len();
     let covered_lines = result.lines.values().filter(|x| **x > 0).count();
     let total_funs = result.functions.len();
@@ -139,7 +292,7 @@ fn get_stats(result: &CovResult) -> HtmlStats {
     let covered_branches = result
         .branches
         .values()
-        .map(|v| v.iter().filter(|x| **x).count())
+        .map(|v| v.iter().filter(|&&x| x > 0).count())
         .sum();
 
     HtmlStats {
@@ -152,12 +305,13 @@ fn get_stats(result: &CovResult) -> HtmlStats {
     }
 }
 
+/// Returns the percentage of covered items, or `None` when there is nothing to cover.
 #[inline(always)]
-fn get_percentage(x: usize, y: usize) -> f64 {
-    if y != 0 {
-        (x as f64) / (y as f64) * 100.
+pub(crate) fn get_percentage(covered: usize, total: usize) -> Option<f64> {
+    if total != 0 {
+        Some((covered as f64) / (total as f64) * 100.)
     } else {
-        0.0
+        None
     }
 }
 
@@ -167,7 +321,7 @@ fn percent(args: &HashMap<String, Value>) -> tera::Result<Value> {
             from_value::<usize>(n.clone()),
             from_value::<usize>(d.clone()),
         ) {
-            Ok(to_value(get_percentage(num, den)).unwrap())
+            Ok(to_value(get_percentage(num, den).unwrap_or(0.0)).unwrap())
         } else {
             Err(tera::Error::msg("Invalid arguments"))
         }
@@ -176,12 +330,7 @@ fn percent(args: &HashMap<String, Value>) -> tera::Result<Value> {
     }
 }
 
-fn get_base(rel_path: &Path) -> String {
-    let count = rel_path.components().count() - 1 /* -1 for the file itself */;
-    "../".repeat(count)
-}
-
-fn get_dirs_result(global: Arc<Mutex<HtmlGlobalStats>>, rel_path: &Path, stats: &HtmlStats) {
+pub fn get_dirs_result(global: Arc<Mutex<HtmlGlobalStats>>, rel_path: &Path, stats: &HtmlStats) {
     let parent = rel_path.parent().unwrap().to_str().unwrap().to_string();
     let file_name = rel_path.file_name().unwrap().to_str().unwrap().to_string();
     let fs = HtmlFileStats {
@@ -199,6 +348,7 @@ fn get_dirs_result(global: Arc<Mutex<HtmlGlobalStats>>, rel_path: &Path, stats:
             let mut files = BTreeMap::new();
             files.insert(file_name, fs);
             v.insert(HtmlDirStats {
+                dirs: BTreeMap::new(),
                 files,
                 stats: stats.clone(),
             });
@@ -206,16 +356,81 @@ fn get_dirs_result(global: Arc<Mutex<HtmlGlobalStats>>, rel_path: &Path, stats:
     };
 }
 
+/// Aggregates the stats of the results by directory, as for the index of the report, without
+/// rendering it.
+pub fn get_global_stats(results: CovResultIter) -> HtmlGlobalStats {
+    let global = Arc::new(Mutex::new(HtmlGlobalStats::default()));
+    for (_, rel_path, result) in results {
+        get_dirs_result(global.clone(), &rel_path, &get_stats(&result));
+    }
+    Arc::try_unwrap(global).unwrap().into_inner().unwrap()
+}
+
 use tera::{Context, Tera};
 
 fn make_context() -> Context {
     let mut ctx = Context::new();
-    let ver = std::env::var("BULMA_VERSION").map_or(BULMA_VERSION.into(), |v| v);
-    ctx.insert("bulma_version", &ver);
+    // The pages are rendered without their head and footer when they are bundled in a single file.
+    ctx.insert("fragment", &false);
 
     ctx
 }
 
+/// Builds the tree of the directories from the ones containing files: every directory, up to the
+/// top level one (""), lists its subdirectories and has the stats of all the files it contains.
+pub fn get_dirs_tree(dirs: &BTreeMap<String, HtmlDirStats>) -> BTreeMap<String, HtmlDirStats> {
+    let mut tree: BTreeMap<String, HtmlDirStats> = BTreeMap::new();
+    for (dir_name, dir_stats) in dirs {
+        tree.entry(dir_name.clone()).or_default().files = dir_stats.files.clone();
+
+        let mut dir = Path::new(dir_name);
+        loop {
+            let key = dir.to_str().unwrap().to_string();
+            tree.entry(key).or_default().stats.add(&dir_stats.stats);
+            let parent = match dir.parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+            let name = dir.file_name().unwrap().to_str().unwrap().to_string();
+            tree.entry(parent.to_str().unwrap().to_string())
+                .or_default()
+                .dirs
+                .entry(name)
+                .or_default()
+                .add(&dir_stats.stats);
+            dir = parent;
+        }
+    }
+    tree
+}
+
+/// Returns the breadcrumbs of a page in the directory `dir`, with the links to the index of each
+/// of its ancestors and of the directory itself.
+fn get_breadcrumbs(dir: &Path) -> Vec<(String, String)> {
+    let depth = dir.components().count();
+    let mut breadcrumbs = vec![("../".repeat(depth) + "index.html", "top_level".to_string())];
+    for (i, component) in dir.components().enumerate() {
+        breadcrumbs.push((
+            "../".repeat(depth - i - 1) + "index.html",
+            component.as_os_str().to_str().unwrap().to_string(),
+        ));
+    }
+    breadcrumbs
+}
+
+/// Returns the relative URL of the root of the report from a page in the directory `dir`.
+fn get_root_url(dir: &Path) -> String {
+    "../".repeat(dir.components().count())
+}
+
+/// Returns the path of a page in the single file report, where the separator is always '/'.
+pub fn get_page_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_str().unwrap())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
 pub fn gen_index(
     tera: &Tera,
     global: &HtmlGlobalStats,
@@ -223,34 +438,7 @@ pub fn gen_index(
     output: &Path,
     branch_enabled: bool,
 ) {
-    let output_file = output.join("index.html");
-    create_parent(&output_file);
-    let mut output_stream = match File::create(&output_file) {
-        Err(_) => {
-            eprintln!("Cannot create file {:?}", output_file);
-            return;
-        }
-        Ok(f) => f,
-    };
-
-    let mut ctx = make_context();
-    let empty: &[&str] = &[];
-    ctx.insert("date", &conf.date);
-    ctx.insert("current", "top_level");
-    ctx.insert("parents", empty);
-    ctx.insert("stats", &global.stats);
-    ctx.insert("items", &global.dirs);
-    ctx.insert("kind", "Directory");
-    ctx.insert("branch_enabled", &branch_enabled);
-
-    let out = tera.render("index.html", &ctx).unwrap();
-
-    if output_stream.write_all(out.as_bytes()).is_err() {
-        eprintln!("Cannot write the file {:?}", output_file);
-        return;
-    }
-
-    for (dir_name, dir_stats) in global.dirs.iter() {
+    for (dir_name, dir_stats) in get_dirs_tree(&global.dirs).iter() {
         gen_dir_index(tera, dir_name, dir_stats, conf, output, branch_enabled);
     }
 }
@@ -274,21 +462,47 @@ pub fn gen_dir_index(
         Ok(f) => f,
     };
 
+    let ctx = make_dir_context(dir_name, dir_stats, conf, branch_enabled);
+    let out = match render(tera, "index.html", &ctx) {
+        Some(out) => out,
+        None => return,
+    };
+
+    if output.write_all(out.as_bytes()).is_err() {
+        eprintln!("Cannot write the file {:?}", output_file);
+    }
+}
+
+fn make_dir_context(
+    dir_name: &str,
+    dir_stats: &HtmlDirStats,
+    conf: &Config,
+    branch_enabled: bool,
+) -> Context {
+    // The last breadcrumb is the directory itself.
+    let mut parents = get_breadcrumbs(Path::new(dir_name));
+    let (_, current) = parents.pop().unwrap();
+
     let mut ctx = make_context();
     ctx.insert("date", &conf.date);
-    ctx.insert("bulma_version", BULMA_VERSION);
-    ctx.insert("current", dir_name);
-    ctx.insert("parents", &[("../index.html", "top_level")]);
+    ctx.insert("root_url", &get_root_url(Path::new(dir_name)));
+    ctx.insert("current", &current);
+    ctx.insert("parents", &parents);
     ctx.insert("stats", &dir_stats.stats);
-    ctx.insert("items", &dir_stats.files);
-    ctx.insert("kind", "File");
+    ctx.insert("dirs", &dir_stats.dirs);
+    ctx.insert("files", &dir_stats.files);
     ctx.insert("branch_enabled", &branch_enabled);
 
-    let out = tera.render("index.html", &ctx).unwrap();
+    ctx
+}
 
-    if output.write_all(out.as_bytes()).is_err() {
-        eprintln!("Cannot write the file {:?}", output_file);
+/// Opens the source file of a result to show it in a report, the files which are outside of the
+/// source directory or can't be read are left out of the reports.
+pub fn open_source(abs_path: &Path, rel_path: &Path) -> Option<BufReader<File>> {
+    if !rel_path.is_relative() {
+        return None;
     }
+    File::open(abs_path).ok().map(BufReader::new)
 }
 
 fn gen_html(
@@ -300,19 +514,12 @@ fn gen_html(
     rel_path: &Path,
     global: Arc<Mutex<HtmlGlobalStats>>,
     branch_enabled: bool,
+    demangle: bool,
 ) {
-    if !rel_path.is_relative() {
-        return;
-    }
-
-    let f = match File::open(&path) {
-        Err(_) => {
-            //eprintln!("Warning: cannot open file {:?}", path);
-            return;
-        }
-        Ok(f) => f,
+    let f = match open_source(path, rel_path) {
+        Some(f) => f,
+        None => return,
     };
-    let f = BufReader::new(f);
 
     let stats = get_stats(result);
     get_dirs_result(global, rel_path, &stats);
@@ -326,26 +533,46 @@ fn gen_html(
         }
         Ok(f) => f,
     };
-    let base_url = get_base(rel_path);
+
+    let ctx = make_file_context(f, result, &stats, conf, rel_path, branch_enabled, demangle);
+    let out = match render(tera, "file.html", &ctx) {
+        Some(out) => out,
+        None => return,
+    };
+
+    if output.write_all(out.as_bytes()).is_err() {
+        eprintln!("Cannot write the file {:?}", output_file);
+    }
+}
+
+fn make_file_context(
+    f: impl BufRead,
+    result: &CovResult,
+    stats: &HtmlStats,
+    conf: &Config,
+    rel_path: &Path,
+    branch_enabled: bool,
+    demangle: bool,
+) -> Context {
     let filename = rel_path.file_name().unwrap().to_str().unwrap();
-    let parent = rel_path.parent().unwrap().to_str().unwrap().to_string();
-    let mut index_url = base_url;
-    index_url.push_str("index.html");
 
     let mut ctx = make_context();
     ctx.insert("date", &conf.date);
-    ctx.insert("bulma_version", BULMA_VERSION);
+    ctx.insert("root_url", &get_root_url(rel_path.parent().unwrap()));
     ctx.insert("current", filename);
-    ctx.insert(
-        "parents",
-        &[
-            (index_url.as_str(), "top_level"),
-            ("./index.html", parent.as_str()),
-        ],
-    );
+    ctx.insert("parents", &get_breadcrumbs(rel_path.parent().unwrap()));
     ctx.insert("stats", &stats);
     ctx.insert("branch_enabled", &branch_enabled);
 
+    // The regions touching each line, only needed for the lines containing uncovered regions.
+    let mut line_regions: FxHashMap<u32, Vec<(RegionSpan, u64)>> = FxHashMap::default();
+    for (span, &count) in &result.regions {
+        for line in span.start_line..=span.end_line {
+            line_regions.entry(line).or_default().push((*span, count));
+        }
+    }
+
+    let mut highlighter = Highlighter::for_path(rel_path);
     let items = f
         .lines()
         .enumerate()
@@ -356,20 +583,241 @@ fn gen_html(
                 .get(&(index as u32))
                 .map(|&v| v as i64)
                 .unwrap_or(-1);
+            let l = l.unwrap();
+
+            let segments = match line_regions.get(&(index as u32)) {
+                Some(regions) if count > 0 && regions.iter().any(|&(_, c)| c == 0) => {
+                    get_line_segments(&l, index as u32, regions)
+                }
+                _ => vec![(l.clone(), false)],
+            };
+            let segments = match highlighter.as_mut() {
+                Some(highlighter) => highlight_segments(segments, highlighter.highlight_line(&l)),
+                None => segments
+                    .into_iter()
+                    .map(|(text, uncovered)| (text, uncovered, None))
+                    .collect(),
+            };
 
-            (index, count, l.unwrap())
+            // The contexts which covered the line.
+            let contexts: Vec<&str> = result
+                .contexts
+                .lines
+                .get(&(index as u32))
+                .map(|counts| {
+                    counts
+                        .iter()
+                        .filter(|&(_, &count)| count > 0)
+                        .map(|(label, _)| label.as_str())
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let branches = if branch_enabled {
+                result
+                    .branches
+                    .get(&(index as u32))
+                    .map(|counts| get_branch_summary(counts))
+            } else {
+                None
+            };
+
+            (index, count, segments, contexts.join(", "), branches)
         })
         .collect::<Vec<_>>();
 
     ctx.insert("items", &items);
 
-    let out = tera.render("file.html", &ctx).unwrap();
+    // The name, start line, executed state and call count of the functions, by start line.
+    let demangle_options = DemangleOptions::name_only();
+    let mut functions: Vec<(String, u32, bool, u64)> = result
+        .functions
+        .iter()
+        .map(|(name, f)| {
+            let name = demangle!(name, demangle, demangle_options).to_string();
+            (name, f.start, f.executed, f.count)
+        })
+        .collect();
+    functions.sort_by(|(a_name, a_start, _, _), (b_name, b_start, _, _)| {
+        (a_start, a_name).cmp(&(b_start, b_name))
+    });
+    ctx.insert("functions", &functions);
 
-    if output.write_all(out.as_bytes()).is_err() {
+    ctx
+}
+
+/// Writes the stylesheet used by the pages of the report.
+pub fn gen_stylesheet(conf: &Config, output: &Path) {
+    let output_file = output.join(STYLESHEET_NAME);
+    if fs::write(&output_file, &conf.stylesheet).is_err() {
         eprintln!("Cannot write the file {:?}", output_file);
     }
 }
 
+/// Renders the whole report in a single self-contained file, the pages of the directories and of
+/// the source files are bundled in it and shown client-side.
+pub fn gen_single_file(
+    tera: &Tera,
+    conf: &Config,
+    results: CovResultIter,
+    branch_enabled: bool,
+    demangle: bool,
+    mut output: impl Write,
+) -> Result<(), GrcovError> {
+    let render = |name: &str, ctx: &Context| {
+        tera.render(name, ctx).map_err(|e| {
+            GrcovError::Output(format!(
+                "Cannot render the template {}: {}",
+                name,
+                get_error_message(&e)
+            ))
+        })
+    };
+    let global = Arc::new(Mutex::new(HtmlGlobalStats::default()));
+    let mut pages: BTreeMap<String, String> = BTreeMap::new();
+    for (abs_path, rel_path, result) in results {
+        let f = match open_source(&abs_path, &rel_path) {
+            Some(f) => f,
+            None => continue,
+        };
+
+        let stats = get_stats(&result);
+        get_dirs_result(global.clone(), &rel_path, &stats);
+
+        let mut ctx = make_file_context(
+            f,
+            &result,
+            &stats,
+            conf,
+            &rel_path,
+            branch_enabled,
+            demangle,
+        );
+        ctx.insert("fragment", &true);
+        pages.insert(
+            get_page_path(&add_html_ext(&rel_path)),
+            render("file.html", &ctx)?,
+        );
+    }
+
+    let global = global.lock().unwrap();
+    for (dir_name, dir_stats) in get_dirs_tree(&global.dirs).iter() {
+        let mut ctx = make_dir_context(dir_name, dir_stats, conf, branch_enabled);
+        ctx.insert("fragment", &true);
+        pages.insert(
+            get_page_path(&Path::new(dir_name).join("index.html")),
+            render("index.html", &ctx)?,
+        );
+    }
+
+    let mut ctx = make_context();
+    ctx.insert("date", &conf.date);
+    ctx.insert("stylesheet", &conf.stylesheet);
+    ctx.insert("pages", &pages);
+    let out = render("single.html", &ctx)?;
+
+    output.write_all(out.as_bytes())?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Splits the segments of a line at the boundaries of its highlighted tokens, and adds the kind
+/// of the tokens to them.
+fn highlight_segments(
+    segments: Vec<(String, bool)>,
+    tokens: Vec<(String, Option<TokenKind>)>,
+) -> Vec<(String, bool, Option<&'static str>)> {
+    let mut result = Vec::with_capacity(segments.len() + tokens.len());
+    let mut tokens = tokens.into_iter().peekable();
+    let mut token_offset = 0;
+    for (text, uncovered) in segments {
+        let mut offset = 0;
+        while offset < text.len() {
+            let (token, kind) = match tokens.peek() {
+                Some(token) => token,
+                None => break,
+            };
+            let len = (token.len() - token_offset).min(text.len() - offset);
+            result.push((
+                text[offset..offset + len].to_string(),
+                uncovered,
+                kind.map(TokenKind::as_str),
+            ));
+            offset += len;
+            token_offset += len;
+            if token_offset == token.len() {
+                tokens.next();
+                token_offset = 0;
+            }
+        }
+        if text.is_empty() {
+            result.push((text, uncovered, None));
+        }
+    }
+    result
+}
+
+/// Returns the number of branches of a line which were taken, the number of branches and the
+/// description of the count of each branch.
+fn get_branch_summary(counts: &[u64]) -> (usize, usize, String) {
+    let taken = counts.iter().filter(|&&count| count > 0).count();
+    let details: Vec<String> = counts
+        .iter()
+        .enumerate()
+        .map(|(n, &count)| match count {
+            0 => format!("Branch {}: not taken", n),
+            1 => format!("Branch {}: taken once", n),
+            _ => format!("Branch {}: taken {} times", n, count),
+        })
+        .collect();
+    (taken, counts.len(), details.join("\n"))
+}
+
+/// Splits a line into segments, flagging the ones which are in a region that was never executed.
+/// Regions are applied from the outermost to the innermost, so that code executed inside an
+/// uncovered region is not flagged.
+fn get_line_segments(
+    line: &str,
+    line_no: u32,
+    regions: &[(RegionSpan, u64)],
+) -> Vec<(String, bool)> {
+    let mut regions = regions.to_vec();
+    regions.sort_by(|(a, _), (b, _)| {
+        (a.start_line, a.start_col)
+            .cmp(&(b.start_line, b.start_col))
+            .then((b.end_line, b.end_col).cmp(&(a.end_line, a.end_col)))
+    });
+
+    let mut uncovered = vec![false; line.len()];
+    for (span, count) in regions {
+        let start = if span.start_line == line_no {
+            span.start_col.saturating_sub(1) as usize
+        } else {
+            0
+        };
+        let end = if span.end_line == line_no {
+            span.end_col.saturating_sub(1) as usize
+        } else {
+            line.len()
+        };
+        for flag in uncovered.iter_mut().take(end).skip(start) {
+            *flag = count == 0;
+        }
+    }
+
+    let mut segments: Vec<(String, bool)> = Vec::new();
+    for (i, c) in line.char_indices() {
+        match segments.last_mut() {
+            Some((text, flag)) if *flag == uncovered[i] => text.push(c),
+            _ => segments.push((c.to_string(), uncovered[i])),
+        }
+    }
+    if segments.is_empty() {
+        segments.push((String::new(), false));
+    }
+    segments
+}
+
 pub fn consumer_html(
     tera: &Tera,
     receiver: HtmlJobReceiver,
@@ -377,6 +825,7 @@ pub fn consumer_html(
     output: &Path,
     conf: Config,
     branch_enabled: bool,
+    demangle: bool,
 ) {
     while let Ok(job) = receiver.recv() {
         if job.is_none() {
@@ -392,6 +841,7 @@ pub fn consumer_html(
             &job.rel_path,
             global.clone(),
             branch_enabled,
+            demangle,
         );
     }
 }
@@ -459,7 +909,10 @@ pub fn gen_badge(tera: &Tera, stats: &HtmlStats, conf: &Config, output: &Path, s
     ctx.insert("hi_limit", &conf.hi_limit);
     ctx.insert("med_limit", &conf.med_limit);
 
-    let out = tera.render(style.template_name(), &ctx).unwrap();
+    let out = match render(tera, style.template_name(), &ctx) {
+        Some(out) => out,
+        None => return,
+    };
 
     if output_stream.write_all(out.as_bytes()).is_err() {
         eprintln!("Cannot write the file {:?}", output_file);
@@ -521,3 +974,238 @@ pub fn gen_coverage_json(stats: &HtmlStats, conf: &Config, output: &Path) {
         eprintln!("cannot write the file {:?}", output_file);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_line_segments() {
+        let span = |start_line, start_col, end_line, end_col| RegionSpan {
+            start_line,
+            start_col,
+            end_line,
+            end_col,
+        };
+
+        let line = "    if a { b() } else { c() }";
+        let regions = vec![
+            (span(1, 1, 3, 2), 1),
+            (span(2, 8, 2, 9), 1),
+            (span(2, 10, 2, 17), 0),
+            (span(2, 12, 2, 15), 0),
+            (span(2, 23, 2, 30), 1),
+        ];
+        assert_eq!(
+            get_line_segments(line, 2, &regions),
+            vec![
+                ("    if a ".to_string(), false),
+                ("{ b() }".to_string(), true),
+                (" else { c() }".to_string(), false),
+            ]
+        );
+
+        // A covered region nested in an uncovered one.
+        let regions = vec![(span(1, 1, 3, 2), 0), (span(2, 10, 2, 17), 3)];
+        assert_eq!(
+            get_line_segments(line, 2, &regions),
+            vec![
+                ("    if a ".to_string(), true),
+                ("{ b() }".to_string(), false),
+                (" else { c() }".to_string(), true),
+            ]
+        );
+
+        // Columns are in bytes, segments must not split characters.
+        let regions = vec![(span(1, 4, 1, 5), 0)];
+        assert_eq!(
+            get_line_segments("é = ö;", 1, &regions),
+            vec![
+                ("é ".to_string(), false),
+                ("=".to_string(), true),
+                (" ö;".to_string(), false)
+            ]
+        );
+
+        assert_eq!(
+            get_line_segments("", 1, &regions),
+            vec![(String::new(), false)]
+        );
+    }
+
+    #[test]
+    fn test_get_config_template_dir() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        fs::create_dir(tmp_dir.path().join("badges")).unwrap();
+        fs::write(
+            tmp_dir.path().join("badges/flat.svg"),
+            "{{ current }}% {{ hi_limit }}",
+        )
+        .unwrap();
+        fs::write(tmp_dir.path().join("grcov.css"), "body {}").unwrap();
+
+        let options = HtmlOptions {
+            template_dir: Some(tmp_dir.path().to_path_buf()),
+            hi_limit: 80.,
+            ..Default::default()
+        };
+        let (tera, conf) = get_config(&options).unwrap();
+        assert_eq!(conf.stylesheet, "body {}");
+        let stats = HtmlStats {
+            total_lines: 4,
+            covered_lines: 3,
+            ..Default::default()
+        };
+        gen_badge(&tera, &stats, &conf, tmp_dir.path(), BadgeStyle::Flat);
+        assert_eq!(
+            fs::read_to_string(tmp_dir.path().join("badges/flat.svg")).unwrap(),
+            "75% 80"
+        );
+        // The templates which aren't overridden are the built-in ones.
+        assert!(tera.get_template_names().any(|name| name == "file.html"));
+
+        fs::write(tmp_dir.path().join("index.html"), "{% if %}").unwrap();
+        assert!(get_config(&options).is_err());
+
+        let options = HtmlOptions {
+            template_dir: Some(tmp_dir.path().join("missing")),
+            ..Default::default()
+        };
+        assert!(get_config(&options).is_err());
+    }
+
+    #[test]
+    fn test_get_config_limits() {
+        let severity = |options: &HtmlOptions, rate: f64, kind: &str| {
+            let (_, conf) = get_config(options).unwrap();
+            let mut args = HashMap::new();
+            args.insert("kind".to_string(), to_value(kind).unwrap());
+            tera::Filter::filter(&conf, &to_value(rate).unwrap(), &args).unwrap()
+        };
+
+        let options = HtmlOptions {
+            fn_hi_limit: 60.,
+            fn_med_limit: 50.,
+            ..Default::default()
+        };
+        assert_eq!(severity(&options, 70., "lines"), "danger");
+        assert_eq!(severity(&options, 70., "functions"), "success");
+        assert_eq!(severity(&options, 55., "functions"), "warning");
+
+        for options in &[
+            HtmlOptions {
+                med_limit: 95.,
+                ..Default::default()
+            },
+            HtmlOptions {
+                branch_hi_limit: 101.,
+                ..Default::default()
+            },
+            HtmlOptions {
+                fn_med_limit: -1.,
+                ..Default::default()
+            },
+        ] {
+            assert!(get_config(options).is_err());
+        }
+    }
+
+    #[test]
+    fn test_highlight_segments() {
+        let segments = vec![("let x".to_string(), false), (" = 1;".to_string(), true)];
+        let tokens = vec![
+            ("let".to_string(), Some(TokenKind::Keyword)),
+            (" x = ".to_string(), None),
+            ("1".to_string(), Some(TokenKind::Number)),
+            (";".to_string(), None),
+        ];
+        assert_eq!(
+            highlight_segments(segments, tokens),
+            vec![
+                ("let".to_string(), false, Some("keyword")),
+                (" x".to_string(), false, None),
+                (" = ".to_string(), true, None),
+                ("1".to_string(), true, Some("number")),
+                (";".to_string(), true, None),
+            ]
+        );
+
+        let tokens = vec![(String::new(), None)];
+        assert_eq!(
+            highlight_segments(vec![(String::new(), false)], tokens),
+            vec![(String::new(), false, None)]
+        );
+    }
+
+    #[test]
+    fn test_get_branch_summary() {
+        assert_eq!(
+            get_branch_summary(&[3, 0, 1]),
+            (
+                2,
+                3,
+                "Branch 0: taken 3 times\nBranch 1: not taken\nBranch 2: taken once".to_string()
+            )
+        );
+        assert_eq!(get_branch_summary(&[]), (0, 0, String::new()));
+    }
+
+    #[test]
+    fn test_get_dirs_tree() {
+        let global = Arc::new(Mutex::new(HtmlGlobalStats::default()));
+        let stats = |total_lines, covered_lines| HtmlStats {
+            total_lines,
+            covered_lines,
+            ..Default::default()
+        };
+        get_dirs_result(global.clone(), Path::new("main.rs"), &stats(4, 1));
+        get_dirs_result(global.clone(), Path::new("src/a/a.rs"), &stats(10, 5));
+        get_dirs_result(global.clone(), Path::new("src/a/b.rs"), &stats(10, 10));
+        get_dirs_result(global.clone(), Path::new("src/c/d/e.rs"), &stats(2, 0));
+        let global = global.lock().unwrap();
+
+        let tree = get_dirs_tree(&global.dirs);
+        assert_eq!(
+            tree.keys().collect::<Vec<_>>(),
+            ["", "src", "src/a", "src/c", "src/c/d"]
+        );
+
+        let root = &tree[""];
+        assert_eq!(root.stats.total_lines, 26);
+        assert_eq!(root.stats.covered_lines, 16);
+        assert_eq!(root.dirs.keys().collect::<Vec<_>>(), ["src"]);
+        assert_eq!(root.dirs["src"].total_lines, 22);
+        assert_eq!(root.files.keys().collect::<Vec<_>>(), ["main.rs"]);
+
+        let src = &tree["src"];
+        assert_eq!(src.stats.covered_lines, 15);
+        assert_eq!(src.dirs.keys().collect::<Vec<_>>(), ["a", "c"]);
+        assert_eq!(src.dirs["a"].covered_lines, 15);
+        assert_eq!(src.dirs["c"].total_lines, 2);
+        assert!(src.files.is_empty());
+
+        assert_eq!(tree["src/c"].dirs["d"].total_lines, 2);
+        assert_eq!(tree["src/c/d"].files.keys().collect::<Vec<_>>(), ["e.rs"]);
+    }
+
+    #[test]
+    fn test_get_breadcrumbs() {
+        let breadcrumbs = |dir: &str| -> Vec<(String, String)> { get_breadcrumbs(Path::new(dir)) };
+        let to_strings = |v: &[(&str, &str)]| -> Vec<(String, String)> {
+            v.iter()
+                .map(|(url, name)| (url.to_string(), name.to_string()))
+                .collect()
+        };
+
+        assert_eq!(breadcrumbs(""), to_strings(&[("index.html", "top_level")]));
+        assert_eq!(
+            breadcrumbs("src/a/b"),
+            to_strings(&[
+                ("../../../index.html", "top_level"),
+                ("../../index.html", "src"),
+                ("../index.html", "a"),
+                ("index.html", "b"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::defs::*;
+use crate::error::GrcovError;
+use crate::merge_results;
+
+/// The extension of the intermediate coverage files.
+pub const INTERMEDIATE_EXTENSION: &str = "grcov";
+
+/// The version of the intermediate format, bumped on every incompatible change.
+pub const INTERMEDIATE_FORMAT_VERSION: u32 = 1;
+
+const INTERMEDIATE_FORMAT_NAME: &str = "grcov-intermediate";
+
+/// The coverage data aggregated from the inputs, before the paths are rewritten. It can be
+/// saved to an intermediate file (e.g. by every shard of a CI job) and merged with the other
+/// ones later on.
+#[derive(Debug, Default)]
+pub struct IntermediateCoverage {
+    pub results: CovResultMap,
+    // The mapping of the build paths to the source paths found in the inputs.
+    pub path_mapping: Option<Value>,
+}
+
+// The results are written as tuples and arrays, to keep the files small.
+#[derive(Serialize, Deserialize)]
+struct IntermediateResult {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lines: Vec<(u32, u64)>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    branches: Vec<(u32, Vec<u64>)>,
+    // The start line, the end line, whether it was executed and the call count.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    functions: BTreeMap<String, (u32, Option<u32>, bool, u64)>,
+    // The start line and column, the end line and column, and the execution count.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    regions: Vec<(u32, u32, u32, u32, u64)>,
+    // The execution counts in each test context, of the lines and of the functions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    line_contexts: Vec<(u32, ContextCounts)>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    function_contexts: BTreeMap<String, ContextCounts>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IntermediateFile {
+    format: String,
+    version: u32,
+    path_mapping: Option<Value>,
+    results: BTreeMap<String, IntermediateResult>,
+}
+
+// The fields identifying the format, the other ones are skipped.
+#[derive(Deserialize)]
+struct IntermediateHeader {
+    format: String,
+    version: u32,
+}
+
+impl From<&CovResult> for IntermediateResult {
+    fn from(result: &CovResult) -> Self {
+        IntermediateResult {
+            lines: result.lines.iter().map(|(&l, &c)| (l, c)).collect(),
+            branches: result
+                .branches
+                .iter()
+                .map(|(&l, counts)| (l, counts.clone()))
+                .collect(),
+            functions: result
+                .functions
+                .iter()
+                .map(|(name, f)| (name.clone(), (f.start, f.end, f.executed, f.count)))
+                .collect(),
+            regions: result
+                .regions
+                .iter()
+                .map(|(span, &c)| {
+                    (
+                        span.start_line,
+                        span.start_col,
+                        span.end_line,
+                        span.end_col,
+                        c,
+                    )
+                })
+                .collect(),
+            line_contexts: result
+                .contexts
+                .lines
+                .iter()
+                .map(|(&l, counts)| (l, counts.clone()))
+                .collect(),
+            function_contexts: result.contexts.functions.clone(),
+        }
+    }
+}
+
+impl From<IntermediateResult> for CovResult {
+    fn from(result: IntermediateResult) -> Self {
+        CovResult {
+            lines: result.lines.into_iter().collect(),
+            branches: result.branches.into_iter().collect(),
+            functions: result
+                .functions
+                .into_iter()
+                .map(|(name, (start, end, executed, count))| {
+                    (
+                        name,
+                        Function {
+                            start,
+                            end,
+                            executed,
+                            count,
+                        },
+                    )
+                })
+                .collect(),
+            regions: result
+                .regions
+                .into_iter()
+                .map(|(start_line, start_col, end_line, end_col, count)| {
+                    (
+                        RegionSpan {
+                            start_line,
+                            start_col,
+                            end_line,
+                            end_col,
+                        },
+                        count,
+                    )
+                })
+                .collect(),
+            contexts: Contexts {
+                lines: result.line_contexts.into_iter().collect(),
+                functions: result.function_contexts,
+            },
+        }
+    }
+}
+
+impl IntermediateCoverage {
+    /// Writes the coverage data as gzip compressed JSON.
+    pub fn write(&self, output: impl Write) -> Result<(), GrcovError> {
+        let file = IntermediateFile {
+            format: INTERMEDIATE_FORMAT_NAME.to_string(),
+            version: INTERMEDIATE_FORMAT_VERSION,
+            path_mapping: self.path_mapping.clone(),
+            results: self
+                .results
+                .iter()
+                .map(|(path, result)| (path.clone(), result.into()))
+                .collect(),
+        };
+
+        let mut encoder = GzEncoder::new(BufWriter::new(output), Compression::default());
+        serde_json::to_writer(&mut encoder, &file)?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    /// Reads coverage data written by `write`, the data of other format versions is rejected.
+    pub fn read(input: impl Read) -> Result<IntermediateCoverage, String> {
+        let mut data = Vec::new();
+        GzDecoder::new(input)
+            .read_to_end(&mut data)
+            .map_err(|e| e.to_string())?;
+
+        // The header is checked first, as the results of other versions might not be readable.
+        let header: IntermediateHeader =
+            serde_json::from_slice(&data).map_err(|_| "Not an intermediate coverage file")?;
+        if header.format != INTERMEDIATE_FORMAT_NAME {
+            return Err("Not an intermediate coverage file".to_string());
+        }
+        if header.version != INTERMEDIATE_FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported intermediate format version {} (expected {})",
+                header.version, INTERMEDIATE_FORMAT_VERSION
+            ));
+        }
+
+        let file: IntermediateFile = serde_json::from_slice(&data).map_err(|e| e.to_string())?;
+        Ok(IntermediateCoverage {
+            results: file
+                .results
+                .into_iter()
+                .map(|(path, result)| (path, result.into()))
+                .collect(),
+            path_mapping: file.path_mapping,
+        })
+    }
+
+    pub fn read_file(path: &Path) -> Result<IntermediateCoverage, GrcovError> {
+        let input_error = |e: String| GrcovError::Input(path.display().to_string(), e);
+        let file = File::open(path).map_err(|e| input_error(e.to_string()))?;
+        IntermediateCoverage::read(BufReader::new(file)).map_err(input_error)
+    }
+
+    /// Merges the results of another intermediate coverage, returns true when an execution count
+    /// overflowed. The first path mapping is kept, like when several are found in the inputs.
+    pub fn merge(&mut self, other: IntermediateCoverage) -> bool {
+        let mut warn_overflow = false;
+        for (path, result) in other.results {
+            match self.results.get_mut(&path) {
+                Some(r) => warn_overflow |= merge_results(r, result),
+                None => {
+                    self.results.insert(path, result);
+                }
+            }
+        }
+        if self.path_mapping.is_none() {
+            self.path_mapping = other.path_mapping;
+        }
+        warn_overflow
+    }
+}
+
+/// Checks whether a path is an intermediate coverage file, from its extension.
+pub fn is_intermediate_file(path: &Path) -> bool {
+    path.is_file() && path.extension().and_then(|e| e.to_str()) == Some(INTERMEDIATE_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHashMap;
+    use serde_json::json;
+
+    fn get_coverage() -> IntermediateCoverage {
+        let mut functions = FxHashMap::default();
+        functions.insert(
+            "main".to_string(),
+            Function {
+                start: 1,
+                end: Some(5),
+                executed: true,
+                count: 2,
+            },
+        );
+        let mut results = FxHashMap::default();
+        results.insert(
+            "/src/main.rs".to_string(),
+            CovResult {
+                lines: [(1, 2), (2, 0)].iter().cloned().collect(),
+                branches: [(2, vec![1, 0])].iter().cloned().collect(),
+                functions,
+                regions: [(
+                    RegionSpan {
+                        start_line: 1,
+                        start_col: 1,
+                        end_line: 5,
+                        end_col: 2,
+                    },
+                    2,
+                )]
+                .iter()
+                .cloned()
+                .collect(),
+                contexts: Contexts::default(),
+            },
+        );
+        crate::set_context(results.get_mut("/src/main.rs").unwrap(), "unit");
+        results.insert("lib.rs".to_string(), CovResult::default());
+        IntermediateCoverage {
+            results,
+            path_mapping: Some(json!({"dist/main.rs": "src/main.rs"})),
+        }
+    }
+
+    #[test]
+    fn test_intermediate_roundtrip() {
+        let coverage = get_coverage();
+        let mut buf = Vec::new();
+        coverage.write(&mut buf).unwrap();
+
+        let read = IntermediateCoverage::read(&buf[..]).unwrap();
+        assert_eq!(read.results, coverage.results);
+        assert_eq!(read.path_mapping, coverage.path_mapping);
+    }
+
+    #[test]
+    fn test_intermediate_merge() {
+        let mut coverage = get_coverage();
+        let mut other = get_coverage();
+        other.path_mapping = None;
+        other
+            .results
+            .insert("other.rs".to_string(), CovResult::default());
+        assert!(!coverage.merge(other));
+
+        assert_eq!(coverage.results.len(), 3);
+        let result = &coverage.results["/src/main.rs"];
+        assert_eq!(result.lines[&1], 4);
+        assert_eq!(result.branches[&2], vec![2, 0]);
+        assert_eq!(result.functions["main"].count, 4);
+        assert_eq!(result.regions.values().next(), Some(&4));
+        assert_eq!(result.contexts.lines[&1]["unit"], 4);
+        assert_eq!(result.contexts.functions["main"]["unit"], 4);
+        assert!(coverage.path_mapping.is_some());
+    }
+
+    #[test]
+    fn test_intermediate_version() {
+        let write = |value: Value| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            serde_json::to_writer(&mut encoder, &value).unwrap();
+            encoder.finish().unwrap()
+        };
+
+        let buf = write(json!({
+            "format": INTERMEDIATE_FORMAT_NAME,
+            "version": INTERMEDIATE_FORMAT_VERSION + 1,
+            "results": {},
+        }));
+        let err = IntermediateCoverage::read(&buf[..]).unwrap_err();
+        assert!(err.starts_with("Unsupported intermediate format version 2"));
+
+        // The version is checked before the results, which might have changed.
+        let buf = write(json!({
+            "format": INTERMEDIATE_FORMAT_NAME,
+            "version": INTERMEDIATE_FORMAT_VERSION + 1,
+            "results": [["main.rs", {"lines": {"1": 2}}]],
+        }));
+        let err = IntermediateCoverage::read(&buf[..]).unwrap_err();
+        assert!(err.starts_with("Unsupported intermediate format version 2"));
+
+        let buf = write(json!({"version": 1}));
+        assert!(IntermediateCoverage::read(&buf[..]).is_err());
+        assert!(IntermediateCoverage::read(&b"not gzip"[..]).is_err());
+    }
+}
@@ -0,0 +1,457 @@
+use quick_xml::{
+    events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event},
+    Writer,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufWriter, Cursor, Write};
+use std::path::Path;
+use symbolic_demangle::DemangleOptions;
+
+use crate::defs::{CovResult, CovResultIter};
+use crate::error::GrcovError;
+use crate::output::get_target_output_writable;
+
+// https://www.jacoco.org/jacoco/trunk/coverage/report.dtd
+
+#[derive(Clone, Copy, Default)]
+struct Counter {
+    missed: usize,
+    covered: usize,
+}
+
+impl Counter {
+    fn add(&mut self, other: Counter) {
+        self.missed += other.missed;
+        self.covered += other.covered;
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Counters {
+    lines: Counter,
+    branches: Counter,
+    methods: Counter,
+    classes: Counter,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.lines.add(other.lines);
+        self.branches.add(other.branches);
+        self.methods.add(other.methods);
+        self.classes.add(other.classes);
+    }
+}
+
+struct Method {
+    name: String,
+    line: u32,
+    counters: Counters,
+}
+
+struct SourceFile {
+    class_name: String,
+    file_name: String,
+    methods: Vec<Method>,
+    // (line number, missed instructions, covered instructions, missed branches, covered branches)
+    lines: Vec<(u32, usize, usize, usize, usize)>,
+    counters: Counters,
+}
+
+fn get_source_file(
+    package: &str,
+    rel_path: &Path,
+    result: CovResult,
+    demangle: bool,
+    demangle_options: DemangleOptions,
+) -> SourceFile {
+    let file_name = rel_path
+        .file_name()
+        .map(|x| x.to_str().unwrap())
+        .unwrap_or_default()
+        .to_string();
+    // The JaCoCo parser expects the top class to be named after the file.
+    let class = file_name.trim_end_matches(".java");
+    let class_name = if package.is_empty() {
+        class.to_string()
+    } else {
+        format!("{}/{}", package, class)
+    };
+
+    let mut lines = Vec::new();
+    let mut counters = Counters::default();
+    // Lines with branches might not have a line count (e.g. coming from JaCoCo).
+    let line_numbers: BTreeSet<u32> = result
+        .lines
+        .keys()
+        .chain(result.branches.keys())
+        .cloned()
+        .collect();
+    for number in line_numbers {
+        let branches = result.branches.get(&number);
+        let hit = match result.lines.get(&number) {
+            Some(&hits) => hits > 0,
+            None => branches.map_or(false, |b| b.iter().any(|&count| count > 0)),
+        };
+        // JaCoCo does not feature execution counts, each line is considered to be a single
+        // instruction.
+        let (mi, ci) = if hit { (0, 1) } else { (1, 0) };
+        let (mb, cb) = branches.map_or((0, 0), |b| {
+            let cb = b.iter().filter(|&&count| count > 0).count();
+            (b.len() - cb, cb)
+        });
+        counters.lines.add(Counter {
+            missed: mi,
+            covered: ci,
+        });
+        counters.branches.add(Counter {
+            missed: mb,
+            covered: cb,
+        });
+        lines.push((number, mi, ci, mb, cb));
+    }
+
+    let end: u32 = lines.last().map_or(0, |l| l.0) + 1;
+    let mut start_indexes: Vec<u32> = result.functions.values().map(|f| f.start).collect();
+    start_indexes.sort_unstable();
+
+    let mut functions: Vec<_> = result.functions.iter().collect();
+    functions.sort_by(|a, b| (a.1.start, a.0).cmp(&(b.1.start, b.0)));
+    let methods = functions
+        .into_iter()
+        .map(|(name, function)| {
+            let func_end = start_indexes
+                .iter()
+                .find(|&&start| start > function.start)
+                .cloned()
+                .unwrap_or(end);
+
+            let mut method_counters = Counters::default();
+            for &(_, mi, ci, _, _) in lines
+                .iter()
+                .filter(|l| l.0 >= function.start && l.0 < func_end)
+            {
+                method_counters.lines.add(Counter {
+                    missed: mi,
+                    covered: ci,
+                });
+            }
+            method_counters.methods = if function.executed {
+                Counter {
+                    missed: 0,
+                    covered: 1,
+                }
+            } else {
+                Counter {
+                    missed: 1,
+                    covered: 0,
+                }
+            };
+            counters.methods.add(method_counters.methods);
+
+            let name = demangle!(name, demangle, demangle_options).to_string();
+            // Functions read from a JaCoCo report are named "Class#method".
+            let name = match name.find('#') {
+                Some(pos) => name[pos + 1..].to_string(),
+                None => name,
+            };
+
+            Method {
+                name,
+                line: function.start,
+                counters: method_counters,
+            }
+        })
+        .collect();
+
+    counters.classes = if counters.lines.covered > 0 || counters.methods.covered > 0 {
+        Counter {
+            missed: 0,
+            covered: 1,
+        }
+    } else {
+        Counter {
+            missed: 1,
+            covered: 0,
+        }
+    };
+
+    SourceFile {
+        class_name,
+        file_name,
+        methods,
+        lines,
+        counters,
+    }
+}
+
+fn write_counters(writer: &mut Writer<Cursor<Vec<u8>>>, counters: &Counters) {
+    let counter_tag = b"counter";
+    for (kind, counter) in &[
+        ("BRANCH", counters.branches),
+        ("LINE", counters.lines),
+        ("METHOD", counters.methods),
+        ("CLASS", counters.classes),
+    ] {
+        // JaCoCo omits the counters for which there is nothing to cover.
+        if counter.missed + counter.covered == 0 {
+            continue;
+        }
+        let mut c = BytesStart::borrowed(counter_tag, counter_tag.len());
+        c.push_attribute(("type", *kind));
+        c.push_attribute(("missed", counter.missed.to_string().as_ref()));
+        c.push_attribute(("covered", counter.covered.to_string().as_ref()));
+        writer.write_event(Event::Empty(c)).unwrap();
+    }
+}
+
+pub fn output_jacoco(
+    results: CovResultIter,
+    output_file: Option<&str>,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    write_jacoco(results, get_target_output_writable(output_file)?, demangle)
+}
+
+pub fn write_jacoco(
+    results: CovResultIter,
+    output: impl Write,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    let demangle_options = DemangleOptions::name_only();
+
+    // Packages are the directories containing the source files.
+    let mut packages: BTreeMap<String, Vec<SourceFile>> = BTreeMap::new();
+    for (_, rel_path, result) in results {
+        // The JaCoCo parser requires every class to have some lines.
+        if result.lines.is_empty() && result.branches.is_empty() {
+            continue;
+        }
+        let package = rel_path
+            .parent()
+            .and_then(|p| p.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let source_file = get_source_file(&package, &rel_path, result, demangle, demangle_options);
+        packages.entry(package).or_default().push(source_file);
+    }
+
+    let mut writer = Writer::new_with_indent(Cursor::new(vec![]), b' ', 4);
+    writer
+        .write_event(Event::Decl(BytesDecl::new(
+            b"1.0",
+            Some(b"UTF-8"),
+            Some(b"yes"),
+        )))
+        .unwrap();
+    writer
+        .write_event(Event::DocType(BytesText::from_escaped_str(
+            r#" report PUBLIC "-//JACOCO//DTD Report 1.1//EN" "report.dtd""#,
+        )))
+        .unwrap();
+
+    let report_tag = b"report";
+    let package_tag = b"package";
+    let class_tag = b"class";
+    let method_tag = b"method";
+    let sourcefile_tag = b"sourcefile";
+    let line_tag = b"line";
+
+    let mut report = BytesStart::borrowed(report_tag, report_tag.len());
+    report.push_attribute(("name", "grcov"));
+    writer.write_event(Event::Start(report)).unwrap();
+
+    let mut report_counters = Counters::default();
+    for (package, source_files) in &packages {
+        let mut pack = BytesStart::borrowed(package_tag, package_tag.len());
+        pack.push_attribute(("name", package.as_ref()));
+        writer.write_event(Event::Start(pack)).unwrap();
+
+        let mut package_counters = Counters::default();
+        for source_file in source_files {
+            let mut c = BytesStart::borrowed(class_tag, class_tag.len());
+            c.push_attribute(("name", source_file.class_name.as_ref()));
+            c.push_attribute(("sourcefilename", source_file.file_name.as_ref()));
+            writer.write_event(Event::Start(c)).unwrap();
+
+            for method in &source_file.methods {
+                let mut m = BytesStart::borrowed(method_tag, method_tag.len());
+                m.push_attribute(("name", method.name.as_ref()));
+                m.push_attribute(("desc", ""));
+                m.push_attribute(("line", method.line.to_string().as_ref()));
+                writer.write_event(Event::Start(m)).unwrap();
+                write_counters(&mut writer, &method.counters);
+                writer
+                    .write_event(Event::End(BytesEnd::borrowed(method_tag)))
+                    .unwrap();
+            }
+            write_counters(&mut writer, &source_file.counters);
+            writer
+                .write_event(Event::End(BytesEnd::borrowed(class_tag)))
+                .unwrap();
+
+            package_counters.add(&source_file.counters);
+        }
+
+        for source_file in source_files {
+            let mut s = BytesStart::borrowed(sourcefile_tag, sourcefile_tag.len());
+            s.push_attribute(("name", source_file.file_name.as_ref()));
+            writer.write_event(Event::Start(s)).unwrap();
+
+            for &(nr, mi, ci, mb, cb) in &source_file.lines {
+                let mut l = BytesStart::borrowed(line_tag, line_tag.len());
+                l.push_attribute(("nr", nr.to_string().as_ref()));
+                l.push_attribute(("mi", mi.to_string().as_ref()));
+                l.push_attribute(("ci", ci.to_string().as_ref()));
+                l.push_attribute(("mb", mb.to_string().as_ref()));
+                l.push_attribute(("cb", cb.to_string().as_ref()));
+                writer.write_event(Event::Empty(l)).unwrap();
+            }
+            write_counters(&mut writer, &source_file.counters);
+            writer
+                .write_event(Event::End(BytesEnd::borrowed(sourcefile_tag)))
+                .unwrap();
+        }
+
+        write_counters(&mut writer, &package_counters);
+        writer
+            .write_event(Event::End(BytesEnd::borrowed(package_tag)))
+            .unwrap();
+
+        report_counters.add(&package_counters);
+    }
+
+    write_counters(&mut writer, &report_counters);
+    writer
+        .write_event(Event::End(BytesEnd::borrowed(report_tag)))
+        .unwrap();
+
+    let result = writer.into_inner().into_inner();
+    let mut file = BufWriter::new(output);
+    file.write_all(&result)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_jacoco_xml_report, Contexts, Function};
+    use rustc_hash::FxHashMap;
+    use std::fs::File;
+    use std::io::{BufReader, Read};
+    use std::path::PathBuf;
+
+    fn get_result() -> CovResult {
+        let mut functions = FxHashMap::default();
+        functions.insert(
+            "Person#<init>".to_string(),
+            Function {
+                start: 3,
+                end: None,
+                executed: true,
+                count: 1,
+            },
+        );
+        functions.insert(
+            "Person#getAge".to_string(),
+            Function {
+                start: 7,
+                end: None,
+                executed: false,
+                count: 0,
+            },
+        );
+        CovResult {
+            lines: [(3, 1), (4, 1), (7, 0), (8, 0)].iter().cloned().collect(),
+            branches: [(5, vec![1, 0]), (9, vec![0, 0])].iter().cloned().collect(),
+            functions,
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
+        }
+    }
+
+    #[test]
+    fn test_jacoco() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_path = tmp_dir.path().join("test_jacoco.xml");
+
+        let results = vec![(
+            PathBuf::from("/src/org/example/Person.java"),
+            PathBuf::from("org/example/Person.java"),
+            get_result(),
+        )];
+
+        output_jacoco(
+            Box::new(results.into_iter()),
+            Some(file_path.to_str().unwrap()),
+            false,
+        )
+        .unwrap();
+
+        let mut results = String::new();
+        File::open(&file_path)
+            .unwrap()
+            .read_to_string(&mut results)
+            .unwrap();
+
+        assert!(results.contains(r#"-//JACOCO//DTD Report 1.1//EN"#));
+        assert!(results.contains(r#"<package name="org/example">"#));
+        assert!(
+            results.contains(r#"<class name="org/example/Person" sourcefilename="Person.java">"#)
+        );
+        assert!(results.contains(r#"<method name="getAge" desc="" line="7">"#));
+        assert!(results.contains(r#"<sourcefile name="Person.java">"#));
+        assert!(results.contains(r#"<line nr="5" mi="0" ci="1" mb="1" cb="1"/>"#));
+        assert!(results.contains(r#"<line nr="7" mi="1" ci="0" mb="0" cb="0"/>"#));
+        assert!(results.contains(r#"<counter type="METHOD" missed="1" covered="1"/>"#));
+        assert!(results.contains(r#"<counter type="BRANCH" missed="3" covered="1"/>"#));
+    }
+
+    #[test]
+    fn test_jacoco_round_trip() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_path = tmp_dir.path().join("test_jacoco_round_trip.xml");
+
+        let results = vec![
+            (
+                PathBuf::from("/src/org/example/Person.java"),
+                PathBuf::from("org/example/Person.java"),
+                get_result(),
+            ),
+            (
+                PathBuf::from("/src/Main.java"),
+                PathBuf::from("Main.java"),
+                CovResult {
+                    lines: [(1, 5)].iter().cloned().collect(),
+                    branches: Default::default(),
+                    functions: FxHashMap::default(),
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
+                },
+            ),
+        ];
+
+        output_jacoco(
+            Box::new(results.into_iter()),
+            Some(file_path.to_str().unwrap()),
+            false,
+        )
+        .unwrap();
+
+        let file = File::open(&file_path).unwrap();
+        let mut parsed = parse_jacoco_xml_report(BufReader::new(file)).unwrap();
+        parsed.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].0, "Main.java");
+        assert_eq!(parsed[0].1.lines, [(1, 1)].iter().cloned().collect());
+
+        let expected = get_result();
+        let (ref name, ref result) = parsed[1];
+        assert_eq!(name, "org/example/Person.java");
+        assert_eq!(result.lines, expected.lines);
+        assert_eq!(result.branches, expected.branches);
+        assert_eq!(result.functions, expected.functions);
+    }
+}
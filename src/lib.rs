@@ -1,9 +1,15 @@
 #![recursion_limit = "1024"]
 #![allow(clippy::too_many_arguments)]
 
+#[macro_use]
+mod macros;
+
 mod defs;
 pub use crate::defs::*;
 
+mod error;
+pub use crate::error::*;
+
 mod producer;
 pub use crate::producer::*;
 
@@ -13,6 +19,12 @@ pub use crate::gcov::*;
 mod llvm_tools;
 pub use crate::llvm_tools::*;
 
+mod profdata;
+pub use crate::profdata::*;
+
+mod covmap;
+pub use crate::covmap::*;
+
 mod parser;
 pub use crate::parser::*;
 
@@ -28,6 +40,12 @@ pub use crate::output::*;
 mod cobertura;
 pub use crate::cobertura::*;
 
+mod sonarqube;
+pub use crate::sonarqube::*;
+
+mod jacoco;
+pub use crate::jacoco::*;
+
 mod reader;
 pub use crate::reader::*;
 
@@ -36,9 +54,41 @@ pub use crate::covdir::*;
 
 pub mod html;
 
+mod highlight;
+
+mod summary;
+pub use crate::summary::*;
+
+mod annotated;
+pub use crate::annotated::*;
+
+mod markdown;
+pub use crate::markdown::*;
+
+mod summary_json;
+pub use crate::summary_json::*;
+
 mod file_filter;
 pub use crate::file_filter::*;
 
+mod threshold;
+pub use crate::threshold::*;
+
+mod patch;
+pub use crate::patch::*;
+
+mod compare;
+pub use crate::compare::*;
+
+mod intermediate;
+pub use crate::intermediate::*;
+
+mod collector;
+pub use crate::collector::*;
+
+mod config;
+pub use crate::config::*;
+
 use log::{error, warn};
 use std::fs;
 use std::io::{BufReader, Cursor};
@@ -67,36 +117,108 @@ pub fn merge_results(result: &mut CovResult, result2: CovResult) -> bool {
         };
     }
 
-    for (line_no, taken) in result2.branches {
+    for (line_no, counts) in result2.branches {
         match result.branches.entry(line_no) {
             btree_map::Entry::Occupied(c) => {
                 let v = c.into_mut();
-                for (x, y) in taken.iter().zip(v.iter_mut()) {
-                    *y |= x;
+                for (x, y) in counts.iter().zip(v.iter_mut()) {
+                    *y = y.checked_add(*x).unwrap_or_else(|| {
+                        warn_overflow = true;
+                        std::u64::MAX
+                    });
                 }
                 let l = v.len();
-                if taken.len() > l {
-                    v.extend(&taken[l..]);
+                if counts.len() > l {
+                    v.extend(&counts[l..]);
                 }
             }
             btree_map::Entry::Vacant(v) => {
-                v.insert(taken);
+                v.insert(counts);
             }
         };
     }
 
     for (name, function) in result2.functions {
         match result.functions.entry(name) {
-            hash_map::Entry::Occupied(f) => f.into_mut().executed |= function.executed,
+            hash_map::Entry::Occupied(f) => {
+                let f = f.into_mut();
+                f.executed |= function.executed;
+                f.count = f.count.checked_add(function.count).unwrap_or_else(|| {
+                    warn_overflow = true;
+                    std::u64::MAX
+                });
+                f.end = f.end.or(function.end);
+            }
             hash_map::Entry::Vacant(v) => {
                 v.insert(function);
             }
         };
     }
 
+    for (span, execution_count) in result2.regions {
+        match result.regions.entry(span) {
+            btree_map::Entry::Occupied(c) => {
+                let v = c.get().checked_add(execution_count).unwrap_or_else(|| {
+                    warn_overflow = true;
+                    std::u64::MAX
+                });
+
+                *c.into_mut() = v;
+            }
+            btree_map::Entry::Vacant(v) => {
+                v.insert(execution_count);
+            }
+        };
+    }
+
+    for (line_no, counts) in result2.contexts.lines {
+        let c = result.contexts.lines.entry(line_no).or_default();
+        warn_overflow |= merge_context_counts(c, counts);
+    }
+    for (name, counts) in result2.contexts.functions {
+        let c = result.contexts.functions.entry(name).or_default();
+        warn_overflow |= merge_context_counts(c, counts);
+    }
+
     warn_overflow
 }
 
+fn merge_context_counts(counts: &mut ContextCounts, counts2: ContextCounts) -> bool {
+    let mut warn_overflow = false;
+    for (label, count) in counts2 {
+        let c = counts.entry(label).or_insert(0);
+        *c = c.checked_add(count).unwrap_or_else(|| {
+            warn_overflow = true;
+            std::u64::MAX
+        });
+    }
+    warn_overflow
+}
+
+/// Labels the coverage of a result with the given test context, replacing its other contexts.
+pub fn set_context(result: &mut CovResult, label: &str) {
+    result.contexts.lines = result
+        .lines
+        .iter()
+        .filter(|&(_, &count)| count > 0)
+        .map(|(&line_no, &count)| {
+            (
+                line_no,
+                vec![(label.to_string(), count)].into_iter().collect(),
+            )
+        })
+        .collect();
+    result.contexts.functions = result
+        .functions
+        .iter()
+        .filter(|(_, function)| function.executed)
+        .map(|(name, function)| {
+            let counts = vec![(label.to_string(), function.count.max(1))];
+            (name.clone(), counts.into_iter().collect())
+        })
+        .collect();
+}
+
 fn add_results(
     mut results: Vec<(String, CovResult)>,
     result_map: &SyncCovResultMap,
@@ -154,11 +276,11 @@ enum GcovType {
 }
 
 macro_rules! try_parse {
-    ($v:expr, $f:expr) => {
+    ($v:expr, $f:expr, $errors:expr) => {
         match $v {
             Ok(val) => val,
             Err(err) => {
-                error!("Error parsing file {}: {}", $f, err);
+                $errors.push(GrcovError::Parse($f.to_string(), err));
                 continue;
             }
         }
@@ -173,8 +295,9 @@ pub fn consumer(
     branch_enabled: bool,
     guess_directory: bool,
     binary_path: Option<&Path>,
-) {
+) -> Vec<GrcovError> {
     let mut gcov_type = GcovType::Unknown;
+    let mut errors = Vec::new();
 
     while let Ok(work_item) = receiver.recv() {
         if work_item.is_none() {
@@ -187,7 +310,10 @@ pub fn consumer(
                     ItemType::Path((stem, gcno_path)) => {
                         // GCC
                         if let Err(e) = run_gcov(&gcno_path, branch_enabled, working_dir) {
-                            error!("Error when running gcov: {}", e);
+                            errors.push(GrcovError::Tool(
+                                work_item.name,
+                                format!("Error when running gcov: {}", e),
+                            ));
                             continue;
                         };
                         let gcov_ext = get_gcov_output_ext();
@@ -209,29 +335,56 @@ pub fn consumer(
                                 } else if gcov_ext.ends_with("gcov") {
                                     parse_gcov(&gcov_path)
                                 } else {
-                                    panic!("Invalid gcov extension: {}", gcov_ext);
+                                    Err(ParserError::InvalidData(format!(
+                                        "Invalid gcov extension: {}",
+                                        gcov_ext
+                                    )))
                                 },
-                                work_item.name
+                                work_item.name,
+                                errors
                             );
-                            fs::remove_file(gcov_path).unwrap();
+                            if let Err(e) = fs::remove_file(&gcov_path) {
+                                errors.push(GrcovError::Tool(
+                                    work_item.name,
+                                    format!("Cannot remove {}: {}", gcov_path.display(), e),
+                                ));
+                                continue;
+                            }
                             new_results
                         } else {
                             let mut new_results: Vec<(String, CovResult)> = Vec::new();
 
                             for entry in WalkDir::new(&working_dir).min_depth(1) {
-                                let gcov_path = entry.unwrap();
-                                let gcov_path = gcov_path.path();
+                                let entry = match entry {
+                                    Ok(entry) => entry,
+                                    Err(e) => {
+                                        errors.push(GrcovError::Tool(
+                                            work_item.name.clone(),
+                                            format!("Cannot read the gcov output: {}", e),
+                                        ));
+                                        continue;
+                                    }
+                                };
+                                let gcov_path = entry.path();
 
                                 new_results.append(&mut try_parse!(
-                                    if gcov_path.extension().unwrap() == "gz" {
+                                    if gcov_path.extension().and_then(|ext| ext.to_str())
+                                        == Some("gz")
+                                    {
                                         parse_gcov_gz(gcov_path)
                                     } else {
                                         parse_gcov(gcov_path)
                                     },
-                                    work_item.name
+                                    work_item.name,
+                                    errors
                                 ));
 
-                                fs::remove_file(gcov_path).unwrap();
+                                if let Err(e) = fs::remove_file(gcov_path) {
+                                    errors.push(GrcovError::Tool(
+                                        work_item.name.clone(),
+                                        format!("Cannot remove {}: {}", gcov_path.display(), e),
+                                    ));
+                                }
                             }
 
                             new_results
@@ -257,9 +410,11 @@ pub fn consumer(
                                 r
                             }
                             Err(e) => {
-                                // Just print the error, don't panic and continue
-                                error!("Error in computing counters: {}", e);
-                                Vec::new()
+                                errors.push(GrcovError::Tool(
+                                    work_item.name,
+                                    format!("Error in computing counters: {}", e),
+                                ));
+                                continue;
                             }
                         }
                     }
@@ -275,30 +430,31 @@ pub fn consumer(
             }
             ItemFormat::Profraw => {
                 if binary_path.is_none() {
-                    error!("The path to the compiled binary must be given as an argument when source-based coverage is used");
+                    errors.push(GrcovError::Tool(
+                        work_item.name,
+                        "The path to the compiled binary must be given as an argument when source-based coverage is used".to_string(),
+                    ));
                     continue;
                 }
 
                 if let ItemType::Paths(profraw_paths) = work_item.item {
-                    match llvm_tools::profraws_to_lcov(
+                    match llvm_tools::profraws_to_results(
                         profraw_paths.as_slice(),
                         binary_path.as_ref().unwrap(),
                         working_dir,
+                        branch_enabled,
                     ) {
-                        Ok(lcovs) => {
-                            let mut new_results: Vec<(String, CovResult)> = Vec::new();
-
-                            for lcov in lcovs {
-                                new_results.append(&mut try_parse!(
-                                    parse_lcov(lcov, branch_enabled),
-                                    work_item.name
-                                ));
+                        Ok((new_results, binary_errors)) => {
+                            for e in binary_errors {
+                                errors.push(GrcovError::Tool(work_item.name.clone(), e));
                             }
-
                             new_results
                         }
                         Err(e) => {
-                            error!("Error while executing llvm tools: {}", e);
+                            errors.push(GrcovError::Tool(
+                                work_item.name,
+                                format!("Error while executing llvm tools: {}", e),
+                            ));
                             continue;
                         }
                     }
@@ -307,13 +463,19 @@ pub fn consumer(
                     continue;
                 }
             }
-            ItemFormat::Info | ItemFormat::JacocoXml => {
+            ItemFormat::Info | ItemFormat::JacocoXml | ItemFormat::LlvmJson => {
                 if let ItemType::Content(content) = work_item.item {
                     if work_item.format == ItemFormat::Info {
-                        try_parse!(parse_lcov(content, branch_enabled), work_item.name)
+                        try_parse!(parse_lcov(content, branch_enabled), work_item.name, errors)
+                    } else if work_item.format == ItemFormat::LlvmJson {
+                        try_parse!(
+                            parse_llvm_cov_json(&content, branch_enabled),
+                            work_item.name,
+                            errors
+                        )
                     } else {
                         let buffer = BufReader::new(Cursor::new(content));
-                        try_parse!(parse_jacoco_xml_report(buffer), work_item.name)
+                        try_parse!(parse_jacoco_xml_report(buffer), work_item.name, errors)
                     }
                 } else {
                     error!("Invalid content type");
@@ -324,12 +486,15 @@ pub fn consumer(
 
         add_results(new_results, result_map, source_dir);
     }
+
+    errors
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use rustc_hash::FxHashMap;
+    use std::collections::BTreeMap;
     use std::fs::File;
     use std::io::Read;
     use std::sync::{Arc, Mutex};
@@ -341,41 +506,47 @@ mod tests {
             "f1".to_string(),
             Function {
                 start: 1,
+                end: None,
                 executed: false,
+                count: 0,
             },
         );
         functions1.insert(
             "f2".to_string(),
             Function {
                 start: 2,
+                end: None,
                 executed: false,
+                count: 0,
             },
         );
         let mut result = CovResult {
             lines: [(1, 21), (2, 7), (7, 0)].iter().cloned().collect(),
-            branches: [
-                (1, vec![false, false]),
-                (2, vec![false, true]),
-                (4, vec![true]),
-            ]
-            .iter()
-            .cloned()
-            .collect(),
+            branches: [(1, vec![0, 0]), (2, vec![0, 3]), (4, vec![5])]
+                .iter()
+                .cloned()
+                .collect(),
             functions: functions1,
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
         };
         let mut functions2: FunctionMap = FxHashMap::default();
         functions2.insert(
             "f1".to_string(),
             Function {
                 start: 1,
+                end: None,
                 executed: false,
+                count: 0,
             },
         );
         functions2.insert(
             "f2".to_string(),
             Function {
                 start: 2,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
         let result2 = CovResult {
@@ -383,15 +554,13 @@ mod tests {
                 .iter()
                 .cloned()
                 .collect(),
-            branches: [
-                (1, vec![false, false]),
-                (2, vec![false, true]),
-                (3, vec![true]),
-            ]
-            .iter()
-            .cloned()
-            .collect(),
+            branches: [(1, vec![0, 0, 2]), (2, vec![0, 4]), (3, vec![1])]
+                .iter()
+                .cloned()
+                .collect(),
             functions: functions2,
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
         };
 
         merge_results(&mut result, result2);
@@ -405,10 +574,10 @@ mod tests {
         assert_eq!(
             result.branches,
             [
-                (1, vec![false, false]),
-                (2, vec![false, true]),
-                (3, vec![true]),
-                (4, vec![true]),
+                (1, vec![0, 0, 2]),
+                (2, vec![0, 7]),
+                (3, vec![1]),
+                (4, vec![5]),
             ]
             .iter()
             .cloned()
@@ -424,6 +593,88 @@ mod tests {
         assert!(func.executed);
     }
 
+    #[test]
+    fn test_merge_results_regions() {
+        let span = |start_line, start_col, end_line, end_col| RegionSpan {
+            start_line,
+            start_col,
+            end_line,
+            end_col,
+        };
+        let mut result = CovResult {
+            lines: [(1, 1), (2, 1)].iter().cloned().collect(),
+            branches: BTreeMap::new(),
+            functions: FxHashMap::default(),
+            regions: [(span(1, 1, 3, 2), 1), (span(2, 5, 2, 10), 0)]
+                .iter()
+                .cloned()
+                .collect(),
+            contexts: Contexts::default(),
+        };
+        let result2 = CovResult {
+            lines: [(1, 2), (2, 2)].iter().cloned().collect(),
+            branches: BTreeMap::new(),
+            functions: FxHashMap::default(),
+            regions: [
+                (span(1, 1, 3, 2), 2),
+                (span(2, 5, 2, 10), 0),
+                (span(2, 12, 2, 20), 2),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            contexts: Contexts::default(),
+        };
+
+        assert!(!merge_results(&mut result, result2));
+        assert_eq!(
+            result.regions,
+            [
+                (span(1, 1, 3, 2), 3),
+                (span(2, 5, 2, 10), 0),
+                (span(2, 12, 2, 20), 2),
+            ]
+            .iter()
+            .cloned()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn test_merge_results_contexts() {
+        let mut result = CovResult {
+            lines: [(1, 1), (2, 0)].iter().cloned().collect(),
+            ..Default::default()
+        };
+        set_context(&mut result, "a");
+        let mut result2 = CovResult {
+            lines: [(1, 2), (2, 3)].iter().cloned().collect(),
+            ..Default::default()
+        };
+        set_context(&mut result2, "b");
+        let mut result3 = result2.clone();
+        set_context(&mut result3, "a");
+
+        assert!(!merge_results(&mut result, result2));
+        assert!(!merge_results(&mut result, result3));
+        assert_eq!(result.lines, [(1, 5), (2, 6)].iter().cloned().collect());
+        assert_eq!(
+            result.contexts.lines[&1],
+            [("a".to_string(), 3), ("b".to_string(), 2)]
+                .iter()
+                .cloned()
+                .collect()
+        );
+        assert_eq!(
+            result.contexts.lines[&2],
+            [("a".to_string(), 3), ("b".to_string(), 3)]
+                .iter()
+                .cloned()
+                .collect()
+        );
+        assert_eq!(result.contexts.labels().len(), 2);
+    }
+
     #[test]
     fn test_merge_relative_path() {
         let mut f = File::open("./test/relative_path/relative_path.info")
@@ -1,11 +1,17 @@
 use cargo_binutils::Tool;
 use is_executable::IsExecutable;
+use log::{debug, warn};
 use std::ffi::OsStr;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use walkdir::WalkDir;
 
+use crate::covmap::{get_coverage, parse_llvm_cov_json};
+use crate::defs::CovResult;
+use crate::profdata::IndexedProfile;
+
 pub fn run(cmd: impl AsRef<OsStr>, args: &[&OsStr]) -> Result<Vec<u8>, String> {
     let mut command = Command::new(cmd);
     command.args(args);
@@ -24,11 +30,7 @@ pub fn run(cmd: impl AsRef<OsStr>, args: &[&OsStr]) -> Result<Vec<u8>, String> {
     Ok(output.stdout)
 }
 
-pub fn profraws_to_lcov(
-    profraw_paths: &[PathBuf],
-    binary_path: &Path,
-    working_dir: &Path,
-) -> Result<Vec<Vec<u8>>, String> {
+fn merge_profraws(profraw_paths: &[PathBuf], working_dir: &Path) -> Result<PathBuf, String> {
     let profdata_path = working_dir.join("grcov.profdata");
 
     let mut args = vec![
@@ -40,7 +42,11 @@ pub fn profraws_to_lcov(
     args.splice(2..2, profraw_paths.iter().map(PathBuf::as_ref));
     run(&Tool::Profdata.path().unwrap(), &args)?;
 
-    let binaries = if binary_path.is_file() {
+    Ok(profdata_path)
+}
+
+fn get_binaries(binary_path: &Path) -> Vec<PathBuf> {
+    if binary_path.is_file() {
         vec![binary_path.to_owned()]
     } else {
         let mut paths = vec![];
@@ -55,22 +61,33 @@ pub fn profraws_to_lcov(
         }
 
         paths
-    };
+    }
+}
+
+fn export(binary: &Path, profdata_path: &Path, format: &str) -> Result<Vec<u8>, String> {
+    let args = [
+        "export".as_ref(),
+        binary.as_ref(),
+        "--instr-profile".as_ref(),
+        profdata_path.as_ref(),
+        "--format".as_ref(),
+        format.as_ref(),
+    ];
+
+    run(&Tool::Cov.path().unwrap(), &args)
+}
+
+pub fn profraws_to_lcov(
+    profraw_paths: &[PathBuf],
+    binary_path: &Path,
+    working_dir: &Path,
+) -> Result<Vec<Vec<u8>>, String> {
+    let profdata_path = merge_profraws(profraw_paths, working_dir)?;
 
     let mut results = vec![];
-    let cov_tool_path = Tool::Cov.path().unwrap();
-
-    for binary in binaries {
-        let args = [
-            "export".as_ref(),
-            binary.as_ref(),
-            "--instr-profile".as_ref(),
-            profdata_path.as_ref(),
-            "--format".as_ref(),
-            "lcov".as_ref(),
-        ];
-
-        if let Ok(result) = run(&cov_tool_path, &args) {
+
+    for binary in get_binaries(binary_path) {
+        if let Ok(result) = export(&binary, &profdata_path, "lcov") {
             results.push(result);
         }
     }
@@ -78,9 +95,190 @@ pub fn profraws_to_lcov(
     Ok(results)
 }
 
+fn is_profdata(path: &Path) -> bool {
+    path.extension().and_then(OsStr::to_str) == Some("profdata")
+}
+
+/// Returns whether the file is an object file llvm-cov can read (ELF, Mach-O or PE/COFF).
+fn is_object_file(data: &[u8]) -> bool {
+    const MAGICS: [&[u8]; 7] = [
+        b"\x7fELF",
+        b"MZ",
+        b"\xfe\xed\xfa\xce",
+        b"\xce\xfa\xed\xfe",
+        b"\xfe\xed\xfa\xcf",
+        b"\xcf\xfa\xed\xfe",
+        b"\xca\xfe\xba\xbe",
+    ];
+    MAGICS.iter().any(|magic| data.starts_with(magic))
+}
+
+fn read_profile(path: &Path) -> Result<IndexedProfile, String> {
+    fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| IndexedProfile::parse(&data))
+        .map_err(|e| format!("Cannot read the profile {:?}: {}", path, e))
+}
+
+/// The results of the binaries whose coverage was read, and the errors of the other binaries.
+pub type BinaryResults = (Vec<(String, CovResult)>, Vec<String>);
+
+/// Reads the coverage mapping of the binaries and the profiles natively, falling back to
+/// `llvm-cov export` for the binaries we can't read (e.g. when they are not ELF files).
+///
+/// The `.profdata` files are merged natively, while `llvm-profdata` is needed as soon as there
+/// is a `.profraw` file. Besides the results, the errors of the binaries whose coverage couldn't
+/// be read at all are returned.
+pub fn profraws_to_results(
+    profraw_paths: &[PathBuf],
+    binary_path: &Path,
+    working_dir: &Path,
+    branch_enabled: bool,
+) -> Result<BinaryResults, String> {
+    let mut profdata_path = if !profraw_paths.iter().all(|path| is_profdata(path)) {
+        Some(merge_profraws(profraw_paths, working_dir)?)
+    } else if let [path] = profraw_paths {
+        Some(path.clone())
+    } else {
+        None
+    };
+    let profile = match profdata_path {
+        Some(ref path) => read_profile(path),
+        None => profraw_paths
+            .iter()
+            .try_fold(IndexedProfile::default(), |mut profile, path| {
+                profile.merge(read_profile(path)?);
+                Ok(profile)
+            }),
+    };
+
+    let mut results = vec![];
+    let mut errors = vec![];
+
+    for binary in get_binaries(binary_path) {
+        let data = match fs::read(&binary) {
+            Ok(data) => data,
+            Err(e) => {
+                errors.push(format!("Cannot read {:?}: {}", binary, e));
+                continue;
+            }
+        };
+        let native_results = profile
+            .as_ref()
+            .map_err(String::clone)
+            .and_then(|profile| get_coverage(&data, profile, branch_enabled));
+
+        let e = match native_results {
+            Ok(mut new_results) => {
+                results.append(&mut new_results);
+                continue;
+            }
+            Err(e) => e,
+        };
+        if !is_object_file(&data) {
+            debug!("Skipping {:?}, as it isn't an object file", binary);
+            continue;
+        }
+        warn!(
+            "Falling back to llvm-cov for {:?}, as its coverage can't be read: {}",
+            binary, e
+        );
+
+        // llvm-cov needs a single indexed profile.
+        let path = match profdata_path {
+            Some(ref path) => path.clone(),
+            None => match merge_profraws(profraw_paths, working_dir) {
+                Ok(path) => profdata_path.insert(path).clone(),
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            },
+        };
+        // The JSON export also contains the regions, unlike the lcov one.
+        match export(&binary, &path, "text")
+            .and_then(|json| parse_llvm_cov_json(&json, branch_enabled).map_err(|e| e.to_string()))
+        {
+            Ok(mut new_results) => results.append(&mut new_results),
+            Err(e) => errors.push(format!(
+                "Cannot export the coverage of {:?} with llvm-cov: {}",
+                binary, e
+            )),
+        }
+    }
+
+    Ok((results, errors))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_profdata_to_results() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let expected = crate::parse_lcov(fs::read("test/llvm-cov/sample.info").unwrap(), true)
+            .unwrap()
+            .into_iter()
+            .map(|(name, result)| (name, result.lines))
+            .collect::<Vec<_>>();
+
+        // The indexed profiles are read without llvm-profdata.
+        let (results, errors) = profraws_to_results(
+            &[PathBuf::from("test/llvm-cov/sample.profdata")],
+            Path::new("test/llvm-cov/sample"),
+            tmp_dir.path(),
+            true,
+        )
+        .unwrap();
+        assert!(errors.is_empty());
+        let lines = results
+            .into_iter()
+            .map(|(name, result)| (name, result.lines))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, expected);
+
+        let (results, errors) = profraws_to_results(
+            &[
+                PathBuf::from("test/llvm-cov/sample.profdata"),
+                PathBuf::from("test/llvm-cov/sample.profdata"),
+            ],
+            Path::new("test/llvm-cov/sample"),
+            tmp_dir.path(),
+            true,
+        )
+        .unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(results.len(), expected.len());
+        for ((_, result), (_, expected_lines)) in results.iter().zip(&expected) {
+            let doubled = expected_lines
+                .iter()
+                .map(|(line, count)| (*line, count * 2))
+                .collect::<BTreeMap<_, _>>();
+            assert_eq!(result.lines, doubled);
+        }
+
+        // The files which aren't object files are skipped.
+        let (results, errors) = profraws_to_results(
+            &[PathBuf::from("test/llvm-cov/sample.profdata")],
+            Path::new("test/llvm-cov/main.rs"),
+            tmp_dir.path(),
+            true,
+        )
+        .unwrap();
+        assert!(results.is_empty());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_is_object_file() {
+        assert!(is_object_file(&fs::read("test/llvm-cov/sample").unwrap()));
+        assert!(is_object_file(b"\xcf\xfa\xed\xfe\x07\x00\x00\x01"));
+        assert!(is_object_file(b"MZ\x90\x00"));
+        assert!(!is_object_file(b"#!/bin/sh"));
+        assert!(!is_object_file(b""));
+    }
 
     #[test]
     fn test_profraws_to_lcov() {
@@ -0,0 +1,17 @@
+/// Returns the demangled name of a function as a `StringOrRef`, or the name itself when
+/// demangling is disabled or fails.
+macro_rules! demangle {
+    ($name: expr, $demangle: expr, $options: expr) => {{
+        if $demangle {
+            match ::symbolic_demangle::Demangle::demangle(
+                &::symbolic_common::Name::from($name),
+                $options,
+            ) {
+                Some(name) => $crate::defs::StringOrRef::S(name),
+                None => $crate::defs::StringOrRef::R($name),
+            }
+        } else {
+            $crate::defs::StringOrRef::R($name)
+        }
+    }};
+}
@@ -2,32 +2,163 @@
 #[global_allocator]
 static GLOBAL: tcmalloc::TCMalloc = tcmalloc::TCMalloc;
 
-use clap::{crate_authors, crate_version, App, Arg, ArgGroup};
-use crossbeam::channel::bounded;
+use clap::{crate_authors, crate_version, App, AppSettings, Arg, SubCommand};
 use log::error;
-use rustc_hash::FxHashMap;
-use serde_json::Value;
 use simplelog::{ColorChoice, Config, LevelFilter, TermLogger, TerminalMode, WriteLogger};
-use std::fs::{self, File};
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader};
 use std::ops::Deref;
 use std::panic;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
-use std::{process, thread};
+use std::path::{Path, PathBuf};
+use std::process;
 
 use grcov::*;
 
+/// The options which can be set in a configuration file, by long flag, with the name of their
+/// argument.
+const CONFIG_OPTIONS: &[(&str, &str)] = &[
+    ("paths", "paths"),
+    ("binary-path", "binary_path"),
+    ("output-type", "output_type"),
+    ("output-path", "output_path"),
+    ("source-dir", "source_dir"),
+    ("prefix-dir", "prefix_dir"),
+    ("ignore-not-existing", "ignore_not_existing"),
+    ("ignore", "ignore_dir"),
+    ("keep-only", "keep_dir"),
+    ("path-mapping", "path_mapping"),
+    ("branch", "branch"),
+    ("filter", "filter"),
+    ("llvm", "llvm"),
+    ("token", "token"),
+    ("commit-sha", "commit_sha"),
+    ("service-name", "service_name"),
+    ("service-number", "service_number"),
+    ("service-job-id", "service_job_id"),
+    ("service-pull-request", "service_pull_request"),
+    ("parallel", "parallel"),
+    ("threads", "threads"),
+    ("guess-directory-when-missing", "guess_directory"),
+    ("vcs-branch", "vcs_branch"),
+    ("log", "log"),
+    ("excl-line", "excl-line"),
+    ("excl-start", "excl-start"),
+    ("excl-stop", "excl-stop"),
+    ("excl-br-line", "excl-br-line"),
+    ("excl-br-start", "excl-br-start"),
+    ("excl-br-stop", "excl-br-stop"),
+    ("no-demangle", "no-demangle"),
+    ("patch", "patch"),
+    ("strict", "strict"),
+    ("context", "context"),
+    ("fail-under-lines", "fail_under_lines"),
+    ("fail-under-functions", "fail_under_functions"),
+    ("fail-under-branches", "fail_under_branches"),
+    ("fail-under-file-lines", "fail_under_file_lines"),
+    ("fail-under-file-functions", "fail_under_file_functions"),
+    ("fail-under-file-branches", "fail_under_file_branches"),
+    ("html-template-dir", "html_template_dir"),
+    ("html-hi-limit", "html_hi_limit"),
+    ("html-med-limit", "html_med_limit"),
+    ("html-fn-hi-limit", "html_fn_hi_limit"),
+    ("html-fn-med-limit", "html_fn_med_limit"),
+    ("html-branch-hi-limit", "html_branch_hi_limit"),
+    ("html-branch-med-limit", "html_branch_med_limit"),
+    ("summary-sort", "summary_sort"),
+    ("summary-color", "summary_color"),
+    ("markdown-details", "markdown_details"),
+    ("markdown-size-limit", "markdown_size_limit"),
+];
+
+/// Returns the command line arguments completed with the options of the configuration file
+/// which weren't given on the command line.
+fn get_config_args(
+    args: &[OsString],
+    matches: &clap::ArgMatches,
+) -> Result<Option<Vec<OsString>>, GrcovError> {
+    let config = match matches.value_of("config") {
+        Some(path) => Some(ProjectConfig::load(Path::new(path))?.ok_or_else(|| {
+            GrcovError::Input(path.to_string(), "No grcov options found".to_string())
+        })?),
+        None => {
+            let mut dirs = vec![Path::new(".")];
+            if let Some(source_dir) = matches.value_of("source_dir") {
+                dirs.push(Path::new(source_dir));
+            }
+            ProjectConfig::find(&dirs)?
+        }
+    };
+    let config = match config {
+        Some(config) => config,
+        None if matches.is_present("profile") => {
+            return Err(GrcovError::Other(
+                "A profile was given, but no configuration file was found".to_string(),
+            ));
+        }
+        None => return Ok(None),
+    };
+
+    let mut options = Vec::new();
+    let mut paths = Vec::new();
+    for (name, value) in config.options(matches.value_of("profile"))? {
+        let arg = CONFIG_OPTIONS
+            .iter()
+            .find(|(option, _)| *option == name)
+            .map(|(_, arg)| *arg)
+            .ok_or_else(|| {
+                GrcovError::Input(
+                    config.path.display().to_string(),
+                    format!("Unknown option '{}'", name),
+                )
+            })?;
+        // The output paths go with the output types, so neither is taken from the configuration
+        // when one of them is given on the command line.
+        let is_given = |arg| matches.occurrences_of(arg) > 0;
+        if is_given(arg)
+            || (arg == "output_type" && is_given("output_path"))
+            || (arg == "output_path" && is_given("output_type"))
+        {
+            continue;
+        }
+
+        if arg == "paths" {
+            paths.extend(match value {
+                ConfigValue::Value(path) => vec![path],
+                ConfigValue::Values(values) => values,
+                ConfigValue::Flag(_) => Vec::new(),
+            });
+        } else {
+            options.extend(value.to_args(&name));
+        }
+    }
+
+    let mut config_args = vec![args[0].clone()];
+    config_args.extend(options.into_iter().map(OsString::from));
+    config_args.extend(args[1..].iter().cloned());
+    config_args.extend(paths.into_iter().map(OsString::from));
+    Ok(Some(config_args))
+}
+
+fn is_percentage(value: String) -> Result<(), String> {
+    match value.parse::<f64>() {
+        Ok(percentage) if (0.0..=100.0).contains(&percentage) => Ok(()),
+        _ => Err(format!("'{}' isn't a percentage between 0 and 100", value)),
+    }
+}
+
 fn main() {
     let default_num_threads = 1.max(num_cpus::get() - 1).to_string();
 
-    let matches = App::new("grcov")
+    let app = App::new("grcov")
                           .version(crate_version!())
                           .author(crate_authors!("\n"))
                           .about("Parse, collect and aggregate code coverage data for multiple source files")
+                          .setting(AppSettings::SubcommandsNegateReqs)
 
                           .arg(Arg::with_name("paths")
                                .help("Sets the input paths to use")
-                               .required(true)
                                .multiple(true)
                                .takes_value(true))
 
@@ -43,30 +174,45 @@ fn main() {
                                .long_help(
 "Sets a custom output type:
 - *html* for a HTML coverage report;
+- *html-single* for a HTML coverage report in a single self-contained file;
 - *coveralls* for the Coveralls specific format;
 - *lcov* for the lcov INFO format;
 - *covdir* for the covdir recursive JSON format;
 - *coveralls+* for the Coveralls specific format with function information;
 - *ade* for the ActiveData-ETL specific format;
+- *cobertura* for a cobertura coverage report;
+- *sonar* for the SonarQube generic test coverage format;
+- *jacoco* for a JaCoCo XML report;
+- *annotated* for the source files annotated with the execution counts of their lines, in the gcov \
+text format, in a directory mirroring the source tree or to stdout when no output path is given;
+- *summary-json* for the line, function and branch stats of every directory, file and function in \
+JSON, with a versioned schema;
+- *markdown* for a summary of the coverage in markdown, for pull request comments or CI job summaries;
+- *summary* for a table of the coverage of the files and directories, to read from a terminal;
+- *patch* for a summary of the coverage of the lines changed by the patch given with --patch;
+- *intermediate* for a file of the aggregated coverage data, to merge with other ones by giving \
+them as input paths;
 - *files* to only return a list of files.
+Several output types can be given, separated by commas (e.g. 'lcov,html'), in which case \
+an output path has to be given for each of them, in the same order.
 ")
                                .short("t")
                                .long("output-type")
                                .value_name("OUTPUT TYPE")
                                .default_value("lcov")
-                               .possible_values(&["ade", "lcov", "coveralls", "coveralls+", "files", "covdir", "html", "cobertura"])
+                               .possible_values(&["ade", "lcov", "coveralls", "coveralls+", "files", "covdir", "html", "html-single", "summary", "summary-json", "markdown", "annotated", "cobertura", "sonar", "jacoco", "patch", "intermediate"])
                                .takes_value(true)
-                               .requires_ifs(&[
-                                   ("coveralls", "coveralls_auth"),
-                                   ("coveralls+", "coveralls_auth")
-                               ]))
+                               .multiple(true)
+                               .require_delimiter(true))
 
                           .arg(Arg::with_name("output_path")
-                               .help("Specifies the output path")
+                               .help("Specifies the output path, once for each output type")
                                .short("o")
                                .long("output-path")
                                .alias("output-file")
                                .value_name("PATH")
+                               .multiple(true)
+                               .number_of_values(1)
                                .takes_value(true))
 
                           .arg(Arg::with_name("source_dir")
@@ -87,6 +233,11 @@ fn main() {
                                .help("Ignore source files that can't be found on the disk")
                                .long("ignore-not-existing"))
 
+                          .arg(Arg::with_name("no_ignore_not_existing")
+                               .help("Doesn't ignore the source files that can't be found on the disk, e.g. when set in the configuration file")
+                               .long("no-ignore-not-existing")
+                               .overrides_with("ignore_not_existing"))
+
                           .arg(Arg::with_name("ignore_dir")
                                .help("Ignore files/directories specified as globs")
                                .long("ignore")
@@ -114,6 +265,11 @@ fn main() {
                                .help("Enables parsing branch coverage information")
                                .long("branch"))
 
+                          .arg(Arg::with_name("no_branch")
+                               .help("Disables parsing branch coverage information, e.g. when enabled in the configuration file")
+                               .long("no-branch")
+                               .overrides_with("branch"))
+
                           .arg(Arg::with_name("filter")
                                .help("Filters out covered/uncovered files. Use 'covered' to only return covered files, 'uncovered' to only return uncovered files")
                                .long("filter")
@@ -124,6 +280,11 @@ fn main() {
                                .help("Speeds-up parsing, when the code coverage information is exclusively coming from a llvm build")
                                .long("llvm"))
 
+                          .arg(Arg::with_name("no_llvm")
+                               .help("Doesn't assume the code coverage information is exclusively coming from a llvm build")
+                               .long("no-llvm")
+                               .overrides_with("llvm"))
+
                           .arg(Arg::with_name("token")
                                .help("Sets the repository token from Coveralls, required for the 'coveralls' and 'coveralls+' formats")
                                .long("token")
@@ -153,8 +314,7 @@ fn main() {
                                .long("service-job-id")
                                .value_name("SERVICE JOB ID")
                                .takes_value(true)
-                               .visible_alias("service-job-number")
-                               .requires("service_name"))
+                               .visible_alias("service-job-number"))
 
                           .arg(Arg::with_name("service_pull_request")
                                .help("Sets the service pull request number")
@@ -166,6 +326,11 @@ fn main() {
                                .help("Sets the build type to be parallel for 'coveralls' and 'coveralls+' formats")
                                .long("parallel"))
 
+                          .arg(Arg::with_name("no_parallel")
+                               .help("Doesn't set the build type to be parallel")
+                               .long("no-parallel")
+                               .overrides_with("parallel"))
+
                           .arg(Arg::with_name("threads")
                                .long("threads")
                                .value_name("NUMBER")
@@ -175,6 +340,11 @@ fn main() {
                           .arg(Arg::with_name("guess_directory")
                                .long("guess-directory-when-missing"))
 
+                          .arg(Arg::with_name("no_guess_directory")
+                               .help("Doesn't guess the directory of the source files which can't be found")
+                               .long("no-guess-directory-when-missing")
+                               .overrides_with("guess_directory"))
+
                           .arg(Arg::with_name("vcs_branch")
                                .help("Set the branch for coveralls report. Defaults to 'master'")
                                .long("vcs-branch")
@@ -229,21 +399,300 @@ fn main() {
                                .help("No symbol demangling")
                                .long("no-demangle"))
 
-                          // This group requires that at least one of --token and --service-job-id
-                          // be present. --service-job-id requires --service-name, so this
-                          // effectively means we accept the following combinations:
-                          // - --token
-                          // - --token --service-job-id --service-name
-                          // - --service-job-id --service-name
-                          .group(ArgGroup::with_name("coveralls_auth").args(&["token", "service_job_id"]).multiple(true))
+                          .arg(Arg::with_name("demangle")
+                               .help("Demangles the symbols, e.g. when disabled in the configuration file")
+                               .long("demangle")
+                               .overrides_with("no-demangle"))
+
+                          .arg(Arg::with_name("patch")
+                               .help("Only reports the coverage of the lines added or modified by the given unified diff ('-' to read it from stdin)")
+                               .long("patch")
+                               .value_name("DIFF")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("config")
+                               .help("Sets the configuration file, defaults to the grcov.toml or the [package.metadata.grcov] table of the Cargo.toml in the current or source directory")
+                               .long("config")
+                               .value_name("FILE")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("profile")
+                               .help("Sets the profile of the configuration file to use")
+                               .long("profile")
+                               .value_name("NAME")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("context")
+                               .help("Collects the coverage of the given path in a test context named LABEL, which is kept in the lcov, covdir, ade and html outputs")
+                               .long("context")
+                               .value_name("LABEL=PATH")
+                               .multiple(true)
+                               .number_of_values(1)
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("strict")
+                               .help("Fails when some of the input files can't be processed, instead of skipping them")
+                               .long("strict"))
+
+                          .arg(Arg::with_name("no_strict")
+                               .help("Skips the input files which can't be processed, e.g. when --strict is set in the configuration file")
+                               .long("no-strict")
+                               .overrides_with("strict"))
+
+                          .arg(Arg::with_name("fail_under_lines")
+                               .help("Exits with a non-zero status if the global line coverage percentage is below this value")
+                               .long("fail-under-lines")
+                               .value_name("PERCENT")
+                               .takes_value(true)
+                               .validator(is_percentage))
+
+                          .arg(Arg::with_name("fail_under_functions")
+                               .help("Exits with a non-zero status if the global function coverage percentage is below this value")
+                               .long("fail-under-functions")
+                               .value_name("PERCENT")
+                               .takes_value(true)
+                               .validator(is_percentage))
+
+                          .arg(Arg::with_name("fail_under_branches")
+                               .help("Exits with a non-zero status if the global branch coverage percentage is below this value")
+                               .long("fail-under-branches")
+                               .value_name("PERCENT")
+                               .takes_value(true)
+                               .validator(is_percentage))
+
+                          .arg(Arg::with_name("fail_under_file_lines")
+                               .help("Exits with a non-zero status if the line coverage percentage of any file is below this value")
+                               .long("fail-under-file-lines")
+                               .value_name("PERCENT")
+                               .takes_value(true)
+                               .validator(is_percentage))
+
+                          .arg(Arg::with_name("fail_under_file_functions")
+                               .help("Exits with a non-zero status if the function coverage percentage of any file is below this value")
+                               .long("fail-under-file-functions")
+                               .value_name("PERCENT")
+                               .takes_value(true)
+                               .validator(is_percentage))
+
+                          .arg(Arg::with_name("fail_under_file_branches")
+                               .help("Exits with a non-zero status if the branch coverage percentage of any file is below this value")
+                               .long("fail-under-file-branches")
+                               .value_name("PERCENT")
+                               .takes_value(true)
+                               .validator(is_percentage))
+
+                          .arg(Arg::with_name("html_template_dir")
+                               .help("Sets a directory containing templates which override the built-in ones of the HTML report (base.html, index.html, file.html, macros.html, single.html, grcov.css or badges/*.svg)")
+                               .long("html-template-dir")
+                               .value_name("DIR")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("html_hi_limit")
+                               .help("Sets the line coverage percentage from which the coverage is shown as high in the HTML report [default: 90]")
+                               .long("html-hi-limit")
+                               .value_name("PERCENT")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("html_med_limit")
+                               .help("Sets the line coverage percentage from which the coverage is shown as medium in the HTML report [default: 75]")
+                               .long("html-med-limit")
+                               .value_name("PERCENT")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("html_fn_hi_limit")
+                               .help("Sets the function coverage percentage from which the coverage is shown as high in the HTML report [default: 90]")
+                               .long("html-fn-hi-limit")
+                               .value_name("PERCENT")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("html_fn_med_limit")
+                               .help("Sets the function coverage percentage from which the coverage is shown as medium in the HTML report [default: 75]")
+                               .long("html-fn-med-limit")
+                               .value_name("PERCENT")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("html_branch_hi_limit")
+                               .help("Sets the branch coverage percentage from which the coverage is shown as high in the HTML report [default: 90]")
+                               .long("html-branch-hi-limit")
+                               .value_name("PERCENT")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("html_branch_med_limit")
+                               .help("Sets the branch coverage percentage from which the coverage is shown as medium in the HTML report [default: 75]")
+                               .long("html-branch-med-limit")
+                               .value_name("PERCENT")
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("summary_sort")
+                               .help("Sets the order of the files and directories in the summary output, by name or from the least covered by lines, functions or branches")
+                               .long("summary-sort")
+                               .value_name("ORDER")
+                               .default_value("name")
+                               .possible_values(&["name", "lines", "functions", "branches"])
+                               .takes_value(true))
+
+                          .arg(Arg::with_name("summary_color")
+                               .help("Colors the coverage percentages of the summary output with the limits of the HTML report")
+                               .long("summary-color"))
+
+                          .arg(Arg::with_name("no_summary_color")
+                               .help("Doesn't color the coverage percentages of the summary output")
+                               .long("no-summary-color")
+                               .overrides_with("summary_color"))
 
-                          .get_matches();
+                          .arg(Arg::with_name("markdown_details")
+                               .help("Adds a collapsible section listing the coverage of the files of each directory to the markdown output")
+                               .long("markdown-details"))
 
-    let paths: Vec<_> = matches.values_of("paths").unwrap().collect();
+                          .arg(Arg::with_name("no_markdown_details")
+                               .help("Doesn't add the collapsible sections to the markdown output")
+                               .long("no-markdown-details")
+                               .overrides_with("markdown_details"))
+
+                          .arg(Arg::with_name("markdown_size_limit")
+                               .help("Sets the maximum size in bytes of the markdown output, the sections which don't fit are left out [default: 65536]")
+                               .long("markdown-size-limit")
+                               .value_name("BYTES")
+                               .takes_value(true))
+
+                          .subcommand(SubCommand::with_name("diff")
+                                      .about("Compares two previously generated lcov or covdir reports")
+                                      .arg(Arg::with_name("base")
+                                           .help("Sets the report to compare against")
+                                           .required(true)
+                                           .takes_value(true))
+                                      .arg(Arg::with_name("current")
+                                           .help("Sets the report to compare")
+                                           .required(true)
+                                           .takes_value(true))
+                                      .arg(Arg::with_name("output_type")
+                                           .help("Sets the output type")
+                                           .short("t")
+                                           .long("output-type")
+                                           .value_name("OUTPUT TYPE")
+                                           .default_value("text")
+                                           .possible_values(&["text", "json"])
+                                           .takes_value(true))
+                                      .arg(Arg::with_name("output_path")
+                                           .help("Specifies the output path")
+                                           .short("o")
+                                           .long("output-path")
+                                           .value_name("PATH")
+                                           .takes_value(true)));
+
+    let args: Vec<OsString> = env::args_os().collect();
+    let matches = app.clone().get_matches_from(&args);
+    let matches = if matches.subcommand_matches("diff").is_some() {
+        matches
+    } else {
+        match get_config_args(&args, &matches) {
+            Ok(Some(config_args)) => app.get_matches_from(config_args),
+            Ok(None) => matches,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    };
+
+    if let Some(matches) = matches.subcommand_matches("diff") {
+        let _ = TermLogger::init(
+            LevelFilter::Error,
+            Config::default(),
+            TerminalMode::Stderr,
+            ColorChoice::Auto,
+        );
+        let load = |name| {
+            let path = matches.value_of(name).unwrap();
+            load_report(Path::new(path)).map_err(|e| GrcovError::Parse(path.to_string(), e))
+        };
+        let result = load("base").and_then(|base| {
+            let diff = compare_reports(&base, &load("current")?);
+            let output_path = matches.value_of("output_path");
+            match matches.value_of("output_type").unwrap() {
+                "json" => output_coverage_diff_json(&diff, output_path),
+                _ => output_coverage_diff_text(&diff, output_path),
+            }
+        });
+        if let Err(e) = result {
+            error!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut contexts: Vec<(&str, Vec<&str>)> = Vec::new();
+    for context in matches.values_of("context").into_iter().flatten() {
+        let (label, path) = match context.find('=') {
+            Some(pos) if pos > 0 => (&context[..pos], &context[pos + 1..]),
+            _ => clap::Error::with_description(
+                &format!("Invalid context '{}', expected LABEL=PATH", context),
+                clap::ErrorKind::InvalidValue,
+            )
+            .exit(),
+        };
+        match contexts.iter_mut().find(|(l, _)| *l == label) {
+            Some((_, paths)) => paths.push(path),
+            None => contexts.push((label, vec![path])),
+        }
+    }
+
+    if !matches.is_present("paths") && contexts.is_empty() {
+        clap::Error::with_description(
+            "The input paths must be given on the command line or in the configuration file",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit();
+    }
+    let paths: Vec<_> = matches
+        .values_of("paths")
+        .map_or_else(Vec::new, |paths| paths.collect());
     let paths: Vec<String> = paths.iter().map(|s| s.to_string()).collect();
     let binary_path = matches.value_of("binary_path").map(PathBuf::from);
-    let output_type = matches.value_of("output_type").unwrap();
-    let output_path = matches.value_of("output_path");
+    let output_types: Vec<_> = matches.values_of("output_type").unwrap().collect();
+    let output_paths: Vec<_> = matches
+        .values_of("output_path")
+        .map_or_else(Vec::new, |paths| paths.collect());
+    if output_types.len() > 1 && output_paths.len() != output_types.len() {
+        clap::Error::with_description(
+            "When several output types are given, an output path must be given for each of them",
+            clap::ErrorKind::WrongNumberOfValues,
+        )
+        .exit();
+    } else if output_types.len() == 1 && output_paths.len() > 1 {
+        clap::Error::with_description(
+            "Only one output path can be given for a single output type",
+            clap::ErrorKind::TooManyValues,
+        )
+        .exit();
+    }
+    // These requirements are checked once the options of the configuration file are merged, as
+    // they can be satisfied by it. The coveralls formats accept the following combinations:
+    // - --token
+    // - --token --service-job-id --service-name
+    // - --service-job-id --service-name
+    let required = [
+        (
+            output_types.iter().any(|t| t.starts_with("coveralls"))
+                && !matches.is_present("token")
+                && !matches.is_present("service_job_id"),
+            "The 'coveralls' and 'coveralls+' formats require --token or --service-job-id",
+        ),
+        (
+            matches.is_present("service_job_id") && !matches.is_present("service_name"),
+            "--service-job-id requires --service-name",
+        ),
+        (
+            output_types.contains(&"patch") && !matches.is_present("patch"),
+            "The 'patch' format requires --patch",
+        ),
+    ];
+    for (missing, description) in &required {
+        if *missing {
+            clap::Error::with_description(description, clap::ErrorKind::MissingRequiredArgument)
+                .exit();
+        }
+    }
     let source_dir = matches.value_of("source_dir").unwrap_or("");
     let prefix_dir = matches.value_of("prefix_dir").unwrap_or("");
     let ignore_not_existing = matches.is_present("ignore_not_existing");
@@ -335,6 +784,73 @@ fn main() {
         excl_br_stop,
     );
     let demangle = !matches.is_present("no-demangle");
+    let changed_lines = matches.value_of("patch").map(|patch| {
+        let changed_lines = if patch == "-" {
+            let stdin = io::stdin();
+            let lock = stdin.lock();
+            parse_unified_diff(lock)
+        } else {
+            let file = File::open(patch).expect("Failed to open the patch file");
+            parse_unified_diff(BufReader::new(file))
+        };
+        changed_lines.unwrap_or_else(|e| panic!("Failed to parse the patch: {}", e))
+    });
+
+    // The thresholds are checked by their validator.
+    let get_threshold = |name| matches.value_of(name).and_then(|v| v.parse::<f64>().ok());
+    let thresholds = Thresholds {
+        global: CoverageLimits {
+            lines: get_threshold("fail_under_lines"),
+            functions: get_threshold("fail_under_functions"),
+            branches: get_threshold("fail_under_branches"),
+        },
+        per_file: CoverageLimits {
+            lines: get_threshold("fail_under_file_lines"),
+            functions: get_threshold("fail_under_file_functions"),
+            branches: get_threshold("fail_under_file_branches"),
+        },
+    };
+
+    let get_limit = |name, default| match matches.value_of(name) {
+        Some(v) => v
+            .parse::<f64>()
+            .unwrap_or_else(|_| panic!("The value of {} should be a number", name)),
+        None => default,
+    };
+    let default_html_options = html::HtmlOptions::default();
+    let html_options = html::HtmlOptions {
+        template_dir: matches.value_of("html_template_dir").map(PathBuf::from),
+        hi_limit: get_limit("html_hi_limit", default_html_options.hi_limit),
+        med_limit: get_limit("html_med_limit", default_html_options.med_limit),
+        fn_hi_limit: get_limit("html_fn_hi_limit", default_html_options.fn_hi_limit),
+        fn_med_limit: get_limit("html_fn_med_limit", default_html_options.fn_med_limit),
+        branch_hi_limit: get_limit("html_branch_hi_limit", default_html_options.branch_hi_limit),
+        branch_med_limit: get_limit(
+            "html_branch_med_limit",
+            default_html_options.branch_med_limit,
+        ),
+    };
+
+    let summary_options = SummaryOptions {
+        sort: match matches.value_of("summary_sort").unwrap() {
+            "lines" => SummarySort::Lines,
+            "functions" => SummarySort::Functions,
+            "branches" => SummarySort::Branches,
+            _ => SummarySort::Name,
+        },
+        color: matches.is_present("summary_color"),
+    };
+
+    let markdown_options = MarkdownOptions {
+        details: matches.is_present("markdown_details"),
+        size_limit: matches
+            .value_of("markdown_size_limit")
+            .map(|v| {
+                v.parse()
+                    .expect("The markdown size limit should be a number of bytes")
+            })
+            .unwrap_or_else(|| MarkdownOptions::default().size_limit),
+    };
 
     panic::set_hook(Box::new(|panic_info| {
         let (filename, line) = panic_info
@@ -362,154 +878,150 @@ fn main() {
         .expect("Number of threads should be a number");
     let guess_directory = matches.is_present("guess_directory");
 
-    let source_root = if !source_dir.is_empty() {
-        Some(canonicalize_path(&source_dir).expect("Source directory does not exist."))
-    } else {
-        None
+    let mut collector = CoverageCollector::new(&paths)
+        .source_dir(source_dir)
+        .ignore_not_existing(ignore_not_existing)
+        .ignore_dirs(&to_ignore_dirs)
+        .keep_dirs(&to_keep_dirs)
+        .filter(filter_option)
+        .file_filter(file_filter)
+        .branch(branch_enabled)
+        .llvm(is_llvm)
+        .guess_directory(guess_directory)
+        .num_threads(num_threads)
+        .strict(matches.is_present("strict"));
+    for (label, paths) in &contexts {
+        collector = collector.context(label, paths);
+    }
+    if let Some(binary_path) = binary_path {
+        collector = collector.binary_path(binary_path);
+    }
+    if !prefix_dir.is_empty() {
+        collector = collector.prefix_dir(prefix_dir);
+    }
+    if !path_mapping_file.is_empty() {
+        collector = collector.path_mapping_file(path_mapping_file);
+    }
+    let results = collector.collect_intermediate().and_then(|coverage| {
+        // The intermediate files are written before the paths are rewritten.
+        for (i, output_type) in output_types.iter().enumerate() {
+            if *output_type == "intermediate" {
+                let output_path = output_paths.get(i).copied();
+                coverage.write(get_target_output_writable(output_path)?)?;
+            }
+        }
+        collector.rewrite(coverage)
+    });
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
     };
+    let iterator: CovResultIter = Box::new(results.into_iter());
 
-    let prefix_dir = if prefix_dir.is_empty() {
-        source_root.clone()
-    } else {
-        Some(PathBuf::from(prefix_dir))
+    let iterator = match changed_lines {
+        Some(changed_lines) => filter_changed_lines(iterator, changed_lines),
+        None => iterator,
     };
 
-    let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
-    let tmp_path = tmp_dir.path().to_owned();
-    assert!(tmp_path.exists());
-
-    let result_map: Arc<SyncCovResultMap> = Arc::new(Mutex::new(
-        FxHashMap::with_capacity_and_hasher(20_000, Default::default()),
-    ));
-    let (sender, receiver) = bounded(2 * num_threads);
-    let path_mapping: Arc<Mutex<Option<Value>>> = Arc::new(Mutex::new(None));
-
-    let producer = {
-        let sender: JobSender = sender.clone();
-        let tmp_path = tmp_path.clone();
-        let path_mapping_file = path_mapping_file.to_owned();
-        let path_mapping = Arc::clone(&path_mapping);
-
-        thread::Builder::new()
-            .name(String::from("Producer"))
-            .spawn(move || {
-                let producer_path_mapping_buf = producer(
-                    &tmp_path,
-                    &paths,
-                    &sender,
-                    filter_option.is_some() && filter_option.unwrap(),
-                    is_llvm,
-                );
+    let results: Vec<_> = iterator.collect();
+    let threshold_failures = check_thresholds(&results, &thresholds);
 
-                let mut path_mapping = path_mapping.lock().unwrap();
-                *path_mapping = if !path_mapping_file.is_empty() {
-                    let file = File::open(path_mapping_file).unwrap();
-                    Some(serde_json::from_reader(file).unwrap())
-                } else {
-                    producer_path_mapping_buf.map(|producer_path_mapping_buf| {
-                        serde_json::from_slice(&producer_path_mapping_buf).unwrap()
-                    })
-                };
-            })
-            .unwrap()
+    let write_output = |output_type: &str, output_path: Option<&str>, iterator: CovResultIter| {
+        let result = match output_type {
+            "ade" => output_activedata_etl(iterator, output_path, demangle),
+            "lcov" => output_lcov(iterator, output_path, demangle),
+            "coveralls" => output_coveralls(
+                iterator,
+                repo_token,
+                service_name,
+                service_number,
+                service_job_id,
+                service_pull_request,
+                commit_sha,
+                false,
+                output_path,
+                vcs_branch,
+                is_parallel,
+                demangle,
+            ),
+            "coveralls+" => output_coveralls(
+                iterator,
+                repo_token,
+                service_name,
+                service_number,
+                service_job_id,
+                service_pull_request,
+                commit_sha,
+                true,
+                output_path,
+                vcs_branch,
+                is_parallel,
+                demangle,
+            ),
+            "files" => output_files(iterator, output_path),
+            "covdir" => output_covdir(iterator, output_path),
+            "html" => output_html(
+                iterator,
+                output_path,
+                num_threads,
+                branch_enabled,
+                demangle,
+                &html_options,
+            ),
+            "html-single" => output_html_single(
+                iterator,
+                output_path,
+                branch_enabled,
+                demangle,
+                &html_options,
+            ),
+            "summary" => output_summary(
+                iterator,
+                output_path,
+                branch_enabled,
+                &summary_options,
+                &html_options,
+            ),
+            "summary-json" => output_summary_json(iterator, output_path, demangle),
+            "markdown" => output_markdown(iterator, output_path, branch_enabled, &markdown_options),
+            "annotated" => output_annotated(iterator, output_path, branch_enabled, demangle),
+            "cobertura" => output_cobertura(source_dir, iterator, output_path, demangle),
+            "sonar" => output_sonarqube(iterator, output_path),
+            "jacoco" => output_jacoco(iterator, output_path, demangle),
+            "patch" => output_patch(iterator, output_path),
+            // Already written from the results before their paths were rewritten.
+            "intermediate" => Ok(()),
+            _ => panic!("{} is not a supported output type", output_type),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+            process::exit(1);
+        }
     };
 
-    let mut parsers = Vec::new();
-
-    for i in 0..num_threads {
-        let receiver = receiver.clone();
-        let result_map = Arc::clone(&result_map);
-        let working_dir = tmp_path.join(format!("{}", i));
-        let source_root = source_root.clone();
-        let binary_path = binary_path.clone();
-
-        let t = thread::Builder::new()
-            .name(format!("Consumer {}", i))
-            .spawn(move || {
-                fs::create_dir(&working_dir).expect("Failed to create working directory");
-                consumer(
-                    &working_dir,
-                    source_root.as_deref(),
-                    &result_map,
-                    receiver,
-                    branch_enabled,
-                    guess_directory,
-                    binary_path.as_deref(),
-                );
-            })
-            .unwrap();
-
-        parsers.push(t);
-    }
-
-    if producer.join().is_err() {
-        process::exit(1);
-    }
-
-    // Poison the receiver, now that the producer is finished.
-    for _ in 0..num_threads {
-        sender.send(None).unwrap();
+    // The results are parsed and rewritten only once, then every writer gets its own copy,
+    // except the last one which can consume them.
+    let (last_output_type, output_types) = output_types.split_last().unwrap();
+    for (i, output_type) in output_types.iter().enumerate() {
+        write_output(
+            output_type,
+            output_paths.get(i).copied(),
+            Box::new(results.clone().into_iter()),
+        );
     }
+    write_output(
+        last_output_type,
+        output_paths.get(output_types.len()).copied(),
+        Box::new(results.into_iter()),
+    );
 
-    for parser in parsers {
-        if parser.join().is_err() {
-            process::exit(1);
+    if !threshold_failures.is_empty() {
+        for failure in &threshold_failures {
+            eprintln!("{}", failure);
         }
+        process::exit(THRESHOLD_EXIT_CODE);
     }
-
-    let result_map_mutex = Arc::try_unwrap(result_map).unwrap();
-    let result_map = result_map_mutex.into_inner().unwrap();
-
-    let path_mapping_mutex = Arc::try_unwrap(path_mapping).unwrap();
-    let path_mapping = path_mapping_mutex.into_inner().unwrap();
-
-    let iterator = rewrite_paths(
-        result_map,
-        path_mapping,
-        source_root.as_deref(),
-        prefix_dir.as_deref(),
-        ignore_not_existing,
-        &to_ignore_dirs,
-        &to_keep_dirs,
-        filter_option,
-        file_filter,
-    );
-
-    match output_type {
-        "ade" => output_activedata_etl(iterator, output_path, demangle),
-        "lcov" => output_lcov(iterator, output_path, demangle),
-        "coveralls" => output_coveralls(
-            iterator,
-            repo_token,
-            service_name,
-            service_number,
-            service_job_id,
-            service_pull_request,
-            commit_sha,
-            false,
-            output_path,
-            vcs_branch,
-            is_parallel,
-            demangle,
-        ),
-        "coveralls+" => output_coveralls(
-            iterator,
-            repo_token,
-            service_name,
-            service_number,
-            service_job_id,
-            service_pull_request,
-            commit_sha,
-            true,
-            output_path,
-            vcs_branch,
-            is_parallel,
-            demangle,
-        ),
-        "files" => output_files(iterator, output_path),
-        "covdir" => output_covdir(iterator, output_path),
-        "html" => output_html(iterator, output_path, num_threads, branch_enabled),
-        "cobertura" => output_cobertura(source_dir, iterator, output_path, demangle),
-        _ => panic!("{} is not a supported output type", output_type),
-    };
 }
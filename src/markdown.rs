@@ -0,0 +1,362 @@
+use std::fmt::Write as _;
+use std::io::{BufWriter, Write};
+use std::path::{Path, MAIN_SEPARATOR};
+
+use crate::defs::*;
+use crate::error::GrcovError;
+use crate::html;
+use crate::output::get_target_output_writable;
+
+/// The number of files and of directories listed as the lowest covered ones.
+const LOWEST_COUNT: usize = 10;
+
+/// The options of the markdown report.
+#[derive(Clone, Debug)]
+pub struct MarkdownOptions {
+    /// Adds a collapsible section for each directory, listing the coverage of its files.
+    pub details: bool,
+    /// The maximum size of the report in bytes, the sections which don't fit are left out.
+    pub size_limit: usize,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        // The maximum size of a comment on GitHub.
+        MarkdownOptions {
+            details: false,
+            size_limit: 65536,
+        }
+    }
+}
+
+fn format_cell(covered: usize, total: usize) -> String {
+    match html::get_percentage(covered, total) {
+        Some(percentage) => format!("{:.2}% ({}/{})", percentage, covered, total),
+        None => "-".to_string(),
+    }
+}
+
+/// Returns a path as inline code which can be put in a table.
+fn format_path(path: &str) -> String {
+    format!("`{}`", path.replace('|', "\\|"))
+}
+
+fn write_table(out: &mut String, title: &str, rows: &[(String, &HtmlStats)], branch_enabled: bool) {
+    if branch_enabled {
+        writeln!(out, "| {} | Lines | Functions | Branches |", title).unwrap();
+        writeln!(out, "| :-- | --: | --: | --: |").unwrap();
+    } else {
+        writeln!(out, "| {} | Lines | Functions |", title).unwrap();
+        writeln!(out, "| :-- | --: | --: |").unwrap();
+    }
+    for (name, stats) in rows {
+        write!(
+            out,
+            "| {} | {} | {} |",
+            format_path(name),
+            format_cell(stats.covered_lines, stats.total_lines),
+            format_cell(stats.covered_funs, stats.total_funs)
+        )
+        .unwrap();
+        if branch_enabled {
+            write!(
+                out,
+                " {} |",
+                format_cell(stats.covered_branches, stats.total_branches)
+            )
+            .unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+}
+
+/// Returns the rows with the lowest line coverage, the ones without lines being left out.
+fn get_lowest<'a>(rows: &[(String, &'a HtmlStats)]) -> Vec<(String, &'a HtmlStats)> {
+    let mut rows: Vec<_> = rows
+        .iter()
+        .filter(|(_, stats)| stats.total_lines != 0)
+        .cloned()
+        .collect();
+    rows.sort_by(|(a_name, a), (b_name, b)| {
+        let a_percentage = html::get_percentage(a.covered_lines, a.total_lines).unwrap();
+        let b_percentage = html::get_percentage(b.covered_lines, b.total_lines).unwrap();
+        a_percentage
+            .partial_cmp(&b_percentage)
+            .unwrap()
+            .then_with(|| a_name.cmp(b_name))
+    });
+    rows.truncate(LOWEST_COUNT);
+    rows
+}
+
+fn get_dir_name(dir: &str) -> String {
+    if dir.is_empty() {
+        format!(".{}", MAIN_SEPARATOR)
+    } else {
+        format!("{}{}", dir, MAIN_SEPARATOR)
+    }
+}
+
+/// Renders the markdown report: the totals, which are always kept, then the sections which fit
+/// under the size limit.
+fn get_markdown(
+    global: &HtmlGlobalStats,
+    branch_enabled: bool,
+    options: &MarkdownOptions,
+) -> String {
+    let mut out = String::new();
+    writeln!(out, "## Coverage report\n").unwrap();
+    writeln!(out, "| | Covered | Total | Coverage |").unwrap();
+    writeln!(out, "| :-- | --: | --: | --: |").unwrap();
+    let stats = &global.stats;
+    let mut totals = vec![
+        ("Lines", stats.covered_lines, stats.total_lines),
+        ("Functions", stats.covered_funs, stats.total_funs),
+    ];
+    if branch_enabled {
+        totals.push(("Branches", stats.covered_branches, stats.total_branches));
+    }
+    for (name, covered, total) in totals {
+        let percentage = match html::get_percentage(covered, total) {
+            Some(percentage) => format!("{:.2}%", percentage),
+            None => "-".to_string(),
+        };
+        writeln!(
+            out,
+            "| {} | {} | {} | {} |",
+            name, covered, total, percentage
+        )
+        .unwrap();
+    }
+
+    let files: Vec<(String, &HtmlStats)> = global
+        .dirs
+        .iter()
+        .flat_map(|(dir, dir_stats)| {
+            dir_stats.files.iter().map(move |(name, file_stats)| {
+                let path = Path::new(dir).join(name);
+                (path.to_str().unwrap().to_string(), &file_stats.stats)
+            })
+        })
+        .collect();
+    let tree = html::get_dirs_tree(&global.dirs);
+    let dirs: Vec<(String, &HtmlStats)> = tree
+        .iter()
+        .filter(|(name, _)| !name.is_empty())
+        .map(|(name, dir_stats)| (get_dir_name(name), &dir_stats.stats))
+        .collect();
+
+    let mut sections = Vec::new();
+    let lowest_files = get_lowest(&files);
+    if !lowest_files.is_empty() {
+        let mut section = String::from("\n### Lowest covered files\n\n");
+        write_table(&mut section, "File", &lowest_files, branch_enabled);
+        sections.push(section);
+    }
+    let lowest_dirs = get_lowest(&dirs);
+    if !lowest_dirs.is_empty() {
+        let mut section = String::from("\n### Lowest covered directories\n\n");
+        write_table(&mut section, "Directory", &lowest_dirs, branch_enabled);
+        sections.push(section);
+    }
+    if options.details {
+        for (dir, dir_stats) in &global.dirs {
+            let mut section = String::new();
+            writeln!(
+                section,
+                "\n<details>\n<summary><code>{}</code>: {}</summary>\n",
+                get_dir_name(dir),
+                format_cell(dir_stats.stats.covered_lines, dir_stats.stats.total_lines)
+            )
+            .unwrap();
+            let files: Vec<_> = dir_stats
+                .files
+                .iter()
+                .map(|(name, file_stats)| (name.clone(), &file_stats.stats))
+                .collect();
+            write_table(&mut section, "File", &files, branch_enabled);
+            writeln!(section, "\n</details>").unwrap();
+            sections.push(section);
+        }
+    }
+
+    let count = sections.len();
+    let get_note = |left: usize| {
+        format!(
+            "\n_{} more {} left out to keep the report under {} bytes._\n",
+            left,
+            if left == 1 {
+                "section was"
+            } else {
+                "sections were"
+            },
+            options.size_limit
+        )
+    };
+    if count != 0 && out.len() + get_note(count).len() > options.size_limit {
+        writeln!(
+            out,
+            "\n_The {} left out, as the totals alone leave no room under the limit of {} bytes._",
+            if count == 1 {
+                "section was"
+            } else {
+                "sections were"
+            },
+            options.size_limit
+        )
+        .unwrap();
+        return out;
+    }
+    for (i, section) in sections.into_iter().enumerate() {
+        let note = get_note(count - i);
+        // The note has to fit too if the section doesn't.
+        if out.len() + section.len() + note.len() > options.size_limit {
+            out.push_str(&note);
+            break;
+        }
+        out.push_str(&section);
+    }
+
+    out
+}
+
+pub fn output_markdown(
+    results: CovResultIter,
+    output_file: Option<&str>,
+    branch_enabled: bool,
+    options: &MarkdownOptions,
+) -> Result<(), GrcovError> {
+    write_markdown(
+        results,
+        get_target_output_writable(output_file)?,
+        branch_enabled,
+        options,
+    )
+}
+
+/// Writes a markdown summary of the coverage, for pull request comments or CI job summaries.
+pub fn write_markdown(
+    results: CovResultIter,
+    output: impl Write,
+    branch_enabled: bool,
+    options: &MarkdownOptions,
+) -> Result<(), GrcovError> {
+    let global = html::get_global_stats(results);
+    let mut writer = BufWriter::new(output);
+    writer.write_all(get_markdown(&global, branch_enabled, options).as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn get_global(paths: &[(&str, &[(u32, u64)])]) -> HtmlGlobalStats {
+        let results: Vec<_> = paths
+            .iter()
+            .map(|(path, lines)| {
+                (
+                    PathBuf::from(path),
+                    PathBuf::from(path),
+                    CovResult {
+                        lines: lines.iter().cloned().collect(),
+                        branches: [(1, vec![1, 0])].iter().cloned().collect(),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        html::get_global_stats(Box::new(results.into_iter()))
+    }
+
+    #[test]
+    fn test_get_markdown() {
+        let global = get_global(&[
+            ("build.rs", &[(1, 0), (2, 0)]),
+            ("src/lib.rs", &[(1, 1), (2, 1), (3, 0)]),
+            ("src/a|b.rs", &[(1, 1)]),
+        ]);
+        let markdown = get_markdown(&global, true, &MarkdownOptions::default());
+        let expected = "\
+## Coverage report
+
+| | Covered | Total | Coverage |
+| :-- | --: | --: | --: |
+| Lines | 3 | 6 | 50.00% |
+| Functions | 0 | 0 | - |
+| Branches | 3 | 6 | 50.00% |
+
+### Lowest covered files
+
+| File | Lines | Functions | Branches |
+| :-- | --: | --: | --: |
+| `build.rs` | 0.00% (0/2) | - | 50.00% (1/2) |
+| `src/lib.rs` | 66.67% (2/3) | - | 50.00% (1/2) |
+| `src/a\\|b.rs` | 100.00% (1/1) | - | 50.00% (1/2) |
+
+### Lowest covered directories
+
+| Directory | Lines | Functions | Branches |
+| :-- | --: | --: | --: |
+| `src/` | 75.00% (3/4) | - | 50.00% (2/4) |
+"
+        .replace("src/", &format!("src{}", MAIN_SEPARATOR));
+        assert_eq!(markdown, expected);
+    }
+
+    #[test]
+    fn test_get_markdown_details() {
+        let global = get_global(&[
+            ("build.rs", &[(1, 0)]),
+            ("src/lib.rs", &[(1, 1)]),
+            ("src/a/main.rs", &[(1, 1), (2, 0)]),
+        ]);
+        let options = MarkdownOptions {
+            details: true,
+            ..Default::default()
+        };
+        let markdown = get_markdown(&global, false, &options);
+        assert_eq!(markdown.matches("<details>").count(), 3);
+        assert!(markdown.contains(&format!(
+            "<summary><code>.{}</code>: 0.00% (0/1)</summary>",
+            MAIN_SEPARATOR
+        )));
+        assert!(markdown.contains("| `main.rs` | 50.00% (1/2) | - |\n"));
+        assert!(!markdown.contains("Branches"));
+    }
+
+    #[test]
+    fn test_get_markdown_size_limit() {
+        let paths: Vec<String> = (0..20).map(|i| format!("src/d{}/f.rs", i)).collect();
+        let lines: &[(u32, u64)] = &[(1, 1), (2, 0)];
+        let global = get_global(
+            &paths
+                .iter()
+                .map(|path| (path.as_str(), lines))
+                .collect::<Vec<_>>(),
+        );
+        let options = MarkdownOptions {
+            details: true,
+            size_limit: 2000,
+        };
+        let markdown = get_markdown(&global, true, &options);
+        assert!(markdown.len() <= 2000);
+        assert!(markdown.contains("### Lowest covered files"));
+        assert!(markdown
+            .ends_with(" more sections were left out to keep the report under 2000 bytes._\n"));
+
+        // The totals are always written.
+        let options = MarkdownOptions {
+            details: false,
+            size_limit: 10,
+        };
+        let markdown = get_markdown(&global, true, &options);
+        assert!(markdown.contains("| Lines | 20 | 40 | 50.00% |"));
+        assert!(markdown.ends_with(
+            "_The sections were left out, as the totals alone leave no room under the limit of 10 \
+             bytes._\n"
+        ));
+    }
+}
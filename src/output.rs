@@ -3,73 +3,74 @@ use md5::{Digest, Md5};
 use rustc_hash::FxHashMap;
 use serde_json::{self, json, Value};
 use std::cell::RefCell;
-use std::collections::{hash_map, BTreeSet};
+use std::collections::{hash_map, BTreeMap, BTreeSet};
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{self, BufWriter, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::{
-    process::{self, Command, Stdio},
+    process::{Command, Stdio},
     thread,
 };
-use symbolic_common::Name;
-use symbolic_demangle::{Demangle, DemangleOptions};
+use symbolic_demangle::DemangleOptions;
 use uuid::Uuid;
 
 use crate::defs::*;
+use crate::error::GrcovError;
 use crate::html;
 
-macro_rules! demangle {
-    ($name: expr, $demangle: expr, $options: expr) => {{
-        if $demangle {
-            if let Some(name) = Name::from($name).demangle($options) {
-                StringOrRef::S(name)
-            } else {
-                StringOrRef::R($name)
-            }
-        } else {
-            StringOrRef::R($name)
-        }
-    }};
-}
-
-pub fn get_target_output_writable(output_file: Option<&str>) -> Box<dyn Write> {
+pub fn get_target_output_writable(output_file: Option<&str>) -> Result<Box<dyn Write>, GrcovError> {
     let write_target: Box<dyn Write> = match output_file {
         Some(filename) => {
             let output = PathBuf::from(filename);
             if output.is_dir() {
-                panic!(
+                return Err(GrcovError::Output(format!(
                     "The output file {} is a directory, but must be a regular file.",
                     filename
-                )
+                )));
             }
-            Box::new(File::create(&output).unwrap_or_else(|_| {
+            Box::new(File::create(&output).map_err(|_| {
                 let parent = output.parent();
                 if let Some(parent_path) = parent {
                     if !parent_path.exists() {
-                        panic!(
+                        return GrcovError::Output(format!(
                             "Cannot create {} to dump coverage data, as {} doesn't exist",
                             filename,
                             parent_path.display()
-                        )
+                        ));
                     }
                 }
-                panic!("Cannot create the file {} to dump coverage data.", filename)
-            }))
+                GrcovError::Output(format!(
+                    "Cannot create the file {} to dump coverage data.",
+                    filename
+                ))
+            })?)
         }
         None => {
             let stdout = io::stdout();
             Box::new(stdout)
         }
     };
-    write_target
+    Ok(write_target)
 }
 
-pub fn output_activedata_etl(results: CovResultIter, output_file: Option<&str>, demangle: bool) {
+pub fn output_activedata_etl(
+    results: CovResultIter,
+    output_file: Option<&str>,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    write_activedata_etl(results, get_target_output_writable(output_file)?, demangle)
+}
+
+pub fn write_activedata_etl(
+    results: CovResultIter,
+    output: impl Write,
+    demangle: bool,
+) -> Result<(), GrcovError> {
     let demangle_options = DemangleOptions::name_only();
-    let mut writer = BufWriter::new(get_target_output_writable(output_file));
+    let mut writer = BufWriter::new(output);
 
     for (_, rel_path, result) in results {
         let covered: Vec<u32> = result
@@ -102,10 +103,16 @@ pub fn output_activedata_etl(results: CovResultIter, output_file: Option<&str>,
             // println!("{} {} {}", name, function.executed, function.start);
             let mut func_end = end;
 
-            for start in &start_indexes {
-                if *start > function.start {
-                    func_end = *start;
-                    break;
+            // When the end of the function is unknown, it is assumed to be the start of the
+            // following one.
+            if let Some(function_end) = function.end {
+                func_end = function_end + 1;
+            } else {
+                for start in &start_indexes {
+                    if *start > function.start {
+                        func_end = *start;
+                        break;
+                    }
                 }
             }
 
@@ -127,58 +134,71 @@ pub fn output_activedata_etl(results: CovResultIter, output_file: Option<&str>,
                 orphan_uncovered.remove(line);
             }
 
-            writeln!(
-                writer,
-                "{}",
-                json!({
-                    "language": "c/c++",
-                    "file": {
-                        "name": rel_path,
-                    },
-                    "method": {
-                        "name": demangle!(name, demangle, demangle_options),
-                        "covered": lines_covered,
-                        "uncovered": lines_uncovered,
-                        "total_covered": lines_covered.len(),
-                        "total_uncovered": lines_uncovered.len(),
-                        "percentage_covered": lines_covered.len() as f32 / (lines_covered.len() + lines_uncovered.len()) as f32,
-                    }
-                })
-            ).unwrap();
-        }
-
-        let orphan_covered: Vec<u32> = orphan_covered.into_iter().collect();
-        let orphan_uncovered: Vec<u32> = orphan_uncovered.into_iter().collect();
-
-        // The orphan lines will represent the file as a whole.
-        writeln!(
-            writer,
-            "{}",
-            json!({
+            let mut method = json!({
                 "language": "c/c++",
-                "is_file": true,
                 "file": {
                     "name": rel_path,
-                    "covered": covered,
-                    "uncovered": uncovered,
-                    "total_covered": covered.len(),
-                    "total_uncovered": uncovered.len(),
-                    "percentage_covered": covered.len() as f32 / (covered.len() + uncovered.len()) as f32,
                 },
                 "method": {
-                    "covered": orphan_covered,
-                    "uncovered": orphan_uncovered,
-                    "total_covered": orphan_covered.len(),
-                    "total_uncovered": orphan_uncovered.len(),
-                    "percentage_covered": orphan_covered.len() as f32 / (orphan_covered.len() + orphan_uncovered.len()) as f32,
+                    "name": demangle!(name, demangle, demangle_options),
+                    "covered": lines_covered,
+                    "uncovered": lines_uncovered,
+                    "total_covered": lines_covered.len(),
+                    "total_uncovered": lines_uncovered.len(),
+                    "percentage_covered": lines_covered.len() as f32 / (lines_covered.len() + lines_uncovered.len()) as f32,
                 }
-            })
-        ).unwrap();
+            });
+            // The contexts in which the function was executed.
+            if let Some(counts) = result.contexts.functions.get(name) {
+                let labels: Vec<&String> = counts
+                    .iter()
+                    .filter(|&(_, &count)| count > 0)
+                    .map(|(label, _)| label)
+                    .collect();
+                method["method"]["contexts"] = json!(labels);
+            }
+            writeln!(writer, "{}", method)?;
+        }
+
+        let orphan_covered: Vec<u32> = orphan_covered.into_iter().collect();
+        let orphan_uncovered: Vec<u32> = orphan_uncovered.into_iter().collect();
+
+        // The orphan lines will represent the file as a whole.
+        let mut file = json!({
+            "language": "c/c++",
+            "is_file": true,
+            "file": {
+                "name": rel_path,
+                "covered": covered,
+                "uncovered": uncovered,
+                "total_covered": covered.len(),
+                "total_uncovered": uncovered.len(),
+                "percentage_covered": covered.len() as f32 / (covered.len() + uncovered.len()) as f32,
+            },
+            "method": {
+                "covered": orphan_covered,
+                "uncovered": orphan_uncovered,
+                "total_covered": orphan_covered.len(),
+                "total_uncovered": orphan_uncovered.len(),
+                "percentage_covered": orphan_covered.len() as f32 / (orphan_covered.len() + orphan_uncovered.len()) as f32,
+            }
+        });
+        let contexts = result.contexts.covered_lines();
+        if !contexts.is_empty() {
+            file["file"]["contexts"] = json!(contexts);
+        }
+        writeln!(writer, "{}", file)?;
     }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn output_covdir(results: CovResultIter, output_file: Option<&str>) -> Result<(), GrcovError> {
+    write_covdir(results, get_target_output_writable(output_file)?)
 }
 
-pub fn output_covdir(results: CovResultIter, output_file: Option<&str>) {
-    let mut writer = BufWriter::new(get_target_output_writable(output_file));
+pub fn write_covdir(results: CovResultIter, output: impl Write) -> Result<(), GrcovError> {
+    let mut writer = BufWriter::new(output);
     let mut relative: FxHashMap<PathBuf, Rc<RefCell<CDDirStats>>> = FxHashMap::default();
     let global = Rc::new(RefCell::new(CDDirStats::new("".to_string())));
     relative.insert(PathBuf::from(""), global.clone());
@@ -221,91 +241,206 @@ pub fn output_covdir(results: CovResultIter, output_file: Option<&str>) {
             };
         }
 
-        prev_stats.borrow_mut().files.push(CDFileStats::new(
+        let mut file_stats = CDFileStats::new(
             path.file_name().unwrap().to_str().unwrap().to_string(),
             result.lines,
-        ));
+            &result.functions,
+            &result.branches,
+        );
+        file_stats.contexts = result.contexts.covered_lines();
+        prev_stats.borrow_mut().files.push(file_stats);
     }
 
     let mut global = global.take();
     global.set_stats();
 
-    serde_json::to_writer(&mut writer, &global.into_json()).unwrap();
+    serde_json::to_writer(&mut writer, &global.into_json())?;
+    writer.flush()?;
+    Ok(())
 }
 
-pub fn output_lcov(results: CovResultIter, output_file: Option<&str>, demangle: bool) {
-    let demangle_options = DemangleOptions::name_only();
-    let mut writer = BufWriter::new(get_target_output_writable(output_file));
-    writer.write_all(b"TN:\n").unwrap();
+pub fn output_lcov(
+    results: CovResultIter,
+    output_file: Option<&str>,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    write_lcov(results, get_target_output_writable(output_file)?, demangle)
+}
 
+pub fn write_lcov(
+    results: CovResultIter,
+    output: impl Write,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    let mut writer = BufWriter::new(output);
+
+    // The counts which aren't part of a context are written in the unnamed test, followed by a
+    // named test for every context. Only the records of the contexts are kept in memory, until
+    // all the results have been written.
+    let mut context_records: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    writer.write_all(b"TN:\n")?;
     for (_, rel_path, result) in results {
-        // println!("{} {:?}", rel_path, result.lines);
-
-        writeln!(writer, "SF:{}", rel_path.display()).unwrap();
-
-        for (name, function) in &result.functions {
-            writeln!(
-                writer,
-                "FN:{},{}",
-                function.start,
-                demangle!(name, demangle, demangle_options)
-            )
-            .unwrap();
-        }
-        for (name, function) in &result.functions {
-            writeln!(
-                writer,
-                "FNDA:{},{}",
-                if function.executed { 1 } else { 0 },
-                demangle!(name, demangle, demangle_options)
-            )
-            .unwrap();
+        if result.contexts.is_empty() {
+            write_lcov_record(&mut writer, &rel_path, &result, demangle)?;
+            continue;
         }
-        if !result.functions.is_empty() {
-            writeln!(writer, "FNF:{}", result.functions.len()).unwrap();
-            writeln!(
-                writer,
-                "FNH:{}",
-                result.functions.values().filter(|x| x.executed).count()
-            )
-            .unwrap();
+
+        write_lcov_record(
+            &mut writer,
+            &rel_path,
+            &context_remainder(&result),
+            demangle,
+        )?;
+        for label in result.contexts.labels() {
+            if let Some(context_result) = context_result(&result, label) {
+                let records = context_records.entry(label.to_string()).or_default();
+                write_lcov_record(records, &rel_path, &context_result, demangle)?;
+            }
         }
+    }
+
+    for (label, records) in context_records {
+        writeln!(writer, "TN:{}", label)?;
+        writer.write_all(&records)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Returns the coverage of a result without the counts of its contexts.
+fn context_remainder(result: &CovResult) -> CovResult {
+    let context_count = |counts: Option<&ContextCounts>| -> u64 {
+        counts.map_or(0, |counts| counts.values().sum())
+    };
+    let mut remainder = result.clone();
+    for (line, count) in remainder.lines.iter_mut() {
+        *count = count.saturating_sub(context_count(result.contexts.lines.get(line)));
+    }
+    for (name, function) in remainder.functions.iter_mut() {
+        let count = if function.executed {
+            function.count.max(1)
+        } else {
+            0
+        };
+        function.count = count.saturating_sub(context_count(result.contexts.functions.get(name)));
+        function.executed = function.count > 0;
+    }
+    remainder.contexts = Contexts::default();
+    remainder
+}
 
-        // branch coverage information
-        let mut branch_count = 0;
-        let mut branch_hit = 0;
-        for (line, taken) in &result.branches {
-            branch_count += taken.len();
-            for (n, b_t) in taken.iter().enumerate() {
+/// Returns the coverage of a result in a context, if it has any.
+fn context_result(result: &CovResult, label: &str) -> Option<CovResult> {
+    let lines: BTreeMap<u32, u64> = result
+        .contexts
+        .lines
+        .iter()
+        .filter_map(|(&line, counts)| counts.get(label).map(|&count| (line, count)))
+        .collect();
+    let functions: FunctionMap = result
+        .contexts
+        .functions
+        .iter()
+        .filter_map(|(name, counts)| {
+            let count = *counts.get(label)?;
+            let function = result.functions.get(name)?;
+            Some((
+                name.clone(),
+                Function {
+                    executed: count > 0,
+                    count,
+                    ..function.clone()
+                },
+            ))
+        })
+        .collect();
+    if lines.is_empty() && functions.is_empty() {
+        return None;
+    }
+    Some(CovResult {
+        lines,
+        branches: BTreeMap::new(),
+        functions,
+        regions: BTreeMap::new(),
+        contexts: Contexts::default(),
+    })
+}
+
+fn write_lcov_record(
+    writer: &mut impl Write,
+    rel_path: &Path,
+    result: &CovResult,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    let demangle_options = DemangleOptions::name_only();
+    writeln!(writer, "SF:{}", rel_path.display())?;
+
+    for (name, function) in &result.functions {
+        writeln!(
+            writer,
+            "FN:{},{}",
+            function.start,
+            demangle!(name, demangle, demangle_options)
+        )?;
+    }
+    for (name, function) in &result.functions {
+        writeln!(
+            writer,
+            "FNDA:{},{}",
+            if function.executed {
+                function.count.max(1)
+            } else {
+                0
+            },
+            demangle!(name, demangle, demangle_options)
+        )?;
+    }
+    if !result.functions.is_empty() {
+        writeln!(writer, "FNF:{}", result.functions.len())?;
+        writeln!(
+            writer,
+            "FNH:{}",
+            result.functions.values().filter(|x| x.executed).count()
+        )?;
+    }
+
+    // branch coverage information
+    let mut branch_count = 0;
+    let mut branch_hit = 0;
+    for (line, counts) in &result.branches {
+        branch_count += counts.len();
+        // A branch which wasn't taken is marked with "-" when its line was never executed.
+        let executed = result.lines.get(line).map_or(false, |&count| count > 0);
+        for (n, count) in counts.iter().enumerate() {
+            if *count > 0 {
+                writeln!(writer, "BRDA:{},0,{},{}", line, n, count)?;
+                branch_hit += 1;
+            } else {
                 writeln!(
                     writer,
                     "BRDA:{},0,{},{}",
                     line,
                     n,
-                    if *b_t { "1" } else { "-" }
-                )
-                .unwrap();
-                if *b_t {
-                    branch_hit += 1;
-                }
+                    if executed { "0" } else { "-" }
+                )?;
             }
         }
+    }
 
-        writeln!(writer, "BRF:{}", branch_count).unwrap();
-        writeln!(writer, "BRH:{}", branch_hit).unwrap();
+    writeln!(writer, "BRF:{}", branch_count)?;
+    writeln!(writer, "BRH:{}", branch_hit)?;
 
-        for (line, execution_count) in &result.lines {
-            writeln!(writer, "DA:{},{}", line, execution_count).unwrap();
-        }
-        writeln!(writer, "LF:{}", result.lines.len()).unwrap();
-        writeln!(
-            writer,
-            "LH:{}",
-            result.lines.values().filter(|&v| *v > 0).count()
-        )
-        .unwrap();
-        writer.write_all(b"end_of_record\n").unwrap();
+    for (line, execution_count) in &result.lines {
+        writeln!(writer, "DA:{},{}", line, execution_count)?;
     }
+    writeln!(writer, "LF:{}", result.lines.len())?;
+    writeln!(
+        writer,
+        "LH:{}",
+        result.lines.values().filter(|&v| *v > 0).count()
+    )?;
+    writer.write_all(b"end_of_record\n")?;
+    Ok(())
 }
 
 fn get_digest(path: PathBuf) -> String {
@@ -422,7 +557,37 @@ pub fn output_coveralls(
     vcs_branch: &str,
     parallel: bool,
     demangle: bool,
-) {
+) -> Result<(), GrcovError> {
+    write_coveralls(
+        results,
+        repo_token,
+        service_name,
+        service_number,
+        service_job_id,
+        service_pull_request,
+        commit_sha,
+        with_function_info,
+        get_target_output_writable(output_file)?,
+        vcs_branch,
+        parallel,
+        demangle,
+    )
+}
+
+pub fn write_coveralls(
+    results: CovResultIter,
+    repo_token: Option<&str>,
+    service_name: Option<&str>,
+    service_number: &str,
+    service_job_id: Option<&str>,
+    service_pull_request: &str,
+    commit_sha: &str,
+    with_function_info: bool,
+    output: impl Write,
+    vcs_branch: &str,
+    parallel: bool,
+    demangle: bool,
+) -> Result<(), GrcovError> {
     let demangle_options = DemangleOptions::name_only();
     let mut source_files = Vec::new();
 
@@ -440,12 +605,12 @@ pub fn output_coveralls(
         }
 
         let mut branches = Vec::new();
-        for (line, taken) in &result.branches {
-            for (n, b_t) in taken.iter().enumerate() {
-                branches.push(*line);
+        for (line, counts) in &result.branches {
+            for (n, count) in counts.iter().enumerate() {
+                branches.push(u64::from(*line));
                 branches.push(0);
-                branches.push(n as u32);
-                branches.push(if *b_t { 1 } else { 0 });
+                branches.push(n as u64);
+                branches.push(*count);
             }
         }
 
@@ -498,15 +663,51 @@ pub fn output_coveralls(
         obj.insert("service_job_id".to_string(), json!(service_job_id));
     }
 
-    let mut writer = BufWriter::new(get_target_output_writable(output_file));
-    serde_json::to_writer(&mut writer, &result).unwrap();
+    let mut writer = BufWriter::new(output);
+    serde_json::to_writer(&mut writer, &result)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn output_files(results: CovResultIter, output_file: Option<&str>) -> Result<(), GrcovError> {
+    write_files(results, get_target_output_writable(output_file)?)
 }
 
-pub fn output_files(results: CovResultIter, output_file: Option<&str>) {
-    let mut writer = BufWriter::new(get_target_output_writable(output_file));
+pub fn write_files(results: CovResultIter, output: impl Write) -> Result<(), GrcovError> {
+    let mut writer = BufWriter::new(output);
     for (_, rel_path, _) in results {
-        writeln!(writer, "{}", rel_path.display()).unwrap();
+        writeln!(writer, "{}", rel_path.display())?;
     }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn output_html_single(
+    results: CovResultIter,
+    output_file: Option<&str>,
+    branch_enabled: bool,
+    demangle: bool,
+    options: &html::HtmlOptions,
+) -> Result<(), GrcovError> {
+    write_html_single(
+        results,
+        get_target_output_writable(output_file)?,
+        branch_enabled,
+        demangle,
+        options,
+    )
+}
+
+/// Writes the HTML report in a single self-contained file, which can be viewed offline.
+pub fn write_html_single(
+    results: CovResultIter,
+    output: impl Write,
+    branch_enabled: bool,
+    demangle: bool,
+    options: &html::HtmlOptions,
+) -> Result<(), GrcovError> {
+    let (tera, config) = html::get_config(options)?;
+    html::gen_single_file(&tera, &config, results, branch_enabled, demangle, output)
 }
 
 pub fn output_html(
@@ -514,7 +715,10 @@ pub fn output_html(
     output_dir: Option<&str>,
     num_threads: usize,
     branch_enabled: bool,
-) {
+    demangle: bool,
+    options: &html::HtmlOptions,
+) -> Result<(), GrcovError> {
+    let (tera, config) = html::get_config(options)?;
     let output = if let Some(output_dir) = output_dir {
         PathBuf::from(output_dir)
     } else {
@@ -523,19 +727,22 @@ pub fn output_html(
 
     if output.exists() {
         if !output.is_dir() {
-            eprintln!("{} is not a directory", output.to_str().unwrap());
-            return;
+            return Err(GrcovError::Output(format!(
+                "{} is not a directory",
+                output.display()
+            )));
         }
     } else if std::fs::create_dir(&output).is_err() {
-        eprintln!("Cannot create directory {}", output.to_str().unwrap());
-        return;
+        return Err(GrcovError::Output(format!(
+            "Cannot create directory {}",
+            output.display()
+        )));
     }
 
     let (sender, receiver) = unbounded();
 
     let stats = Arc::new(Mutex::new(HtmlGlobalStats::default()));
     let mut threads = Vec::with_capacity(num_threads);
-    let (tera, config) = html::get_config();
     for i in 0..num_threads {
         let receiver = receiver.clone();
         let output = output.clone();
@@ -545,9 +752,16 @@ pub fn output_html(
         let t = thread::Builder::new()
             .name(format!("Consumer HTML {}", i))
             .spawn(move || {
-                html::consumer_html(&tera, receiver, stats, &output, config, branch_enabled);
-            })
-            .unwrap();
+                html::consumer_html(
+                    &tera,
+                    receiver,
+                    stats,
+                    &output,
+                    config,
+                    branch_enabled,
+                    demangle,
+                );
+            })?;
 
         threads.push(t);
     }
@@ -568,25 +782,28 @@ pub fn output_html(
 
     for t in threads {
         if t.join().is_err() {
-            process::exit(1);
+            return Err(GrcovError::Other("An HTML thread panicked".to_string()));
         }
     }
 
     let global = Arc::try_unwrap(stats).unwrap().into_inner().unwrap();
 
     html::gen_index(&tera, &global, &config, &output, branch_enabled);
+    html::gen_stylesheet(&config, &output);
 
     for style in html::BadgeStyle::iter() {
         html::gen_badge(&tera, &global.stats, &config, &output, style);
     }
 
     html::gen_coverage_json(&global.stats, &config, &output);
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{collections::BTreeMap, path::Path};
+    use std::{collections::BTreeMap, fs, path::Path};
 
     fn read_file(path: &Path) -> String {
         let mut f =
@@ -610,16 +827,18 @@ mod tests {
                 branches: {
                     let mut map = BTreeMap::new();
                     // 3 hit branches over 10
-                    map.insert(1, vec![true, false, false, true, false, false]);
-                    map.insert(2, vec![false, false, false, true]);
+                    map.insert(1, vec![1, 0, 0, 1, 0, 0]);
+                    map.insert(2, vec![0, 0, 0, 1]);
                     map
                 },
                 functions: FxHashMap::default(),
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         )];
 
         let results = Box::new(results.into_iter());
-        output_lcov(results, Some(file_path.to_str().unwrap()), false);
+        output_lcov(results, Some(file_path.to_str().unwrap()), false).unwrap();
 
         let results = read_file(&file_path);
 
@@ -627,6 +846,200 @@ mod tests {
         assert!(results.contains("BRH:3\n"));
     }
 
+    #[test]
+    fn test_lcov_branch_counts() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_name = "test_lcov_branch_counts.info";
+        let file_path = tmp_dir.path().join(&file_name);
+
+        let results = vec![(
+            PathBuf::from("foo/bar/a.cpp"),
+            PathBuf::from("foo/bar/a.cpp"),
+            CovResult {
+                lines: [(1, 10), (2, 0)].iter().cloned().collect(),
+                branches: [(1, vec![7, 0]), (2, vec![0, 0])].iter().cloned().collect(),
+                functions: FxHashMap::default(),
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
+            },
+        )];
+
+        let results = Box::new(results.into_iter());
+        output_lcov(results, Some(file_path.to_str().unwrap()), false).unwrap();
+
+        let results = read_file(&file_path);
+
+        assert!(results.contains("BRDA:1,0,0,7\n"));
+        assert!(results.contains("BRDA:1,0,1,0\n"));
+        assert!(results.contains("BRDA:2,0,0,-\n"));
+        assert!(results.contains("BRDA:2,0,1,-\n"));
+        assert!(results.contains("BRH:1\n"));
+
+        // The counts are preserved when parsing the output again.
+        let parsed = crate::parse_lcov(results.into_bytes(), true).unwrap();
+        assert_eq!(
+            parsed[0].1.branches,
+            [(1, vec![7, 0]), (2, vec![0, 0])].iter().cloned().collect()
+        );
+    }
+
+    #[test]
+    fn test_write_lcov() {
+        let results = vec![(
+            PathBuf::from("foo/bar/a.cpp"),
+            PathBuf::from("foo/bar/a.cpp"),
+            CovResult {
+                lines: [(1, 10), (2, 0)].iter().cloned().collect(),
+                branches: BTreeMap::new(),
+                functions: FxHashMap::default(),
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
+            },
+        )];
+
+        let mut output = Vec::new();
+        write_lcov(Box::new(results.into_iter()), &mut output, false).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "TN:\nSF:foo/bar/a.cpp\nBRF:0\nBRH:0\nDA:1,10\nDA:2,0\nLF:2\nLH:1\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn test_write_lcov_contexts() {
+        let mut functions = FxHashMap::default();
+        functions.insert(
+            "main".to_string(),
+            Function {
+                start: 1,
+                end: None,
+                executed: true,
+                count: 3,
+            },
+        );
+        let mut result = CovResult {
+            lines: [(1, 3), (2, 1), (3, 0)].iter().cloned().collect(),
+            branches: BTreeMap::new(),
+            functions,
+            regions: BTreeMap::new(),
+            contexts: Contexts::default(),
+        };
+        result.contexts.lines.insert(
+            1,
+            [("a".to_string(), 1), ("b".to_string(), 2)]
+                .iter()
+                .cloned()
+                .collect(),
+        );
+        result
+            .contexts
+            .lines
+            .insert(2, [("b".to_string(), 1)].iter().cloned().collect());
+        result.contexts.functions.insert(
+            "main".to_string(),
+            [("b".to_string(), 3)].iter().cloned().collect(),
+        );
+        let results = vec![(PathBuf::from("a.c"), PathBuf::from("a.c"), result.clone())];
+
+        let mut output = Vec::new();
+        write_lcov(Box::new(results.into_iter()), &mut output, false).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "TN:\nSF:a.c\nFN:1,main\nFNDA:0,main\nFNF:1\nFNH:0\nBRF:0\nBRH:0\n\
+DA:1,0\nDA:2,0\nDA:3,0\nLF:3\nLH:0\nend_of_record\n\
+TN:a\nSF:a.c\nBRF:0\nBRH:0\nDA:1,1\nLF:1\nLH:1\nend_of_record\n\
+TN:b\nSF:a.c\nFN:1,main\nFNDA:3,main\nFNF:1\nFNH:1\nBRF:0\nBRH:0\n\
+DA:1,2\nDA:2,1\nLF:2\nLH:2\nend_of_record\n"
+        );
+
+        // The sections add up to the original result when parsing the output again.
+        let mut parsed = crate::parse_lcov(output.into_bytes(), false)
+            .unwrap()
+            .into_iter()
+            .map(|(_, result)| result);
+        let mut merged = parsed.next().unwrap();
+        for other in parsed {
+            crate::merge_results(&mut merged, other);
+        }
+        assert_eq!(merged, result);
+    }
+
+    #[test]
+    fn test_write_html_single() {
+        let results = vec![(
+            PathBuf::from("src/lib.rs"),
+            PathBuf::from("src/lib.rs"),
+            CovResult {
+                lines: [(1, 10), (2, 0)].iter().cloned().collect(),
+                ..Default::default()
+            },
+        )];
+
+        let mut output = Vec::new();
+        write_html_single(
+            Box::new(results.into_iter()),
+            &mut output,
+            false,
+            false,
+            &html::HtmlOptions::default(),
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        for path in &["index.html", "src&#x2F;index.html", "src&#x2F;lib.rs.html"] {
+            assert!(output.contains(&format!("data-path=\"{}\"", path)));
+        }
+        // The stylesheet is inlined and the pages are bundled without their own head.
+        assert!(output.contains(".has-background-success-light {"));
+        assert!(!output.contains("<link"));
+        assert_eq!(output.matches("<html").count(), 1);
+    }
+
+    #[test]
+    fn test_write_html_single_demangle() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let source = tmp_dir.path().join("lib.rs");
+        fs::write(&source, "fn main() {}\n").unwrap();
+        let mut functions: FunctionMap = FxHashMap::default();
+        functions.insert(
+            "_ZN4test4mainE".to_string(),
+            Function {
+                start: 1,
+                end: None,
+                executed: true,
+                count: 1,
+            },
+        );
+        let get_output = |demangle| {
+            let results = vec![(
+                source.clone(),
+                PathBuf::from("lib.rs"),
+                CovResult {
+                    lines: [(1, 1)].iter().cloned().collect(),
+                    functions: functions.clone(),
+                    ..Default::default()
+                },
+            )];
+            let mut output = Vec::new();
+            write_html_single(
+                Box::new(results.into_iter()),
+                &mut output,
+                false,
+                demangle,
+                &html::HtmlOptions::default(),
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let output = get_output(true);
+        assert!(output.contains("test::main"));
+        assert!(!output.contains("_ZN4test4mainE"));
+        assert!(get_output(false).contains("_ZN4test4mainE"));
+    }
+
     #[test]
     fn test_lcov_demangle() {
         let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
@@ -645,30 +1058,38 @@ mod tests {
                         "_RINvNtC3std3mem8align_ofNtNtC3std3mem12DiscriminantE".to_string(),
                         Function {
                             start: 1,
+                            end: None,
                             executed: true,
+                            count: 1,
                         },
                     );
                     map.insert(
                         "_ZN9wikipedia7article6formatEv".to_string(),
                         Function {
                             start: 2,
+                            end: None,
                             executed: true,
+                            count: 1,
                         },
                     );
                     map.insert(
                         "hello_world".to_string(),
                         Function {
                             start: 3,
+                            end: None,
                             executed: true,
+                            count: 1,
                         },
                     );
                     map
                 },
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         )];
 
         let results = Box::new(results.into_iter());
-        output_lcov(results, Some(file_path.to_str().unwrap()), true);
+        output_lcov(results, Some(file_path.to_str().unwrap()), true).unwrap();
 
         let results = read_file(&file_path);
 
@@ -691,6 +1112,8 @@ mod tests {
                     lines: [(1, 10), (2, 11)].iter().cloned().collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
                 },
             ),
             (
@@ -700,6 +1123,8 @@ mod tests {
                     lines: [(1, 0), (2, 10), (4, 0)].iter().cloned().collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
                 },
             ),
             (
@@ -709,6 +1134,8 @@ mod tests {
                     lines: [(1, 10), (4, 1)].iter().cloned().collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
                 },
             ),
             (
@@ -718,12 +1145,14 @@ mod tests {
                     lines: [(1, 10), (2, 0)].iter().cloned().collect(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
                 },
             ),
         ];
 
         let results = Box::new(results.into_iter());
-        output_covdir(results, Some(file_path.to_str().unwrap()));
+        output_covdir(results, Some(file_path.to_str().unwrap())).unwrap();
 
         let results: Value = serde_json::from_str(&read_file(&file_path)).unwrap();
         let expected_path = PathBuf::from("./test/").join(&file_name);
@@ -732,6 +1161,77 @@ mod tests {
         assert_eq!(results, expected);
     }
 
+    #[test]
+    fn test_covdir_functions_branches() {
+        let function = |executed| Function {
+            start: 1,
+            end: None,
+            executed,
+            count: executed as u64,
+        };
+        let results = vec![
+            (
+                PathBuf::from("foo/a.cpp"),
+                PathBuf::from("foo/a.cpp"),
+                CovResult {
+                    lines: [(1, 1), (2, 0)].iter().cloned().collect(),
+                    branches: [(1, vec![1, 0]), (2, vec![0, 0])].iter().cloned().collect(),
+                    functions: [("f".to_string(), function(true))]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    ..Default::default()
+                },
+            ),
+            (
+                PathBuf::from("foo/bar/b.cpp"),
+                PathBuf::from("foo/bar/b.cpp"),
+                CovResult {
+                    lines: [(1, 0)].iter().cloned().collect(),
+                    branches: [(1, vec![3, 1])].iter().cloned().collect(),
+                    functions: [
+                        ("g".to_string(), function(false)),
+                        ("h".to_string(), function(false)),
+                    ]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        let mut output = Vec::new();
+        write_covdir(Box::new(results.into_iter()), &mut output).unwrap();
+        let results: Value = serde_json::from_slice(&output).unwrap();
+
+        let check = |value: &Value, functions: [u64; 3], branches: [u64; 3], percents: [f64; 2]| {
+            assert_eq!(value["functionsTotal"], json!(functions[0]));
+            assert_eq!(value["functionsCovered"], json!(functions[1]));
+            assert_eq!(value["functionsMissed"], json!(functions[2]));
+            assert_eq!(value["functionsPercent"], json!(percents[0]));
+            assert_eq!(value["branchesTotal"], json!(branches[0]));
+            assert_eq!(value["branchesCovered"], json!(branches[1]));
+            assert_eq!(value["branchesMissed"], json!(branches[2]));
+            assert_eq!(value["branchesPercent"], json!(percents[1]));
+        };
+        let foo = &results["children"]["foo"];
+        check(&foo["children"]["a.cpp"], [1, 1, 0], [4, 1, 3], [100., 25.]);
+        check(
+            &foo["children"]["bar"]["children"]["b.cpp"],
+            [2, 0, 2],
+            [2, 2, 0],
+            [0., 100.],
+        );
+        check(&foo["children"]["bar"], [2, 0, 2], [2, 2, 0], [0., 100.]);
+        check(foo, [3, 1, 2], [6, 3, 3], [33.33, 50.]);
+        check(&results, [3, 1, 2], [6, 3, 3], [33.33, 50.]);
+        // The line stats are still there.
+        assert_eq!(results["linesTotal"], json!(3));
+        assert_eq!(results["linesCovered"], json!(1));
+        assert_eq!(results["coveragePercent"], json!(33.33));
+    }
+
     #[test]
     fn test_coveralls_service_job_id() {
         let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
@@ -745,6 +1245,8 @@ mod tests {
                 lines: [(1, 10), (2, 11)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         )];
 
@@ -765,7 +1267,8 @@ mod tests {
             "unused",
             parallel,
             false,
-        );
+        )
+        .unwrap();
 
         let results: Value = serde_json::from_str(&read_file(&file_path)).unwrap();
 
@@ -785,6 +1288,8 @@ mod tests {
                 lines: [(1, 10), (2, 11)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         )];
 
@@ -805,7 +1310,8 @@ mod tests {
             "unused",
             parallel,
             false,
-        );
+        )
+        .unwrap();
 
         let results: Value = serde_json::from_str(&read_file(&file_path)).unwrap();
 
@@ -825,6 +1331,8 @@ mod tests {
                 lines: [(1, 10), (2, 11)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         )];
 
@@ -846,7 +1354,8 @@ mod tests {
             "unused",
             parallel,
             false,
-        );
+        )
+        .unwrap();
 
         let results: Value = serde_json::from_str(&read_file(&file_path)).unwrap();
 
@@ -17,6 +17,7 @@ use quick_xml::Reader;
 use rustc_hash::FxHashMap;
 
 use crate::defs::*;
+use crate::set_context;
 
 #[derive(Debug)]
 pub enum ParserError {
@@ -58,6 +59,8 @@ impl fmt::Display for ParserError {
     }
 }
 
+impl std::error::Error for ParserError {}
+
 macro_rules! try_parse {
     ($v:expr, $l:expr) => {
         match $v.parse() {
@@ -101,7 +104,7 @@ fn remove_newline(l: &mut Vec<u8>) {
     }
 }
 
-pub fn add_branch(branches: &mut BTreeMap<u32, Vec<bool>>, line_no: u32, no: u32, taken: bool) {
+pub fn add_branch(branches: &mut BTreeMap<u32, Vec<u64>>, line_no: u32, no: u32, count: u64) {
     match branches.entry(line_no) {
         btree_map::Entry::Occupied(c) => {
             let v = c.into_mut();
@@ -109,16 +112,16 @@ pub fn add_branch(branches: &mut BTreeMap<u32, Vec<bool>>, line_no: u32, no: u32
             let no = no as usize;
 
             match no.cmp(&l) {
-                Ordering::Equal => v.push(taken),
+                Ordering::Equal => v.push(count),
                 Ordering::Greater => {
-                    v.extend(vec![false; no - l]);
-                    v.push(taken);
+                    v.extend(vec![0; no - l]);
+                    v.push(count);
                 }
-                Ordering::Less => v[no] |= taken,
+                Ordering::Less => v[no] = v[no].saturating_add(count),
             }
         }
         btree_map::Entry::Vacant(v) => {
-            v.insert(vec![taken; 1]);
+            v.insert(vec![count; 1]);
         }
     };
 }
@@ -131,6 +134,8 @@ pub fn parse_lcov(
     let mut cur_lines = BTreeMap::new();
     let mut cur_branches = BTreeMap::new();
     let mut cur_functions = FxHashMap::default();
+    // The name of the test, used as the context of the following records.
+    let mut cur_test_name = String::new();
 
     // We only log the duplicated FN error once per parse_lcov call.
     let mut duplicated_error_logged = false;
@@ -138,6 +143,7 @@ pub fn parse_lcov(
     let mut results = Vec::new();
     let iter = &mut buffer.iter().peekable();
 
+    const TN: u32 = (b'T' as u32) * (1 << 8) + (b'N' as u32);
     const SF: u32 = (b'S' as u32) * (1 << 8) + (b'F' as u32);
     const DA: u32 = (b'D' as u32) * (1 << 8) + (b'A' as u32);
     const FN: u32 = (b'F' as u32) * (1 << 8) + (b'N' as u32);
@@ -157,16 +163,21 @@ pub fn parse_lcov(
         match *c {
             b'e' => {
                 // we've a end_of_record
-                results.push((
-                    cur_file.unwrap(),
-                    CovResult {
-                        lines: cur_lines,
-                        branches: cur_branches,
-                        functions: cur_functions,
-                    },
-                ));
+                let file = cur_file.take().ok_or_else(|| {
+                    ParserError::InvalidRecord(format!("end_of_record without SF at line {}", line))
+                })?;
+                let mut result = CovResult {
+                    lines: cur_lines,
+                    branches: cur_branches,
+                    functions: cur_functions,
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
+                };
+                if !cur_test_name.is_empty() {
+                    set_context(&mut result, &cur_test_name);
+                }
+                results.push((file, result));
 
-                cur_file = None;
                 cur_lines = BTreeMap::new();
                 cur_branches = BTreeMap::new();
                 cur_functions = FxHashMap::default();
@@ -176,7 +187,7 @@ pub fn parse_lcov(
                 continue;
             }
             _ => {
-                if *c != b'S' && *c != b'D' && *c != b'F' && *c != b'B' {
+                if *c != b'S' && *c != b'D' && *c != b'F' && *c != b'B' && *c != b'T' {
                     iter.take_while(|&&c| c != b'\n').last();
                     continue;
                 }
@@ -185,6 +196,13 @@ pub fn parse_lcov(
                     .take_while(|&&c| c != b':')
                     .fold(*c as u32, |r, &x| r * (1 << 8) + u32::from(x));
                 match key {
+                    TN => {
+                        // TN:string
+                        cur_test_name = iter
+                            .take_while(|&&c| c != b'\n' && c != b'\r')
+                            .map(|&c| c as char)
+                            .collect();
+                    }
                     SF => {
                         // SF:string
                         cur_file = Some(
@@ -240,15 +258,17 @@ pub fn parse_lcov(
                             f_name,
                             Function {
                                 start,
+                                end: None,
                                 executed: false,
+                                count: 0,
                             },
                         );
                     }
                     FNDA => {
                         // FNDA:int,string
-                        let executed = iter
-                            .take_while(|&&c| c != b',')
-                            .fold(0, |r, &x| r * 10 + u64::from(x - b'0'));
+                        let count = iter.take_while(|&&c| c != b',').fold(0u64, |r, &x| {
+                            r.saturating_mul(10).saturating_add(u64::from(x - b'0'))
+                        });
                         if iter.peek().is_none() {
                             return Err(ParserError::InvalidRecord(format!(
                                 "FNDA at line {}",
@@ -260,7 +280,8 @@ pub fn parse_lcov(
                             .map(|&c| c as char)
                             .collect();
                         if let Some(f) = cur_functions.get_mut(&f_name) {
-                            f.executed |= executed != 0;
+                            f.executed |= count != 0;
+                            f.count = f.count.saturating_add(count);
                         } else {
                             return Err(ParserError::Parse(format!(
                                 "FN record missing for function {}",
@@ -298,10 +319,18 @@ pub fn parse_lcov(
                                     line
                                 )));
                             }
-                            let taken = iter
+                            // The count is "-" when the block was never executed.
+                            let taken: String = iter
                                 .take_while(|&&c| c != b'\n' && c != b'\r')
-                                .any(|&x| x != b'-');
-                            add_branch(&mut cur_branches, line_no, branch_number, taken);
+                                .map(|&c| c as char)
+                                .collect();
+                            let count = match taken.as_str() {
+                                "-" => 0,
+                                taken => taken.parse::<u64>().map_err(|_| {
+                                    ParserError::InvalidRecord(format!("BRDA at line {}", line))
+                                })?,
+                            };
+                            add_branch(&mut cur_branches, line_no, branch_number, count);
                         } else {
                             iter.take_while(|&&c| c != b'\n').last();
                         }
@@ -365,12 +394,12 @@ struct GcovFunction {
 }
 
 pub fn parse_gcov_gz(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, ParserError> {
-    let f = File::open(&gcov_path)
-        .unwrap_or_else(|_| panic!("Failed to open gcov file {}", gcov_path.display()));
+    let f = File::open(&gcov_path)?;
 
     let file = BufReader::new(&f);
     let gz = GzDecoder::new(file);
-    let mut gcov: GcovJson = serde_json::from_reader(gz).unwrap();
+    let mut gcov: GcovJson =
+        serde_json::from_reader(gz).map_err(|e| ParserError::InvalidData(e.to_string()))?;
     let mut results = Vec::new();
 
     if gcov.format_version != "1" {
@@ -388,7 +417,7 @@ pub fn parse_gcov_gz(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, Parse
             if !line.branches.is_empty() {
                 branches.insert(
                     line.line_number,
-                    line.branches.drain(..).map(|b| b.count > 0).collect(),
+                    line.branches.drain(..).map(|b| b.count).collect(),
                 );
             }
         }
@@ -401,7 +430,9 @@ pub fn parse_gcov_gz(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, Parse
                 fun.demangled_name,
                 Function {
                     start: fun.start_line,
+                    end: Some(fun.end_line),
                     executed: fun.execution_count > 0,
+                    count: fun.execution_count,
                 },
             );
         }
@@ -411,6 +442,8 @@ pub fn parse_gcov_gz(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, Parse
                 lines,
                 branches,
                 functions,
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         ));
     }
@@ -425,8 +458,7 @@ pub fn parse_gcov(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, ParserEr
     let mut cur_functions = FxHashMap::default();
     let mut results = Vec::new();
 
-    let f = File::open(&gcov_path)
-        .unwrap_or_else(|_| panic!("Failed to open gcov file {}", gcov_path.display()));
+    let f = File::open(&gcov_path)?;
 
     let mut file = BufReader::new(&f);
     let mut l = vec![];
@@ -456,6 +488,8 @@ pub fn parse_gcov(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, ParserEr
                             lines: cur_lines,
                             branches: cur_branches,
                             functions: cur_functions,
+                            regions: BTreeMap::new(),
+                            contexts: Contexts::default(),
                         },
                     ));
                 }
@@ -468,9 +502,17 @@ pub fn parse_gcov(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, ParserEr
             "function" => {
                 let mut f_splits = value.splitn(3, ',');
                 let start = try_parse_next!(f_splits, l);
-                let executed = try_next!(f_splits, l) != "0";
+                let count = try_parse_next!(f_splits, l);
                 let f_name = try_next!(f_splits, l);
-                cur_functions.insert(f_name.to_owned(), Function { start, executed });
+                cur_functions.insert(
+                    f_name.to_owned(),
+                    Function {
+                        start,
+                        end: None,
+                        executed: count > 0,
+                        count,
+                    },
+                );
             }
             "lcount" => {
                 let mut values = value.splitn(2, ',');
@@ -485,14 +527,19 @@ pub fn parse_gcov(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, ParserEr
             "branch" => {
                 let mut values = value.splitn(2, ',');
                 let line_no = try_parse_next!(values, l);
-                let taken = try_next!(values, l) == "taken";
+                // The intermediate format doesn't contain the number of times a branch was taken.
+                let count = if try_next!(values, l) == "taken" {
+                    1
+                } else {
+                    0
+                };
                 match cur_branches.entry(line_no) {
                     btree_map::Entry::Occupied(c) => {
                         let v = c.into_mut();
-                        v.push(taken);
+                        v.push(count);
                     }
                     btree_map::Entry::Vacant(p) => {
-                        p.insert(vec![taken; 1]);
+                        p.insert(vec![count; 1]);
                     }
                 }
             }
@@ -501,12 +548,15 @@ pub fn parse_gcov(gcov_path: &Path) -> Result<Vec<(String, CovResult)>, ParserEr
     }
 
     if !cur_lines.is_empty() {
+        let cur_file = cur_file.ok_or_else(|| ParserError::InvalidRecord("file".to_string()))?;
         results.push((
-            cur_file.unwrap(),
+            cur_file,
             CovResult {
                 lines: cur_lines,
                 branches: cur_branches,
                 functions: cur_functions,
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         ));
     }
@@ -536,7 +586,7 @@ fn parse_jacoco_report_sourcefile<T: BufRead>(
     buf: &mut Vec<u8>,
 ) -> Result<JacocoReport, ParserError> {
     let mut lines: BTreeMap<u32, u64> = BTreeMap::new();
-    let mut branches: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
+    let mut branches: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
 
     loop {
         match parser.read_event(buf) {
@@ -566,8 +616,9 @@ fn parse_jacoco_report_sourcefile<T: BufRead>(
 
                 if mb > 0 || cb > 0 {
                     // This line is a branch.
-                    let mut v = vec![true; cb as usize];
-                    v.extend(vec![false; mb as usize]);
+                    // JaCoCo does not feature branch counts, so we set them to 0 or 1.
+                    let mut v = vec![1; cb as usize];
+                    v.extend(vec![0; mb as usize]);
                     branches.insert(nr, v);
                 } else {
                     // This line is a statement.
@@ -610,7 +661,13 @@ fn parse_jacoco_report_method<T: BufRead>(
         buf.clear();
     }
 
-    Ok(Function { start, executed })
+    // JaCoCo does not feature call counts, so we set the count to 0 or 1.
+    Ok(Function {
+        start,
+        end: None,
+        executed,
+        count: if executed { 1 } else { 0 },
+    })
 }
 
 fn parse_jacoco_report_class<T: BufRead>(
@@ -677,6 +734,8 @@ fn parse_jacoco_report_package<T: BufRead>(
                                     functions,
                                     lines: BTreeMap::new(),
                                     branches: BTreeMap::new(),
+                                    regions: BTreeMap::new(),
+                                    contexts: Contexts::default(),
                                 });
                             }
                         };
@@ -698,6 +757,8 @@ fn parse_jacoco_report_package<T: BufRead>(
                                     functions: FxHashMap::default(),
                                     lines,
                                     branches,
+                                    regions: BTreeMap::new(),
+                                    contexts: Contexts::default(),
                                 });
                             }
                         };
@@ -869,10 +930,14 @@ mod tests {
         let func = result.functions.get("MainProcessSingleton").unwrap();
         assert_eq!(func.start, 15);
         assert!(func.executed);
+        assert_eq!(func.count, 1);
+        let func = result.functions.get("observe").unwrap();
+        assert_eq!(func.count, 2);
         assert!(result.functions.contains_key("logConsoleMessage"));
         let func = result.functions.get("logConsoleMessage").unwrap();
         assert_eq!(func.start, 21);
         assert!(!func.executed);
+        assert_eq!(func.count, 0);
     }
 
     #[test]
@@ -953,12 +1018,12 @@ mod tests {
         assert_eq!(
             result.branches,
             [
-                (34, vec![false, false]),
-                (41, vec![false, false]),
-                (44, vec![false, false]),
-                (60, vec![false, false]),
-                (63, vec![false, false]),
-                (68, vec![true, true])
+                (34, vec![0, 0]),
+                (41, vec![0, 0]),
+                (44, vec![0, 0]),
+                (60, vec![0, 0]),
+                (63, vec![0, 0]),
+                (68, vec![2, 1])
             ]
             .iter()
             .cloned()
@@ -1167,6 +1232,43 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_lcov_parser_BRDA_taken_count() {
+        let buf =
+            b"SF:a.c\nDA:1,1\nDA:2,0\nBRDA:1,0,0,12\nBRDA:1,0,1,0\nBRDA:2,0,0,-\nend_of_record\n";
+        let results = parse_lcov(buf.to_vec(), true).unwrap();
+        assert_eq!(
+            results[0].1.branches,
+            [(1, vec![12, 0]), (2, vec![0])].iter().cloned().collect()
+        );
+
+        for taken in &["1x2", "", "-1", "1.5"] {
+            let buf = format!("SF:a.c\nDA:1,1\nBRDA:1,0,0,{}\nend_of_record\n", taken);
+            let result = parse_lcov(buf.into_bytes(), true);
+            assert!(
+                matches!(result, Err(ParserError::InvalidRecord(_))),
+                "{}",
+                taken
+            );
+        }
+    }
+
+    #[test]
+    fn test_lcov_parser_test_names() {
+        let buf = b"TN:\nSF:a.c\nFN:1,main\nFNDA:1,main\nDA:1,1\nDA:2,0\nend_of_record\n\
+TN:unit\nSF:a.c\nFN:1,main\nFNDA:2,main\nDA:1,2\nDA:2,0\nend_of_record\n";
+        let results = parse_lcov(buf.to_vec(), false).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].1.contexts.is_empty());
+        let result = &results[1].1;
+        assert_eq!(result.lines, [(1, 2), (2, 0)].iter().cloned().collect());
+        assert_eq!(result.contexts.lines.len(), 1);
+        assert_eq!(result.contexts.lines[&1]["unit"], 2);
+        assert_eq!(result.contexts.functions["main"]["unit"], 2);
+    }
+
     #[test]
     fn test_parser() {
         let results = parse_gcov(Path::new("./test/prova.gcov")).unwrap();
@@ -1659,7 +1761,7 @@ mod tests {
 
         assert_eq!(
             result.branches,
-            [(399, vec![false, false]), (401, vec![true, false])]
+            [(399, vec![0, 0]), (401, vec![1, 0])]
                 .iter()
                 .cloned()
                 .collect()
@@ -1724,6 +1826,8 @@ mod tests {
             String::from("hello#<init>"),
             Function {
                 executed: false,
+                count: 0,
+                end: None,
                 start: 1,
             },
         );
@@ -1731,17 +1835,21 @@ mod tests {
             String::from("hello#main"),
             Function {
                 executed: true,
+                count: 1,
+                end: None,
                 start: 3,
             },
         );
-        let mut branches: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
-        branches.insert(3, vec![true, true]);
+        let mut branches: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
+        branches.insert(3, vec![1, 1]);
         let expected = vec![(
             String::from("hello.java"),
             CovResult {
                 lines,
                 branches,
                 functions,
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         )];
 
@@ -1780,15 +1888,25 @@ mod tests {
             ),
             ("Person#setAge", 22, false),
         ] {
-            functions.insert(String::from(name), Function { start, executed });
+            functions.insert(
+                String::from(name),
+                Function {
+                    start,
+                    end: None,
+                    executed,
+                    count: 0,
+                },
+            );
         }
-        let branches: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
+        let branches: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
         let expected = vec![(
             String::from("org/gradle/Person.java"),
             CovResult {
                 lines,
                 branches,
                 functions,
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         )];
 
@@ -0,0 +1,537 @@
+use rustc_hash::FxHashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::defs::*;
+use crate::error::GrcovError;
+use crate::output::get_target_output_writable;
+use crate::parser::ParserError;
+
+/// Lines added or modified by a patch, by the path of the file after the patch was applied.
+pub type ChangedLines = FxHashMap<PathBuf, BTreeSet<u32>>;
+
+/// Returns the path of a `---` or `+++` header, which may be followed by a tab and a timestamp
+/// (e.g. with `diff -u`).
+fn get_header_path(header: &str) -> &str {
+    header.split('\t').next().unwrap().trim_end()
+}
+
+fn get_target_path(header: &str, git_prefix: bool) -> Option<PathBuf> {
+    let path = get_header_path(header);
+    if path == "/dev/null" {
+        // The file was removed by the patch.
+        return None;
+    }
+    // Remove the prefix added by git, unless --no-prefix was used and `b/` is a directory.
+    let path = if git_prefix {
+        path.strip_prefix("b/").unwrap_or(path)
+    } else {
+        path
+    };
+    Some(PathBuf::from(path))
+}
+
+fn parse_range(range: &str, line: &str) -> Result<(u32, u32), ParserError> {
+    let mut values = range.splitn(2, ',');
+    let start = values
+        .next()
+        .unwrap()
+        .parse()
+        .map_err(|_| ParserError::InvalidRecord(line.to_string()))?;
+    let count = match values.next() {
+        Some(count) => count
+            .parse()
+            .map_err(|_| ParserError::InvalidRecord(line.to_string()))?,
+        None => 1,
+    };
+    Ok((start, count))
+}
+
+fn parse_hunk_header(line: &str) -> Result<(u32, u32, u32), ParserError> {
+    // @@ -old_start[,old_count] +new_start[,new_count] @@ [section heading]
+    let mut parts = line.split_whitespace().skip(1);
+    let (old, new) = match (parts.next(), parts.next()) {
+        (Some(old), Some(new)) if old.starts_with('-') && new.starts_with('+') => (old, new),
+        _ => return Err(ParserError::InvalidRecord(line.to_string())),
+    };
+    let (_, old_count) = parse_range(&old[1..], line)?;
+    let (new_start, new_count) = parse_range(&new[1..], line)?;
+    Ok((old_count, new_start, new_count))
+}
+
+/// Counts a line of a hunk against the number of lines given by its header.
+fn decrement(remaining: u32, line: &str) -> Result<u32, ParserError> {
+    remaining
+        .checked_sub(1)
+        .ok_or_else(|| ParserError::InvalidData(format!("Hunk longer than its header: {}", line)))
+}
+
+/// Parses a unified diff (as generated by `git diff` or `diff -u`) and returns the lines
+/// which were added or modified in every file.
+pub fn parse_unified_diff<T: BufRead>(reader: T) -> Result<ChangedLines, ParserError> {
+    let mut changed_lines = ChangedLines::default();
+    let mut cur_file: Option<PathBuf> = None;
+    let mut old_remaining = 0;
+    let mut new_remaining = 0;
+    let mut new_line = 0;
+    // Whether the paths have the `a/` and `b/` prefixes added by git.
+    let mut git_prefix = false;
+
+    for line in reader.lines() {
+        let line = line?;
+
+        if old_remaining > 0 || new_remaining > 0 {
+            // We are inside a hunk.
+            if line.starts_with('+') {
+                if let Some(ref cur_file) = cur_file {
+                    changed_lines
+                        .entry(cur_file.clone())
+                        .or_insert_with(BTreeSet::new)
+                        .insert(new_line);
+                }
+                new_line += 1;
+                new_remaining = decrement(new_remaining, &line)?;
+            } else if line.starts_with('-') {
+                old_remaining = decrement(old_remaining, &line)?;
+            } else if line.starts_with('\\') {
+                // "\ No newline at end of file"
+            } else {
+                // Context line, an empty context line might have lost its leading space.
+                new_line += 1;
+                new_remaining = decrement(new_remaining, &line)?;
+                old_remaining = decrement(old_remaining, &line)?;
+            }
+            continue;
+        }
+
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            git_prefix = paths.starts_with("a/");
+        } else if let Some(header) = line.strip_prefix("--- ") {
+            // The source of an added file is /dev/null, the "diff --git" line tells then.
+            let path = get_header_path(header);
+            if path != "/dev/null" {
+                git_prefix = path.starts_with("a/");
+            }
+        } else if let Some(header) = line.strip_prefix("+++ ") {
+            cur_file = get_target_path(header, git_prefix);
+        } else if line.starts_with("@@ ") {
+            let (old_count, new_start, new_count) = parse_hunk_header(&line)?;
+            old_remaining = old_count;
+            new_remaining = new_count;
+            new_line = new_start;
+        }
+    }
+
+    if old_remaining > 0 || new_remaining > 0 {
+        return Err(ParserError::InvalidData("Truncated hunk".to_string()));
+    }
+
+    Ok(changed_lines)
+}
+
+fn get_changed_lines<'a>(
+    changed_lines: &'a ChangedLines,
+    abs_path: &Path,
+    rel_path: &Path,
+) -> Option<&'a BTreeSet<u32>> {
+    if let Some(lines) = changed_lines.get(rel_path) {
+        return Some(lines);
+    }
+
+    // The paths in the diff are relative to the root of the repository, which might not be the
+    // source directory.
+    let repository_path = abs_path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").exists())
+        .and_then(|root| abs_path.strip_prefix(root).ok());
+    if let Some(lines) = repository_path.and_then(|path| changed_lines.get(path)) {
+        return Some(lines);
+    }
+
+    // Otherwise, the source directory is assumed to be in the repository: the path in the diff
+    // has to end with the relative path, the longest one matching the absolute path wins.
+    changed_lines
+        .iter()
+        .filter(|(path, _)| path.ends_with(rel_path) && abs_path.ends_with(path))
+        .max_by_key(|(path, _)| path.components().count())
+        .map(|(_, lines)| lines)
+}
+
+/// Only keeps the files touched by the patch and, in each of them, the lines, branches and
+/// functions which were added or modified.
+pub fn filter_changed_lines(results: CovResultIter, changed_lines: ChangedLines) -> CovResultIter {
+    Box::new(results.filter_map(move |(abs_path, rel_path, result)| {
+        let lines = get_changed_lines(&changed_lines, &abs_path, &rel_path)?;
+        let functions: FunctionMap = result
+            .functions
+            .into_iter()
+            .filter(|(_, function)| lines.contains(&function.start))
+            .collect();
+        let result = CovResult {
+            lines: result
+                .lines
+                .into_iter()
+                .filter(|(line, _)| lines.contains(line))
+                .collect(),
+            branches: result
+                .branches
+                .into_iter()
+                .filter(|(line, _)| lines.contains(line))
+                .collect(),
+            regions: result
+                .regions
+                .into_iter()
+                .filter(|(span, _)| {
+                    lines
+                        .range(span.start_line..=span.end_line)
+                        .next()
+                        .is_some()
+                })
+                .collect(),
+            contexts: Contexts {
+                lines: result
+                    .contexts
+                    .lines
+                    .into_iter()
+                    .filter(|(line, _)| lines.contains(line))
+                    .collect(),
+                functions: result
+                    .contexts
+                    .functions
+                    .into_iter()
+                    .filter(|(name, _)| functions.contains_key(name))
+                    .collect(),
+            },
+            functions,
+        };
+        Some((abs_path, rel_path, result))
+    }))
+}
+
+fn format_ranges(lines: &[u32]) -> String {
+    let mut ranges: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let start = lines[i];
+        let mut end = start;
+        while i + 1 < lines.len() && lines[i + 1] == end + 1 {
+            end += 1;
+            i += 1;
+        }
+        if start == end {
+            ranges.push(start.to_string());
+        } else {
+            ranges.push(format!("{}-{}", start, end));
+        }
+        i += 1;
+    }
+    ranges.join(", ")
+}
+
+/// Writes a summary of the coverage of a patch, the results are expected to be filtered with
+/// `filter_changed_lines` already.
+pub fn output_patch(results: CovResultIter, output_file: Option<&str>) -> Result<(), GrcovError> {
+    write_patch(results, get_target_output_writable(output_file)?)
+}
+
+pub fn write_patch(results: CovResultIter, output: impl Write) -> Result<(), GrcovError> {
+    let mut writer = BufWriter::new(output);
+
+    let mut total = 0;
+    let mut covered = 0;
+    let mut uncovered_lines = BTreeMap::new();
+    for (_, rel_path, result) in results {
+        total += result.lines.len();
+        covered += result.lines.values().filter(|&&c| c > 0).count();
+        let uncovered: Vec<u32> = result
+            .lines
+            .iter()
+            .filter(|&(_, &c)| c == 0)
+            .map(|(&l, _)| l)
+            .collect();
+        if !uncovered.is_empty() {
+            uncovered_lines.insert(rel_path, uncovered);
+        }
+    }
+
+    let percent = if total != 0 {
+        covered as f64 / total as f64 * 100.
+    } else {
+        100.
+    };
+    writeln!(
+        writer,
+        "Patch coverage: {}/{} lines ({:.2}%)",
+        covered, total, percent
+    )?;
+
+    if !uncovered_lines.is_empty() {
+        writeln!(writer, "\nUncovered changed lines:")?;
+        for (path, lines) in &uncovered_lines {
+            writeln!(writer, "{}: {}", path.display(), format_ranges(lines))?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::{Cursor, Read};
+
+    const DIFF: &str = "diff --git a/src/main.rs b/src/main.rs
+index 1111111..2222222 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,5 +1,6 @@
+ fn main() {
+-    let a = 1;
++    let a = 2;
++    let b = 3;
+     println!(\"{}\", a);
+
+ }
+@@ -10,3 +11,4 @@ fn foo() {
+ fn bar() {
++    baz();
+ }
+
+diff --git a/src/removed.rs b/src/removed.rs
+deleted file mode 100644
+--- a/src/removed.rs
++++ /dev/null
+@@ -1,2 +0,0 @@
+-fn removed() {
+-}
+diff --git a/src/new.rs b/src/new.rs
+new file mode 100644
+--- /dev/null
++++ b/src/new.rs
+@@ -0,0 +1,2 @@
++fn new() {
++}
+\\ No newline at end of file
+";
+
+    #[test]
+    fn test_parse_unified_diff() {
+        let changed_lines = parse_unified_diff(Cursor::new(DIFF)).unwrap();
+        assert_eq!(changed_lines.len(), 2);
+        assert_eq!(
+            changed_lines[&PathBuf::from("src/main.rs")],
+            [2, 3, 12].iter().cloned().collect()
+        );
+        assert_eq!(
+            changed_lines[&PathBuf::from("src/new.rs")],
+            [1, 2].iter().cloned().collect()
+        );
+    }
+
+    #[test]
+    fn test_parse_unified_diff_no_prefix() {
+        // With `git diff --no-prefix`, a top level `b` directory is kept.
+        let diff = "diff --git b/foo.c b/foo.c
+index 1111111..2222222 100644
+--- b/foo.c
++++ b/foo.c
+@@ -1,1 +1,2 @@
+ foo
++bar
+diff --git b/new.c b/new.c
+new file mode 100644
+--- /dev/null
++++ b/new.c
+@@ -0,0 +1,1 @@
++new
+";
+        let changed_lines = parse_unified_diff(Cursor::new(diff)).unwrap();
+        assert_eq!(
+            changed_lines[&PathBuf::from("b/foo.c")],
+            [2].iter().cloned().collect()
+        );
+        assert_eq!(
+            changed_lines[&PathBuf::from("b/new.c")],
+            [1].iter().cloned().collect()
+        );
+
+        // Same with a plain unified diff.
+        let diff = "--- b/foo.c\t2021-01-01\n+++ b/foo.c\t2021-01-02\n@@ -1,0 +1,1 @@\n+foo\n";
+        let changed_lines = parse_unified_diff(Cursor::new(diff)).unwrap();
+        assert!(changed_lines.contains_key(&PathBuf::from("b/foo.c")));
+    }
+
+    #[test]
+    fn test_parse_unified_diff_invalid_hunk() {
+        let diff = "--- a/foo.c\n+++ b/foo.c\n@@ -1,2 +a,2 @@\n";
+        assert!(parse_unified_diff(Cursor::new(diff)).is_err());
+
+        let diff = "--- a/foo.c\n+++ b/foo.c\n@@ -1,2 +1,3 @@\n+foo\n";
+        assert!(parse_unified_diff(Cursor::new(diff)).is_err());
+    }
+
+    #[test]
+    fn test_parse_unified_diff_malformed_hunk() {
+        // More added lines than the header says.
+        let diff = "--- a/foo.c\n+++ b/foo.c\n@@ -1,1 +1,1 @@\n+foo\n+bar\n-baz\n";
+        match parse_unified_diff(Cursor::new(diff)) {
+            Err(ParserError::InvalidData(_)) => {}
+            _ => panic!("Expected an InvalidData error"),
+        }
+
+        // More removed lines than the header says.
+        let diff = "--- a/foo.c\n+++ b/foo.c\n@@ -1,1 +1,2 @@\n-foo\n-bar\n+baz\n";
+        match parse_unified_diff(Cursor::new(diff)) {
+            Err(ParserError::InvalidData(_)) => {}
+            _ => panic!("Expected an InvalidData error"),
+        }
+
+        // A context line when only added lines are left.
+        let diff = "--- a/foo.c\n+++ b/foo.c\n@@ -1,0 +1,2 @@\n+foo\n bar\n";
+        match parse_unified_diff(Cursor::new(diff)) {
+            Err(ParserError::InvalidData(_)) => {}
+            _ => panic!("Expected an InvalidData error"),
+        }
+    }
+
+    fn region(start_line: u32, end_line: u32) -> RegionSpan {
+        RegionSpan {
+            start_line,
+            start_col: 1,
+            end_line,
+            end_col: 10,
+        }
+    }
+
+    #[test]
+    fn test_patch_coverage() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_path = tmp_dir.path().join("test_patch_coverage.txt");
+
+        let changed_lines = parse_unified_diff(Cursor::new(DIFF)).unwrap();
+        let results = vec![
+            (
+                PathBuf::from("/repo/src/main.rs"),
+                PathBuf::from("main.rs"),
+                CovResult {
+                    lines: [(1, 1), (2, 1), (3, 0), (4, 1), (12, 0)]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    branches: [(1, vec![1]), (3, vec![0, 1])].iter().cloned().collect(),
+                    functions: FxHashMap::default(),
+                    regions: [(region(1, 1), 1), (region(3, 5), 0), (region(4, 4), 1)]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    contexts: Contexts::default(),
+                },
+            ),
+            (
+                PathBuf::from("/repo/src/other.rs"),
+                PathBuf::from("other.rs"),
+                CovResult {
+                    lines: [(1, 0)].iter().cloned().collect(),
+                    branches: BTreeMap::new(),
+                    functions: FxHashMap::default(),
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
+                },
+            ),
+        ];
+
+        let results: Vec<_> =
+            filter_changed_lines(Box::new(results.into_iter()), changed_lines).collect();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].2.lines,
+            [(2, 1), (3, 0), (12, 0)].iter().cloned().collect()
+        );
+        assert_eq!(
+            results[0].2.branches,
+            [(3, vec![0, 1])].iter().cloned().collect()
+        );
+        // The regions touching a changed line are kept, for the highlighting of the HTML report.
+        assert_eq!(
+            results[0].2.regions,
+            [(region(3, 5), 0)].iter().cloned().collect()
+        );
+
+        output_patch(
+            Box::new(results.into_iter()),
+            Some(file_path.to_str().unwrap()),
+        )
+        .unwrap();
+
+        let mut output = String::new();
+        File::open(&file_path)
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(
+            output,
+            "Patch coverage: 1/3 lines (33.33%)\n\nUncovered changed lines:\nmain.rs: 3, 12\n"
+        );
+    }
+
+    #[test]
+    fn test_patch_same_file_names() {
+        let diff = "\
+--- a/lib.rs
++++ b/lib.rs
+@@ -1,0 +1,1 @@
++fn root() {}
+--- a/sub/lib.rs
++++ b/sub/lib.rs
+@@ -1,0 +2,1 @@
++fn sub() {}
+";
+        let changed_lines = parse_unified_diff(Cursor::new(diff)).unwrap();
+        let result = CovResult {
+            lines: [(1, 1), (2, 0)].iter().cloned().collect(),
+            ..Default::default()
+        };
+        let results = vec![
+            (
+                PathBuf::from("/repo/lib.rs"),
+                PathBuf::from("lib.rs"),
+                result.clone(),
+            ),
+            (
+                PathBuf::from("/repo/sub/lib.rs"),
+                PathBuf::from("sub/lib.rs"),
+                result.clone(),
+            ),
+            // Not touched by the diff, even if the root lib.rs has the same name.
+            (
+                PathBuf::from("/repo/other/lib.rs"),
+                PathBuf::from("other/lib.rs"),
+                result,
+            ),
+        ];
+
+        let results: Vec<_> =
+            filter_changed_lines(Box::new(results.into_iter()), changed_lines).collect();
+        let lines: Vec<_> = results
+            .iter()
+            .map(|(abs_path, _, result)| {
+                let lines: Vec<_> = result.lines.keys().cloned().collect();
+                (abs_path.to_str().unwrap(), lines)
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![("/repo/lib.rs", vec![1]), ("/repo/sub/lib.rs", vec![2]),]
+        );
+    }
+
+    #[test]
+    fn test_format_ranges() {
+        assert_eq!(format_ranges(&[]), "");
+        assert_eq!(format_ranges(&[1]), "1");
+        assert_eq!(format_ranges(&[1, 2, 3, 5, 7, 8]), "1-3, 5, 7-8");
+    }
+}
@@ -314,9 +314,11 @@ pub fn rewrite_paths(
                     crate::FilterType::Both(number) => {
                         result.branches.remove(&number);
                         result.lines.remove(&number);
+                        result.contexts.lines.remove(&number);
                     }
                     crate::FilterType::Line(number) => {
                         result.lines.remove(&number);
+                        result.contexts.lines.remove(&number);
                     }
                     crate::FilterType::Branch(number) => {
                         result.branches.remove(&number);
@@ -384,6 +386,8 @@ mod tests {
                 lines: BTreeMap::new(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             }
         }};
     }
@@ -394,6 +398,8 @@ mod tests {
                 lines: [(42, 1)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             }
         }};
     }
@@ -404,6 +410,8 @@ mod tests {
                 lines: [(42, 0)].iter().cloned().collect(),
                 branches: BTreeMap::new(),
                 functions: FxHashMap::default(),
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             }
         }};
     }
@@ -413,7 +421,7 @@ mod tests {
             let mut result = empty_result!();
             for i in 1..20 {
                 result.lines.insert(i, 1);
-                result.branches.insert(i, vec![true]);
+                result.branches.insert(i, vec![1]);
             }
             result
         }};
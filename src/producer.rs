@@ -9,6 +9,7 @@ use walkdir::WalkDir;
 use zip::ZipArchive;
 
 use crate::defs::*;
+use crate::error::GrcovError;
 
 #[derive(Debug)]
 pub enum ArchiveType {
@@ -60,6 +61,7 @@ impl Archive {
         profraws: &RefCell<FxHashMap<String, Vec<&'a Archive>>>,
         infos: &RefCell<FxHashMap<String, Vec<&'a Archive>>>,
         xmls: &RefCell<FxHashMap<String, Vec<&'a Archive>>>,
+        llvm_jsons: &RefCell<FxHashMap<String, Vec<&'a Archive>>>,
         linked_files_maps: &RefCell<FxHashMap<String, &'a Archive>>,
         is_llvm: bool,
     ) {
@@ -80,7 +82,7 @@ impl Archive {
                     let filename = clean_path(&path.with_extension(""));
                     self.insert_vec(filename, gcda_stem_archives);
                 }
-                "profraw" => {
+                "profraw" | "profdata" => {
                     let filename = clean_path(path);
                     self.insert_vec(filename, profraws);
                 }
@@ -101,6 +103,9 @@ impl Archive {
                     if filename == "linked-files-map.json" {
                         let filename = clean_path(path);
                         linked_files_maps.borrow_mut().insert(filename, self);
+                    } else if Archive::check_file(file, &Archive::is_llvm_cov_json) {
+                        let filename = clean_path(path);
+                        self.insert_vec(filename, llvm_jsons);
                     }
                 }
                 _ => {}
@@ -126,6 +131,11 @@ impl Archive {
         false
     }
 
+    fn is_llvm_cov_json(reader: &mut dyn Read) -> bool {
+        let mut bytes: [u8; 9] = [0; 9];
+        reader.read_exact(&mut bytes).is_ok() && &bytes == b"{\"data\":["
+    }
+
     fn is_info(reader: &mut dyn Read) -> bool {
         let mut bytes: [u8; 3] = [0; 3];
         reader.read_exact(&mut bytes).is_ok()
@@ -147,6 +157,7 @@ impl Archive {
         profraws: &RefCell<FxHashMap<String, Vec<&'a Archive>>>,
         infos: &RefCell<FxHashMap<String, Vec<&'a Archive>>>,
         xmls: &RefCell<FxHashMap<String, Vec<&'a Archive>>>,
+        llvm_jsons: &RefCell<FxHashMap<String, Vec<&'a Archive>>>,
         linked_files_maps: &RefCell<FxHashMap<String, &'a Archive>>,
         is_llvm: bool,
     ) {
@@ -164,6 +175,7 @@ impl Archive {
                         profraws,
                         infos,
                         xmls,
+                        llvm_jsons,
                         linked_files_maps,
                         is_llvm,
                     );
@@ -185,6 +197,7 @@ impl Archive {
                             profraws,
                             infos,
                             xmls,
+                            llvm_jsons,
                             linked_files_maps,
                             is_llvm,
                         );
@@ -203,6 +216,7 @@ impl Archive {
                         profraws,
                         infos,
                         xmls,
+                        llvm_jsons,
                         linked_files_maps,
                         is_llvm,
                     );
@@ -404,6 +418,10 @@ fn profraw_producer(
     for (name, archives) in profraws {
         let path = PathBuf::from(name);
         let stem = clean_path(&path.with_extension(""));
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("profraw");
 
         // TODO: If there is only one archive and it is not a zip, we don't need to "extract".
 
@@ -417,7 +435,8 @@ fn profraw_producer(
             let profraw_path = if let Some(profraw_path) = profraw_path {
                 profraw_path
             } else {
-                let tmp_path = tmp_dir.join(format!("{}_{}.profraw", stem, num + 1));
+                // Keep the extension, as the indexed profiles are read differently.
+                let tmp_path = tmp_dir.join(format!("{}_{}.{}", stem, num + 1, ext));
                 archive.extract(name, &tmp_path);
                 tmp_path
             };
@@ -463,19 +482,26 @@ pub fn get_mapping(linked_files_maps: &FxHashMap<String, &Archive>) -> Option<Ve
     }
 }
 
-fn open_archive(path: &str) -> ZipArchive<BufReader<File>> {
-    let file = File::open(&path).unwrap_or_else(|_| panic!("Failed to open ZIP file '{}'.", path));
+fn open_archive(path: &str) -> Result<ZipArchive<BufReader<File>>, GrcovError> {
+    let file = File::open(&path).map_err(|e| {
+        GrcovError::Input(path.to_string(), format!("Failed to open ZIP file: {}", e))
+    })?;
     let reader = BufReader::new(file);
-    ZipArchive::new(reader).unwrap_or_else(|_| panic!("Failed to parse ZIP file: {}", path))
+    ZipArchive::new(reader).map_err(|e| {
+        GrcovError::Input(path.to_string(), format!("Failed to parse ZIP file: {}", e))
+    })
 }
 
+/// Explores the inputs and sends the work items to the consumers. The inputs which can't be
+/// opened or aren't coverage files are added to the failures and skipped.
 pub fn producer(
     tmp_dir: &Path,
     paths: &[String],
     sender: &JobSender,
     ignore_orphan_gcno: bool,
     is_llvm: bool,
-) -> Option<Vec<u8>> {
+    failures: &mut Vec<GrcovError>,
+) -> Result<Option<Vec<u8>>, GrcovError> {
     let mut archives: Vec<Archive> = Vec::new();
     let mut plain_files: Vec<PathBuf> = Vec::new();
 
@@ -483,7 +509,13 @@ pub fn producer(
 
     for path in paths {
         if path.ends_with(".zip") {
-            let archive = open_archive(path);
+            let archive = match open_archive(path) {
+                Ok(archive) => archive,
+                Err(e) => {
+                    failures.push(e);
+                    continue;
+                }
+            };
             archives.push(Archive {
                 name: path.to_string(),
                 item: RefCell::new(ArchiveType::Zip(RefCell::new(archive))),
@@ -501,17 +533,26 @@ pub fn producer(
                     item: RefCell::new(ArchiveType::Dir(full_path)),
                 });
             } else if let Some(ext) = full_path.clone().extension() {
-                let ext = ext.to_str().unwrap();
-                if ext == "info" || ext == "json" || ext == "xml" || ext == "profraw" {
+                let ext = ext.to_str().unwrap_or_default();
+                if ext == "info"
+                    || ext == "json"
+                    || ext == "xml"
+                    || ext == "profraw"
+                    || ext == "profdata"
+                {
                     plain_files.push(full_path);
                 } else {
-                    panic!(
-                        "Cannot load file '{:?}': it isn't a .info, a .json or a .xml file.",
-                        full_path
-                    );
+                    failures.push(GrcovError::Input(
+                        full_path.display().to_string(),
+                        "it isn't a .info, a .json, a .xml, a .profraw or a .profdata file"
+                            .to_string(),
+                    ));
                 }
             } else {
-                panic!("Cannot load file '{:?}': it isn't a directory, a .info, a .json or a .xml file.", full_path);
+                failures.push(GrcovError::Input(
+                    full_path.display().to_string(),
+                    "it isn't a directory, a .info, a .json, a .xml, a .profraw or a .profdata file".to_string(),
+                ));
             }
         }
     }
@@ -530,6 +571,7 @@ pub fn producer(
     let profraws: RefCell<FxHashMap<String, Vec<&Archive>>> = RefCell::new(FxHashMap::default());
     let infos: RefCell<FxHashMap<String, Vec<&Archive>>> = RefCell::new(FxHashMap::default());
     let xmls: RefCell<FxHashMap<String, Vec<&Archive>>> = RefCell::new(FxHashMap::default());
+    let llvm_jsons: RefCell<FxHashMap<String, Vec<&Archive>>> = RefCell::new(FxHashMap::default());
     let linked_files_maps: RefCell<FxHashMap<String, &Archive>> =
         RefCell::new(FxHashMap::default());
 
@@ -540,21 +582,24 @@ pub fn producer(
             &profraws,
             &infos,
             &xmls,
+            &llvm_jsons,
             &linked_files_maps,
             is_llvm,
         );
     }
 
-    assert!(
-        !(gcno_stems_archives.borrow().is_empty()
-            && profraws.borrow().is_empty()
-            && infos.borrow().is_empty()
-            && xmls.borrow().is_empty()),
-        "No input files found"
-    );
+    if gcno_stems_archives.borrow().is_empty()
+        && profraws.borrow().is_empty()
+        && infos.borrow().is_empty()
+        && xmls.borrow().is_empty()
+        && llvm_jsons.borrow().is_empty()
+    {
+        return Err(GrcovError::NoInputFiles);
+    }
 
     file_content_producer(&infos.into_inner(), sender, ItemFormat::Info);
     file_content_producer(&xmls.into_inner(), sender, ItemFormat::JacocoXml);
+    file_content_producer(&llvm_jsons.into_inner(), sender, ItemFormat::LlvmJson);
     profraw_producer(tmp_dir, &profraws.into_inner(), sender);
     gcno_gcda_producer(
         tmp_dir,
@@ -564,7 +609,7 @@ pub fn producer(
         ignore_orphan_gcno,
     );
 
-    get_mapping(&linked_files_maps.into_inner())
+    Ok(get_mapping(&linked_files_maps.into_inner()))
 }
 
 #[cfg(test)]
@@ -658,7 +703,15 @@ mod tests {
 
         let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
         let tmp_path = tmp_dir.path().to_owned();
-        let mapping = producer(&tmp_path, &["test".to_string()], &sender, false, false);
+        let mapping = producer(
+            &tmp_path,
+            &["test".to_string()],
+            &sender,
+            false,
+            false,
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "Platform_1.gcno", true),
@@ -723,6 +776,8 @@ mod tests {
                 "relative_path/relative_path.info",
                 false,
             ),
+            (ItemFormat::Info, false, "llvm-cov/sample.info", false),
+            (ItemFormat::LlvmJson, false, "llvm-cov/sample.json", false),
             (ItemFormat::Gcno, false, "llvm/file", true),
             (ItemFormat::Gcno, false, "llvm/file_branch", true),
             (ItemFormat::Gcno, false, "llvm/reader", true),
@@ -778,7 +833,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "RootAccessibleWrap_1.gcno", true),
@@ -801,7 +858,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![(ItemFormat::Gcno, true, "main_1.gcno", true)];
 
@@ -821,7 +880,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "main_1.gcno", true),
@@ -844,7 +905,9 @@ mod tests {
             &sender,
             true,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![(ItemFormat::Gcno, true, "main_1.gcno", true)];
 
@@ -867,7 +930,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "Platform_1.gcno", true),
@@ -918,7 +983,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "Platform_1.gcno", true),
@@ -977,7 +1044,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "Platform_1.gcno", true),
@@ -1019,7 +1088,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "Platform_1.gcno", true),
@@ -1068,7 +1139,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Profraw, true, "default_1.profraw", false),
@@ -1091,7 +1164,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Info, false, "1494603967-2977-2_0.info", true),
@@ -1127,7 +1202,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (
@@ -1160,7 +1237,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (
@@ -1205,7 +1284,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "Platform_1.gcno", true),
@@ -1257,7 +1338,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![(ItemFormat::Gcno, true, "main_1.gcno", false)];
 
@@ -1282,7 +1365,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![(ItemFormat::Gcno, true, "main_1.gcno", true)];
 
@@ -1304,7 +1389,49 @@ mod tests {
             &sender,
             false,
             false,
+            &mut Vec::new(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_producer_invalid_zip() {
+        let (sender, _) = unbounded();
+        let mut failures = Vec::new();
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        let zip_path = tmp_path.join("corrupt.zip");
+        fs::write(&zip_path, b"not a zip file").unwrap();
+        let result = producer(
+            &tmp_path,
+            &[zip_path.to_str().unwrap().to_string()],
+            &sender,
+            false,
+            false,
+            &mut failures,
         );
+        // The input is skipped, which leaves nothing to parse.
+        assert!(matches!(result, Err(GrcovError::NoInputFiles)));
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(failures[0], GrcovError::Input(..)));
+    }
+
+    #[test]
+    fn test_producer_no_input_files() {
+        let (sender, _) = unbounded();
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        let result = producer(
+            &tmp_path,
+            &[tmp_path.to_str().unwrap().to_string()],
+            &sender,
+            false,
+            false,
+            &mut Vec::new(),
+        );
+        assert!(matches!(result, Err(GrcovError::NoInputFiles)));
     }
 
     // Test extracting gcno/gcda archives, where a gcno file exist with no matching gcda file.
@@ -1320,7 +1447,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "Platform_1.gcno", false),
@@ -1362,7 +1491,9 @@ mod tests {
             &sender,
             false,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "Platform_1.gcno", false),
@@ -1408,7 +1539,9 @@ mod tests {
             &sender,
             true,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "nsMaiInterfaceValue_1.gcno", true),
@@ -1442,7 +1575,9 @@ mod tests {
             &sender,
             true,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![
             (ItemFormat::Gcno, true, "nsMaiInterfaceValue_1.gcno", true),
@@ -1484,7 +1619,9 @@ mod tests {
             &sender,
             true,
             true,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
         let gcno_buf: Vec<u8> = vec![
             111, 110, 99, 103, 42, 50, 48, 52, 74, 200, 254, 66, 0, 0, 0, 1, 9, 0, 0, 0, 0, 0, 0,
             0, 236, 217, 93, 255, 2, 0, 0, 0, 109, 97, 105, 110, 0, 0, 0, 0, 2, 0, 0, 0, 102, 105,
@@ -1538,7 +1675,9 @@ mod tests {
             &sender,
             true,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         assert!(mapping.is_some());
         let mapping = mapping.unwrap();
@@ -1568,7 +1707,9 @@ mod tests {
             &sender,
             true,
             false,
-        );
+            &mut Vec::new(),
+        )
+        .unwrap();
 
         let expected = vec![(ItemFormat::Profraw, true, "default.profraw", false)];
 
@@ -1576,35 +1717,66 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    fn test_plain_profdata_producer() {
+        let (sender, receiver) = unbounded();
+
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let tmp_path = tmp_dir.path().to_owned();
+        producer(
+            &tmp_path,
+            &["test/llvm-cov/sample.profdata".to_string()],
+            &sender,
+            true,
+            false,
+            &mut Vec::new(),
+        )
+        .unwrap();
+
+        let expected = vec![(ItemFormat::Profraw, true, "sample.profdata", false)];
+
+        check_produced(PathBuf::from("test/llvm-cov"), &receiver, expected);
+    }
+
+    #[test]
     fn test_plain_producer_with_gcno() {
         let (sender, _) = unbounded();
+        let mut failures = Vec::new();
 
         let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
         let tmp_path = tmp_dir.path().to_owned();
-        producer(
+        let result = producer(
             &tmp_path,
             &["sub2/RootAccessibleWrap_1.gcno".to_string()],
             &sender,
             true,
             false,
+            &mut failures,
         );
+        // The input is skipped, which leaves nothing to parse.
+        assert!(matches!(result, Err(GrcovError::NoInputFiles)));
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(failures[0], GrcovError::Input(..)));
     }
 
     #[test]
-    #[should_panic]
     fn test_plain_producer_with_gcda() {
         let (sender, _) = unbounded();
+        let mut failures = Vec::new();
 
         let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
         let tmp_path = tmp_dir.path().to_owned();
-        producer(
+        let result = producer(
             &tmp_path,
             &["./test/llvm/file.gcda".to_string()],
             &sender,
             true,
             false,
+            &mut failures,
         );
+        // The input is skipped, which leaves nothing to parse.
+        assert!(matches!(result, Err(GrcovError::NoInputFiles)));
+        assert_eq!(failures.len(), 1);
+        assert!(matches!(failures[0], GrcovError::Input(..)));
     }
 
     #[test]
@@ -0,0 +1,327 @@
+use md5::{Digest, Md5};
+use rustc_hash::FxHashMap;
+use std::convert::TryInto;
+
+// Reader for the indexed profile format written by `llvm-profdata merge`, see
+// https://github.com/llvm/llvm-project/blob/main/llvm/lib/ProfileData/InstrProfReader.cpp
+
+const INDEXED_MAGIC: u64 = 0x8169_666f_7270_6cff;
+const MIN_SUPPORTED_VERSION: u64 = 2;
+const MAX_SUPPORTED_VERSION: u64 = 13;
+
+/// Computes the hash LLVM uses to refer to function names and filename lists, i.e. the lower
+/// 64 bits of their MD5 digest.
+pub fn compute_llvm_hash(data: &[u8]) -> u64 {
+    let digest = Md5::digest(data);
+    u64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+pub(crate) struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(data: &'a [u8], big_endian: bool) -> Self {
+        ByteReader {
+            data,
+            pos: 0,
+            big_endian,
+        }
+    }
+
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub(crate) fn seek(&mut self, pos: usize) -> Result<(), String> {
+        if pos > self.data.len() {
+            return Err(format!("Unexpected end of data (offset {})", pos));
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    pub(crate) fn align(&mut self, alignment: usize) -> Result<(), String> {
+        let rem = self.pos % alignment;
+        if rem != 0 {
+            let pos = (self.pos + alignment - rem).min(self.data.len());
+            self.seek(pos)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| format!("Unexpected end of data (offset {})", self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, String> {
+        let bytes = self.read_bytes(2)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, String> {
+        let bytes = self.read_bytes(4)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    pub(crate) fn read_u64(&mut self) -> Result<u64, String> {
+        let bytes = self.read_bytes(8)?.try_into().unwrap();
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    pub(crate) fn read_uleb128(&mut self) -> Result<u64, String> {
+        let mut result = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift >= 64 {
+                return Err("Invalid LEB128 value".to_string());
+            }
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+}
+
+/// The counters of a function, as recorded in a profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileRecord {
+    pub name: String,
+    pub hash: u64,
+    pub counts: Vec<u64>,
+}
+
+/// The content of an indexed profile (`.profdata` file).
+#[derive(Debug, Default)]
+pub struct IndexedProfile {
+    // Records by the hash of the function name, a function might have several records with
+    // different structural hashes.
+    records: FxHashMap<u64, Vec<ProfileRecord>>,
+}
+
+impl IndexedProfile {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut reader = ByteReader::new(data, false);
+        if reader.read_u64()? != INDEXED_MAGIC {
+            return Err("Not an indexed profile".to_string());
+        }
+        // The higher bits contain flags describing the kind of profile.
+        let version = reader.read_u64()? & 0xffff_ffff;
+        if !(MIN_SUPPORTED_VERSION..=MAX_SUPPORTED_VERSION).contains(&version) {
+            return Err(format!("Unsupported indexed profile version {}", version));
+        }
+        let _unused = reader.read_u64()?;
+        let _hash_type = reader.read_u64()?;
+        let hash_offset = reader.read_u64()? as usize;
+
+        // The function records are stored in an on-disk chained hash table, we don't need to
+        // look them up by hash so we just go through all the buckets.
+        reader.seek(hash_offset)?;
+        let num_buckets = reader.read_u64()?;
+        let _num_entries = reader.read_u64()?;
+        let mut bucket_offsets = Vec::new();
+        for _ in 0..num_buckets {
+            let offset = reader.read_u64()? as usize;
+            if offset != 0 {
+                bucket_offsets.push(offset);
+            }
+        }
+
+        let mut records: FxHashMap<u64, Vec<ProfileRecord>> = FxHashMap::default();
+        for offset in bucket_offsets {
+            reader.seek(offset)?;
+            let num_items = reader.read_u16()?;
+            for _ in 0..num_items {
+                let _hash = reader.read_u64()?;
+                let key_len = reader.read_u64()? as usize;
+                let data_len = reader.read_u64()? as usize;
+                let name = reader.read_bytes(key_len)?;
+                let data = reader.read_bytes(data_len)?;
+
+                let name_hash = compute_llvm_hash(name);
+                let name = String::from_utf8_lossy(name).into_owned();
+                let entry = records.entry(name_hash).or_default();
+                for (hash, counts) in Self::parse_records(data, version)? {
+                    entry.push(ProfileRecord {
+                        name: name.clone(),
+                        hash,
+                        counts,
+                    });
+                }
+            }
+        }
+
+        Ok(IndexedProfile { records })
+    }
+
+    fn parse_records(data: &[u8], version: u64) -> Result<Vec<(u64, Vec<u64>)>, String> {
+        let mut reader = ByteReader::new(data, false);
+        let mut records = Vec::new();
+        while !reader.is_empty() {
+            let hash = reader.read_u64()?;
+            let num_counts = reader.read_u64()?;
+            let mut counts = Vec::with_capacity(num_counts.min(data.len() as u64) as usize);
+            for _ in 0..num_counts {
+                counts.push(reader.read_u64()?);
+            }
+            if version >= 11 {
+                // MC/DC bitmap, each byte is stored as a 64-bit value.
+                let num_bitmap_bytes = reader.read_u64()? as usize;
+                reader.read_bytes(num_bitmap_bytes.saturating_mul(8))?;
+            }
+            if version >= 3 {
+                // Value profiling data, prefixed by its total size.
+                let start = reader.pos();
+                let total_size = reader.read_u32()? as usize;
+                reader.seek(start + total_size)?;
+            }
+            records.push((hash, counts));
+        }
+        Ok(records)
+    }
+
+    /// Adds the counters of another profile to this one, as `llvm-profdata merge` does.
+    pub fn merge(&mut self, other: IndexedProfile) {
+        for (name_hash, other_records) in other.records {
+            let records = self.records.entry(name_hash).or_default();
+            for other_record in other_records {
+                match records.iter_mut().find(|r| r.hash == other_record.hash) {
+                    Some(record) if record.counts.len() == other_record.counts.len() => {
+                        for (count, other_count) in
+                            record.counts.iter_mut().zip(other_record.counts)
+                        {
+                            *count = count.saturating_add(other_count);
+                        }
+                    }
+                    // The same function can't have different numbers of counters for the same
+                    // structural hash, keep the first record like llvm-profdata.
+                    Some(_) => {}
+                    None => records.push(other_record),
+                }
+            }
+        }
+    }
+
+    /// Returns the record of a function given the hash of its name and its structural hash.
+    pub fn get(&self, name_hash: u64, hash: u64) -> Result<Option<&ProfileRecord>, String> {
+        match self.records.get(&name_hash) {
+            Some(records) => match records.iter().find(|r| r.hash == hash) {
+                Some(record) => Ok(Some(record)),
+                None => Err(format!("Function hash mismatch for {}", records[0].name)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the name of a function given the hash of its name, if it is in the profile.
+    pub fn get_name(&self, name_hash: u64) -> Option<&str> {
+        self.records
+            .get(&name_hash)
+            .map(|records| records[0].name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_uleb128() {
+        let data = [0xe5, 0x8e, 0x26, 0x02];
+        let mut reader = ByteReader::new(&data, false);
+        assert_eq!(reader.read_uleb128(), Ok(624_485));
+        assert_eq!(reader.read_uleb128(), Ok(2));
+        assert!(reader.read_uleb128().is_err());
+    }
+
+    #[test]
+    fn test_compute_llvm_hash() {
+        // MD5("main") = fad58de7366495db4650cfefac2fcd61
+        assert_eq!(compute_llvm_hash(b"main"), 0xdb95_6436_e78d_d5fa);
+    }
+
+    #[test]
+    fn test_indexed_profile() {
+        let data = fs::read("test/llvm-cov/sample.profdata").unwrap();
+        let profile = IndexedProfile::parse(&data).unwrap();
+        assert_eq!(profile.len(), 4);
+
+        let name_hash = compute_llvm_hash(b"_RNvCs32gj3y4fnmh_4main4main");
+        assert_eq!(
+            profile.get_name(name_hash),
+            Some("_RNvCs32gj3y4fnmh_4main4main")
+        );
+        let record = &profile.records[&name_hash][0];
+        assert_eq!(profile.get(name_hash, record.hash), Ok(Some(record)));
+        assert!(profile.get(name_hash, record.hash + 1).is_err());
+        assert_eq!(profile.get(compute_llvm_hash(b"unknown"), 0), Ok(None));
+    }
+
+    #[test]
+    fn test_indexed_profile_merge() {
+        let data = fs::read("test/llvm-cov/sample.profdata").unwrap();
+        let mut profile = IndexedProfile::parse(&data).unwrap();
+        profile.merge(IndexedProfile::parse(&data).unwrap());
+        assert_eq!(profile.len(), 4);
+
+        let single = IndexedProfile::parse(&data).unwrap();
+        for (name_hash, records) in &profile.records {
+            for (record, single_record) in records.iter().zip(&single.records[name_hash]) {
+                let doubled: Vec<u64> = single_record.counts.iter().map(|c| c * 2).collect();
+                assert_eq!(record.counts, doubled);
+            }
+        }
+
+        let mut empty = IndexedProfile::default();
+        empty.merge(single);
+        assert_eq!(empty.len(), 4);
+    }
+
+    #[test]
+    fn test_indexed_profile_invalid() {
+        assert!(IndexedProfile::parse(b"not a profile").is_err());
+        let data = fs::read("test/default.profraw").unwrap();
+        assert!(IndexedProfile::parse(&data).is_err());
+    }
+}
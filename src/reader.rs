@@ -11,7 +11,7 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::result::Result;
 
-use crate::defs::{CovResult, Function};
+use crate::defs::{Contexts, CovResult, Function};
 
 const GCOV_ARC_ON_TREE: u32 = 1 << 0;
 const GCOV_ARC_FAKE: u32 = 1 << 1;
@@ -858,13 +858,27 @@ impl Gcno {
                     lines: BTreeMap::new(),
                     branches: BTreeMap::new(),
                     functions: FxHashMap::default(),
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
                 }),
             };
             res.functions.insert(
                 fun.name.clone(),
                 Function {
                     start: fun.start_line,
+                    // The end line is only available since GCC 8.
+                    end: if fun.end_line > 0 {
+                        Some(fun.end_line)
+                    } else {
+                        None
+                    },
                     executed: fun.executed,
+                    // The first edge goes out of the entry block.
+                    count: if fun.executed {
+                        fun.edges.first().map_or(0, |edge| edge.counter)
+                    } else {
+                        0
+                    },
                 },
             );
             if fun.executed {
@@ -899,28 +913,30 @@ impl Gcno {
                         continue;
                     }
 
-                    let taken: Vec<_> = block
+                    let counts: Vec<_> = block
                         .destination
                         .iter()
                         .filter_map(|no| {
                             let edge = &fun.edges[*no];
                             if edge.is_fake() {
                                 None
+                            } else if fun.executed {
+                                Some(edge.counter)
                             } else {
-                                Some(fun.executed && edge.counter > 0)
+                                Some(0)
                             }
                         })
                         .collect();
-                    if taken.len() <= 1 {
+                    if counts.len() <= 1 {
                         continue;
                     }
                     match res.branches.entry(line) {
                         btree_map::Entry::Occupied(c) => {
                             let v = c.into_mut();
-                            v.extend_from_slice(&taken);
+                            v.extend_from_slice(&counts);
                         }
                         btree_map::Entry::Vacant(p) => {
-                            p.insert(taken);
+                            p.insert(counts);
                         }
                     }
                 }
@@ -1405,16 +1421,20 @@ mod tests {
             String::from("main"),
             Function {
                 start: 1,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
-        let branches: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
+        let branches: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
         let expected = vec![(
             String::from("file.c"),
             CovResult {
                 lines,
                 branches,
                 functions,
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         )];
 
@@ -1460,36 +1480,44 @@ mod tests {
             String::from("foo"),
             Function {
                 start: 1,
+                end: None,
                 executed: true,
+                count: 2,
             },
         );
         functions.insert(
             String::from("bar"),
             Function {
                 start: 12,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
         functions.insert(
             String::from("oof"),
             Function {
                 start: 20,
+                end: None,
                 executed: false,
+                count: 0,
             },
         );
         functions.insert(
             String::from("main"),
             Function {
                 start: 31,
+                end: None,
                 executed: true,
+                count: 1,
             },
         );
-        let mut branches: BTreeMap<u32, Vec<bool>> = BTreeMap::new();
+        let mut branches: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
         [
-            (2, vec![true, true]),
-            (3, vec![true, false]),
-            (13, vec![false, true]),
-            (21, vec![false, false, false, false]),
+            (2, vec![1, 1]),
+            (3, vec![1, 0]),
+            (13, vec![0, 1]),
+            (21, vec![0, 0, 0, 0]),
         ]
         .iter()
         .for_each(|x| {
@@ -1502,6 +1530,8 @@ mod tests {
                 lines,
                 branches,
                 functions,
+                regions: BTreeMap::new(),
+                contexts: Contexts::default(),
             },
         )];
 
@@ -0,0 +1,135 @@
+use quick_xml::{
+    events::{BytesDecl, BytesEnd, BytesStart, Event},
+    Writer,
+};
+use std::collections::BTreeSet;
+use std::io::{BufWriter, Cursor, Write};
+
+use crate::defs::CovResultIter;
+use crate::error::GrcovError;
+use crate::output::get_target_output_writable;
+
+// https://docs.sonarqube.org/latest/analysis/generic-test/
+
+pub fn output_sonarqube(
+    results: CovResultIter,
+    output_file: Option<&str>,
+) -> Result<(), GrcovError> {
+    write_sonarqube(results, get_target_output_writable(output_file)?)
+}
+
+pub fn write_sonarqube(results: CovResultIter, output: impl Write) -> Result<(), GrcovError> {
+    let mut writer = Writer::new_with_indent(Cursor::new(vec![]), b' ', 4);
+    writer
+        .write_event(Event::Decl(BytesDecl::new(b"1.0", None, None)))
+        .unwrap();
+
+    let cov_tag = b"coverage";
+    let mut cov = BytesStart::borrowed(cov_tag, cov_tag.len());
+    cov.push_attribute(("version", "1"));
+    writer.write_event(Event::Start(cov)).unwrap();
+
+    let file_tag = b"file";
+    let line_tag = b"lineToCover";
+    for (_, rel_path, result) in results {
+        let mut file = BytesStart::borrowed(file_tag, file_tag.len());
+        file.push_attribute(("path", rel_path.to_str().unwrap_or_default()));
+        writer.write_event(Event::Start(file)).unwrap();
+
+        // Lines with branches might not have a line count (e.g. coming from JaCoCo).
+        let line_numbers: BTreeSet<u32> = result
+            .lines
+            .keys()
+            .chain(result.branches.keys())
+            .cloned()
+            .collect();
+        for number in line_numbers {
+            let branches = result.branches.get(&number);
+            let covered = match result.lines.get(&number) {
+                Some(&hits) => hits > 0,
+                None => branches.map_or(false, |b| b.iter().any(|&count| count > 0)),
+            };
+
+            let mut l = BytesStart::borrowed(line_tag, line_tag.len());
+            l.push_attribute(("lineNumber", number.to_string().as_ref()));
+            l.push_attribute(("covered", covered.to_string().as_ref()));
+            if let Some(branches) = branches {
+                let covered_branches = branches.iter().filter(|&&count| count > 0).count();
+                l.push_attribute(("branchesToCover", branches.len().to_string().as_ref()));
+                l.push_attribute(("coveredBranches", covered_branches.to_string().as_ref()));
+            }
+            writer.write_event(Event::Empty(l)).unwrap();
+        }
+
+        writer
+            .write_event(Event::End(BytesEnd::borrowed(file_tag)))
+            .unwrap();
+    }
+
+    writer
+        .write_event(Event::End(BytesEnd::borrowed(cov_tag)))
+        .unwrap();
+
+    let result = writer.into_inner().into_inner();
+    let mut file = BufWriter::new(output);
+    file.write_all(&result)?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Contexts, CovResult};
+    use rustc_hash::FxHashMap;
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_sonarqube() {
+        let tmp_dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file_path = tmp_dir.path().join("test_sonarqube.xml");
+
+        let results = vec![
+            (
+                PathBuf::from("/src/main.rs"),
+                PathBuf::from("src/main.rs"),
+                CovResult {
+                    lines: [(1, 1), (2, 0), (3, 2)].iter().cloned().collect(),
+                    branches: [(3, vec![1, 0]), (4, vec![0, 0])].iter().cloned().collect(),
+                    functions: FxHashMap::default(),
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
+                },
+            ),
+            (
+                PathBuf::from("/src/lib.rs"),
+                PathBuf::from("src/lib.rs"),
+                CovResult::default(),
+            ),
+        ];
+
+        let results = Box::new(results.into_iter());
+        output_sonarqube(results, Some(file_path.to_str().unwrap())).unwrap();
+
+        let mut results = String::new();
+        File::open(&file_path)
+            .unwrap()
+            .read_to_string(&mut results)
+            .unwrap();
+
+        assert!(results.contains(r#"<coverage version="1">"#));
+        assert!(results.contains(r#"<file path="src/main.rs">"#));
+        assert!(results.contains(r#"<lineToCover lineNumber="1" covered="true"/>"#));
+        assert!(results.contains(r#"<lineToCover lineNumber="2" covered="false"/>"#));
+        assert!(results.contains(
+            r#"<lineToCover lineNumber="3" covered="true" branchesToCover="2" coveredBranches="1"/>"#
+        ));
+        assert!(results.contains(
+            r#"<lineToCover lineNumber="4" covered="false" branchesToCover="2" coveredBranches="0"/>"#
+        ));
+        assert!(results.contains(r#"<file path="src/lib.rs">"#));
+    }
+}
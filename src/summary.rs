@@ -0,0 +1,381 @@
+use std::cmp::Ordering;
+use std::io::{BufWriter, Write};
+use std::path::{Path, MAIN_SEPARATOR};
+
+use crate::defs::*;
+use crate::error::GrcovError;
+use crate::html;
+use crate::output::get_target_output_writable;
+
+/// The order of the rows of the summary table, within the directories.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SummarySort {
+    /// By path.
+    Name,
+    /// By line coverage, from the least covered.
+    Lines,
+    /// By function coverage, from the least covered.
+    Functions,
+    /// By branch coverage, from the least covered.
+    Branches,
+}
+
+/// The options of the summary table.
+#[derive(Clone, Debug)]
+pub struct SummaryOptions {
+    pub sort: SummarySort,
+    /// Colors the coverage percentages with the severity bands of the HTML report.
+    pub color: bool,
+}
+
+impl Default for SummaryOptions {
+    fn default() -> Self {
+        SummaryOptions {
+            sort: SummarySort::Name,
+            color: false,
+        }
+    }
+}
+
+/// A column group of the table: the total, the missed count and the coverage of lines, functions
+/// or branches.
+struct Column {
+    title: &'static str,
+    get: fn(&HtmlStats) -> (usize, usize),
+    hi_limit: f64,
+    med_limit: f64,
+}
+
+fn get_lines(stats: &HtmlStats) -> (usize, usize) {
+    (stats.covered_lines, stats.total_lines)
+}
+
+fn get_functions(stats: &HtmlStats) -> (usize, usize) {
+    (stats.covered_funs, stats.total_funs)
+}
+
+fn get_branches(stats: &HtmlStats) -> (usize, usize) {
+    (stats.covered_branches, stats.total_branches)
+}
+
+/// Sorts the rows by name or from the least covered one, the rows without anything to cover
+/// being the last ones.
+fn sort_rows(rows: &mut [(String, HtmlStats)], sort: SummarySort) {
+    let get = match sort {
+        SummarySort::Name => None,
+        SummarySort::Lines => Some(get_lines as fn(&HtmlStats) -> (usize, usize)),
+        SummarySort::Functions => Some(get_functions as fn(&HtmlStats) -> (usize, usize)),
+        SummarySort::Branches => Some(get_branches as fn(&HtmlStats) -> (usize, usize)),
+    };
+    rows.sort_by(|(a_name, a), (b_name, b)| {
+        let order = match get {
+            Some(get) => {
+                let ((a_covered, a_total), (b_covered, b_total)) = (get(a), get(b));
+                match (
+                    html::get_percentage(a_covered, a_total),
+                    html::get_percentage(b_covered, b_total),
+                ) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b).unwrap(),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+            }
+            None => Ordering::Equal,
+        };
+        order.then_with(|| a_name.cmp(b_name))
+    });
+}
+
+/// Returns the rows of the table: the files of the top level directory, then every directory,
+/// with the stats of all the files it contains, followed by its own files.
+fn get_rows(global: &HtmlGlobalStats, sort: SummarySort) -> Vec<(String, HtmlStats)> {
+    let tree = html::get_dirs_tree(&global.dirs);
+    let mut dirs: Vec<_> = tree
+        .iter()
+        .filter(|(name, _)| !name.is_empty())
+        .map(|(name, dir_stats)| (name.clone(), dir_stats.stats.clone()))
+        .collect();
+    sort_rows(&mut dirs, sort);
+
+    let mut rows = Vec::new();
+    for (dir, stats) in std::iter::once((String::new(), global.stats.clone())).chain(dirs) {
+        let mut files: Vec<_> = match tree.get(&dir) {
+            Some(dir_stats) => dir_stats
+                .files
+                .iter()
+                .map(|(name, file_stats)| {
+                    let path = Path::new(&dir).join(name);
+                    (path.to_str().unwrap().to_string(), file_stats.stats.clone())
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        sort_rows(&mut files, sort);
+        if !dir.is_empty() {
+            rows.push((format!("{}{}", dir, MAIN_SEPARATOR), stats));
+        }
+        rows.extend(files);
+    }
+    rows
+}
+
+fn write_row(
+    writer: &mut impl Write,
+    name: &str,
+    name_width: usize,
+    stats: &HtmlStats,
+    columns: &[Column],
+    color: bool,
+) -> Result<(), GrcovError> {
+    write!(writer, "{:<width$}", name, width = name_width)?;
+    for column in columns {
+        let (covered, total) = (column.get)(stats);
+        write!(
+            writer,
+            "  {:>width$}  {:>6}  ",
+            total,
+            total - covered,
+            width = column.title.len().max(6)
+        )?;
+        match html::get_percentage(covered, total) {
+            Some(rate) => {
+                let cover = format!("{:>7}", format!("{:.2}%", rate));
+                if color {
+                    let code = match html::get_severity(column.hi_limit, column.med_limit, rate) {
+                        "success" => 32,
+                        "warning" => 33,
+                        _ => 31,
+                    };
+                    write!(writer, "\x1b[{}m{}\x1b[0m", code, cover)?;
+                } else {
+                    write!(writer, "{}", cover)?;
+                }
+            }
+            None => write!(writer, "{:>7}", "-")?,
+        }
+    }
+    writeln!(writer)?;
+    Ok(())
+}
+
+pub fn output_summary(
+    results: CovResultIter,
+    output_file: Option<&str>,
+    branch_enabled: bool,
+    options: &SummaryOptions,
+    html_options: &html::HtmlOptions,
+) -> Result<(), GrcovError> {
+    write_summary(
+        results,
+        get_target_output_writable(output_file)?,
+        branch_enabled,
+        options,
+        html_options,
+    )
+}
+
+/// Writes a table of the line, function and branch coverage of every file and directory, with
+/// the totals, for a quick look from a terminal.
+pub fn write_summary(
+    results: CovResultIter,
+    output: impl Write,
+    branch_enabled: bool,
+    options: &SummaryOptions,
+    html_options: &html::HtmlOptions,
+) -> Result<(), GrcovError> {
+    let global = html::get_global_stats(results);
+
+    let mut columns = vec![
+        Column {
+            title: "Lines",
+            get: get_lines,
+            hi_limit: html_options.hi_limit,
+            med_limit: html_options.med_limit,
+        },
+        Column {
+            title: "Functions",
+            get: get_functions,
+            hi_limit: html_options.fn_hi_limit,
+            med_limit: html_options.fn_med_limit,
+        },
+    ];
+    if branch_enabled {
+        columns.push(Column {
+            title: "Branches",
+            get: get_branches,
+            hi_limit: html_options.branch_hi_limit,
+            med_limit: html_options.branch_med_limit,
+        });
+    }
+
+    let rows = get_rows(&global, options.sort);
+    let name_width = rows
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .chain(std::iter::once("TOTAL".len()))
+        .max()
+        .unwrap();
+
+    let mut writer = BufWriter::new(output);
+    let mut header = format!("{:<width$}", "Name", width = name_width);
+    for column in &columns {
+        header.push_str(&format!(
+            "  {:>width$}  {:>6}  {:>7}",
+            column.title,
+            "Missed",
+            "Cover",
+            width = column.title.len().max(6)
+        ));
+    }
+    let separator = "-".repeat(header.len());
+    writeln!(writer, "{}", header)?;
+    writeln!(writer, "{}", separator)?;
+    for (name, stats) in &rows {
+        write_row(
+            &mut writer,
+            name,
+            name_width,
+            stats,
+            &columns,
+            options.color,
+        )?;
+    }
+    writeln!(writer, "{}", separator)?;
+    write_row(
+        &mut writer,
+        "TOTAL",
+        name_width,
+        &global.stats,
+        &columns,
+        options.color,
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHashMap;
+    use std::path::PathBuf;
+
+    fn get_results() -> Vec<(PathBuf, PathBuf, CovResult)> {
+        let mut functions = FxHashMap::default();
+        functions.insert(
+            "f".to_string(),
+            Function {
+                start: 1,
+                end: None,
+                executed: true,
+                count: 1,
+            },
+        );
+        vec![
+            (
+                PathBuf::from("build.rs"),
+                PathBuf::from("build.rs"),
+                CovResult {
+                    lines: [(1, 0), (2, 0)].iter().cloned().collect(),
+                    ..Default::default()
+                },
+            ),
+            (
+                PathBuf::from("src/a/lib.rs"),
+                PathBuf::from("src/a/lib.rs"),
+                CovResult {
+                    lines: [(1, 1), (2, 1), (3, 1), (4, 0)].iter().cloned().collect(),
+                    branches: [(2, vec![1, 0])].iter().cloned().collect(),
+                    functions,
+                    ..Default::default()
+                },
+            ),
+            (
+                PathBuf::from("src/main.rs"),
+                PathBuf::from("src/main.rs"),
+                CovResult {
+                    lines: [(1, 1), (2, 1)].iter().cloned().collect(),
+                    ..Default::default()
+                },
+            ),
+        ]
+    }
+
+    fn summary(branch_enabled: bool, options: &SummaryOptions) -> String {
+        let mut output = Vec::new();
+        write_summary(
+            Box::new(get_results().into_iter()),
+            &mut output,
+            branch_enabled,
+            options,
+            &html::HtmlOptions::default(),
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_write_summary() {
+        let output = summary(true, &SummaryOptions::default());
+        let separator = MAIN_SEPARATOR.to_string();
+        let expected = "\
+Name           Lines  Missed    Cover  Functions  Missed    Cover  Branches  Missed    Cover
+--------------------------------------------------------------------------------------------
+build.rs           2       2    0.00%          0       0        -         0       0        -
+src/               6       1   83.33%          1       0  100.00%         2       1   50.00%
+src/main.rs        2       0  100.00%          0       0        -         0       0        -
+src/a/             4       1   75.00%          1       0  100.00%         2       1   50.00%
+src/a/lib.rs       4       1   75.00%          1       0  100.00%         2       1   50.00%
+--------------------------------------------------------------------------------------------
+TOTAL              8       3   62.50%          1       0  100.00%         2       1   50.00%
+"
+        .replace('/', &separator);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_write_summary_sort() {
+        let output = summary(
+            false,
+            &SummaryOptions {
+                sort: SummarySort::Lines,
+                color: false,
+            },
+        );
+        let separator = MAIN_SEPARATOR.to_string();
+        let names: Vec<_> = output
+            .lines()
+            .skip(2)
+            .map(|line| line.split(' ').next().unwrap())
+            .collect();
+        let expected: Vec<_> = vec![
+            "build.rs",
+            "src/a/",
+            "src/a/lib.rs",
+            "src/",
+            "src/main.rs",
+            "-----------------------------------------------------------------",
+            "TOTAL",
+        ]
+        .into_iter()
+        .map(|name| name.replace('/', &separator))
+        .collect();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn test_write_summary_color() {
+        let output = summary(
+            false,
+            &SummaryOptions {
+                sort: SummarySort::Name,
+                color: true,
+            },
+        );
+        assert!(output.contains("\x1b[31m  0.00%\x1b[0m"));
+        assert!(output.contains("\x1b[33m 75.00%\x1b[0m"));
+        assert!(output.contains("\x1b[32m100.00%\x1b[0m"));
+        // The rows without anything to cover aren't colored.
+        assert_eq!(output.matches("\x1b[0m").count(), 10);
+    }
+}
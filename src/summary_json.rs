@@ -0,0 +1,350 @@
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use symbolic_demangle::DemangleOptions;
+
+use crate::defs::*;
+use crate::error::GrcovError;
+use crate::html;
+use crate::output::get_target_output_writable;
+
+/// The version of the schema of the JSON summary, increased when a field is changed or removed.
+pub const SUMMARY_JSON_VERSION: u32 = 1;
+
+/// The total, covered and missed lines, functions or branches, the percentage being null when
+/// there is nothing to cover.
+#[derive(Serialize, Debug, PartialEq)]
+struct SummaryStats {
+    total: usize,
+    covered: usize,
+    missed: usize,
+    percent: Option<f64>,
+}
+
+impl SummaryStats {
+    fn new(covered: usize, total: usize) -> Self {
+        Self {
+            total,
+            covered,
+            missed: total - covered,
+            percent: html::get_percentage(covered, total)
+                .map(|percent| f64::round(percent * 100.) / 100.),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct CoverageSummary {
+    lines: SummaryStats,
+    functions: SummaryStats,
+    branches: SummaryStats,
+}
+
+impl From<&HtmlStats> for CoverageSummary {
+    fn from(stats: &HtmlStats) -> Self {
+        Self {
+            lines: SummaryStats::new(stats.covered_lines, stats.total_lines),
+            functions: SummaryStats::new(stats.covered_funs, stats.total_funs),
+            branches: SummaryStats::new(stats.covered_branches, stats.total_branches),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct FunctionSummary {
+    name: String,
+    start: u32,
+    end: Option<u32>,
+    executed: bool,
+    count: u64,
+    lines: SummaryStats,
+    branches: SummaryStats,
+}
+
+#[derive(Serialize, Debug)]
+struct FileSummary {
+    name: String,
+    path: String,
+    #[serde(flatten)]
+    stats: CoverageSummary,
+    #[serde(rename = "functionList")]
+    function_list: Vec<FunctionSummary>,
+}
+
+#[derive(Serialize, Debug)]
+struct DirSummary {
+    name: String,
+    path: String,
+    #[serde(flatten)]
+    stats: CoverageSummary,
+    dirs: Vec<DirSummary>,
+    files: Vec<FileSummary>,
+}
+
+#[derive(Serialize, Debug)]
+struct Summary {
+    version: u32,
+    #[serde(flatten)]
+    root: DirSummary,
+}
+
+/// Returns the stats of the functions of a file, sorted by start line. A function without an end
+/// line is assumed to end before the next one starts.
+fn get_functions(result: &CovResult, demangle: bool) -> Vec<FunctionSummary> {
+    let demangle_options = DemangleOptions::name_only();
+    let mut functions: Vec<(&String, &Function)> = result.functions.iter().collect();
+    functions.sort_by_key(|&(name, f)| (f.start, name));
+
+    let last_line = result.lines.keys().last().copied().unwrap_or(0);
+    functions
+        .iter()
+        .map(|&(name, function)| {
+            let end = function.end.unwrap_or_else(|| {
+                functions
+                    .iter()
+                    .map(|(_, f)| f.start)
+                    .find(|&start| start > function.start)
+                    .map(|start| start - 1)
+                    .unwrap_or(last_line)
+            });
+            let end = end.max(function.start);
+            let lines = result.lines.range(function.start..=end);
+            let covered_lines = lines.clone().filter(|&(_, &count)| count > 0).count();
+            let branches = result
+                .branches
+                .range(function.start..=end)
+                .flat_map(|(_, counts)| counts);
+            let covered_branches = branches.clone().filter(|&&count| count > 0).count();
+
+            FunctionSummary {
+                name: demangle!(name, demangle, demangle_options).to_string(),
+                start: function.start,
+                end: function.end,
+                executed: function.executed,
+                count: function.count,
+                lines: SummaryStats::new(covered_lines, lines.count()),
+                branches: SummaryStats::new(covered_branches, branches.count()),
+            }
+        })
+        .collect()
+}
+
+fn get_dir_summary(
+    dir: &str,
+    tree: &BTreeMap<String, HtmlDirStats>,
+    files: &mut FxHashMap<String, Vec<FileSummary>>,
+) -> DirSummary {
+    let dir_stats = &tree[dir];
+    let path = Path::new(dir);
+    DirSummary {
+        name: path
+            .file_name()
+            .map(|name| name.to_str().unwrap().to_string())
+            .unwrap_or_default(),
+        path: html::get_page_path(path),
+        stats: CoverageSummary::from(&dir_stats.stats),
+        dirs: dir_stats
+            .dirs
+            .keys()
+            .map(|name| get_dir_summary(path.join(name).to_str().unwrap(), tree, files))
+            .collect(),
+        files: files.remove(dir).unwrap_or_default(),
+    }
+}
+
+pub fn output_summary_json(
+    results: CovResultIter,
+    output_file: Option<&str>,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    write_summary_json(results, get_target_output_writable(output_file)?, demangle)
+}
+
+/// Writes the line, function and branch stats of every directory, file and function in JSON,
+/// following the schema of version [`SUMMARY_JSON_VERSION`]. As in the HTML report, the files
+/// outside of the source directory are left out.
+pub fn write_summary_json(
+    results: CovResultIter,
+    output: impl Write,
+    demangle: bool,
+) -> Result<(), GrcovError> {
+    let results: Vec<_> = results
+        .filter(|(_, rel_path, _)| rel_path.is_relative())
+        .collect();
+
+    let mut files: FxHashMap<String, Vec<FileSummary>> = FxHashMap::default();
+    for (_, rel_path, result) in &results {
+        let parent = rel_path.parent().unwrap().to_str().unwrap().to_string();
+        files.entry(parent).or_default().push(FileSummary {
+            name: rel_path.file_name().unwrap().to_str().unwrap().to_string(),
+            path: html::get_page_path(rel_path),
+            stats: CoverageSummary::from(&html::get_stats(result)),
+            function_list: get_functions(result, demangle),
+        });
+    }
+    for dir_files in files.values_mut() {
+        dir_files.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    let global = html::get_global_stats(Box::new(results.into_iter()));
+    let mut tree = html::get_dirs_tree(&global.dirs);
+    tree.entry(String::new()).or_default();
+    let summary = Summary {
+        version: SUMMARY_JSON_VERSION,
+        root: get_dir_summary("", &tree, &mut files),
+    };
+
+    let mut writer = BufWriter::new(output);
+    serde_json::to_writer(&mut writer, &summary)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_write_summary_json() {
+        let mut functions = FxHashMap::default();
+        functions.insert(
+            "_ZN4test4mainE".to_string(),
+            Function {
+                start: 1,
+                end: None,
+                executed: true,
+                count: 2,
+            },
+        );
+        functions.insert(
+            "unused".to_string(),
+            Function {
+                start: 4,
+                end: Some(5),
+                executed: false,
+                count: 0,
+            },
+        );
+        let results = vec![
+            (
+                PathBuf::from("/src/a/lib.rs"),
+                PathBuf::from("src/a/lib.rs"),
+                CovResult {
+                    lines: [(1, 2), (2, 2), (3, 0), (4, 0), (5, 0), (7, 1)]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    branches: [(2, vec![1, 0]), (5, vec![0, 0])].iter().cloned().collect(),
+                    functions,
+                    ..Default::default()
+                },
+            ),
+            (
+                PathBuf::from("/build.rs"),
+                PathBuf::from("build.rs"),
+                CovResult {
+                    lines: [(1, 1)].iter().cloned().collect(),
+                    ..Default::default()
+                },
+            ),
+            // Left out as it is outside of the source directory.
+            (
+                PathBuf::from("/usr/include/stdio.h"),
+                PathBuf::from("/usr/include/stdio.h"),
+                CovResult {
+                    lines: [(1, 1)].iter().cloned().collect(),
+                    ..Default::default()
+                },
+            ),
+        ];
+
+        let mut output = Vec::new();
+        write_summary_json(Box::new(results.into_iter()), &mut output, true).unwrap();
+        let summary: Value = serde_json::from_slice(&output).unwrap();
+
+        let lib_stats = json!({
+            "lines": {"total": 6, "covered": 3, "missed": 3, "percent": 50.0},
+            "functions": {"total": 2, "covered": 1, "missed": 1, "percent": 50.0},
+            "branches": {"total": 4, "covered": 1, "missed": 3, "percent": 25.0},
+        });
+        let with_stats = |value: Value, stats: &Value| {
+            let mut value = value;
+            for (key, stat) in stats.as_object().unwrap() {
+                value[key] = stat.clone();
+            }
+            value
+        };
+        let lib = with_stats(
+            json!({
+                "name": "lib.rs",
+                "path": "src/a/lib.rs",
+                "functionList": [
+                    {
+                        "name": "test::main",
+                        "start": 1,
+                        "end": null,
+                        "executed": true,
+                        "count": 2,
+                        "lines": {"total": 3, "covered": 2, "missed": 1, "percent": 66.67},
+                        "branches": {"total": 2, "covered": 1, "missed": 1, "percent": 50.0},
+                    },
+                    {
+                        "name": "unused",
+                        "start": 4,
+                        "end": 5,
+                        "executed": false,
+                        "count": 0,
+                        "lines": {"total": 2, "covered": 0, "missed": 2, "percent": 0.0},
+                        "branches": {"total": 2, "covered": 0, "missed": 2, "percent": 0.0},
+                    },
+                ],
+            }),
+            &lib_stats,
+        );
+        let dir = |name: &str, path: &str, dirs: Value, files: Value| {
+            with_stats(
+                json!({"name": name, "path": path, "dirs": dirs, "files": files}),
+                &lib_stats,
+            )
+        };
+        let src = dir(
+            "src",
+            "src",
+            json!([dir("a", "src/a", json!([]), json!([lib]))]),
+            json!([]),
+        );
+        let build = json!({
+            "name": "build.rs",
+            "path": "build.rs",
+            "lines": {"total": 1, "covered": 1, "missed": 0, "percent": 100.0},
+            "functions": {"total": 0, "covered": 0, "missed": 0, "percent": null},
+            "branches": {"total": 0, "covered": 0, "missed": 0, "percent": null},
+            "functionList": [],
+        });
+        let expected = json!({
+            "version": 1,
+            "name": "",
+            "path": "",
+            "lines": {"total": 7, "covered": 4, "missed": 3, "percent": 57.14},
+            "functions": {"total": 2, "covered": 1, "missed": 1, "percent": 50.0},
+            "branches": {"total": 4, "covered": 1, "missed": 3, "percent": 25.0},
+            "dirs": [src],
+            "files": [build],
+        });
+        assert_eq!(summary, expected);
+    }
+
+    #[test]
+    fn test_write_summary_json_empty() {
+        let mut output = Vec::new();
+        write_summary_json(Box::new(Vec::new().into_iter()), &mut output, false).unwrap();
+        let summary: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(summary["version"], json!(SUMMARY_JSON_VERSION));
+        assert_eq!(summary["lines"]["percent"], Value::Null);
+        assert_eq!(summary["dirs"], json!([]));
+    }
+}
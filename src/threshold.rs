@@ -0,0 +1,281 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::defs::*;
+
+/// Exit code used when the coverage is below one of the requested thresholds.
+pub const THRESHOLD_EXIT_CODE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThresholdKind {
+    Lines,
+    Functions,
+    Branches,
+}
+
+impl fmt::Display for ThresholdKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ThresholdKind::Lines => write!(f, "line"),
+            ThresholdKind::Functions => write!(f, "function"),
+            ThresholdKind::Branches => write!(f, "branch"),
+        }
+    }
+}
+
+/// Minimum coverage percentages, each one is checked separately.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CoverageLimits {
+    pub lines: Option<f64>,
+    pub functions: Option<f64>,
+    pub branches: Option<f64>,
+}
+
+impl CoverageLimits {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_none() && self.functions.is_none() && self.branches.is_none()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Thresholds {
+    pub global: CoverageLimits,
+    pub per_file: CoverageLimits,
+}
+
+impl Thresholds {
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty() && self.per_file.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ThresholdFailure {
+    /// The file which is below the threshold, `None` for the global coverage.
+    pub path: Option<PathBuf>,
+    pub kind: ThresholdKind,
+    pub coverage: f64,
+    pub threshold: f64,
+}
+
+impl fmt::Display for ThresholdFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.path {
+            Some(ref path) => write!(
+                f,
+                "{}: {} coverage {:.2}% is below the threshold of {:.2}%",
+                path.display(),
+                self.kind,
+                self.coverage,
+                self.threshold
+            ),
+            None => write!(
+                f,
+                "Global {} coverage {:.2}% is below the threshold of {:.2}%",
+                self.kind, self.coverage, self.threshold
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    lines: (usize, usize),
+    functions: (usize, usize),
+    branches: (usize, usize),
+}
+
+impl Counts {
+    fn new(result: &CovResult) -> Self {
+        Self {
+            lines: (
+                result.lines.values().filter(|&&c| c > 0).count(),
+                result.lines.len(),
+            ),
+            functions: (
+                result.functions.values().filter(|f| f.executed).count(),
+                result.functions.len(),
+            ),
+            branches: (
+                result
+                    .branches
+                    .values()
+                    .map(|v| v.iter().filter(|&&b| b > 0).count())
+                    .sum(),
+                result.branches.values().map(|v| v.len()).sum(),
+            ),
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.lines.0 += other.lines.0;
+        self.lines.1 += other.lines.1;
+        self.functions.0 += other.functions.0;
+        self.functions.1 += other.functions.1;
+        self.branches.0 += other.branches.0;
+        self.branches.1 += other.branches.1;
+    }
+
+    fn check(&self, limits: &CoverageLimits, path: Option<&PathBuf>) -> Vec<ThresholdFailure> {
+        let checks = [
+            (ThresholdKind::Lines, limits.lines, self.lines),
+            (ThresholdKind::Functions, limits.functions, self.functions),
+            (ThresholdKind::Branches, limits.branches, self.branches),
+        ];
+
+        checks
+            .iter()
+            .filter_map(|&(kind, threshold, (covered, total))| {
+                let threshold = threshold?;
+                // Nothing to measure (e.g. a file without branches), so nothing to fail.
+                if total == 0 {
+                    return None;
+                }
+                let coverage = covered as f64 / total as f64 * 100.;
+                if coverage < threshold {
+                    Some(ThresholdFailure {
+                        path: path.cloned(),
+                        kind,
+                        coverage,
+                        threshold,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Checks the aggregated results against the given thresholds and returns all the failures,
+/// per-file failures first and the global ones last.
+pub fn check_thresholds(
+    results: &[(PathBuf, PathBuf, CovResult)],
+    thresholds: &Thresholds,
+) -> Vec<ThresholdFailure> {
+    let mut failures = Vec::new();
+    let mut global = Counts::default();
+
+    for (_, rel_path, result) in results {
+        let counts = Counts::new(result);
+        if !thresholds.per_file.is_empty() {
+            failures.append(&mut counts.check(&thresholds.per_file, Some(rel_path)));
+        }
+        global.add(&counts);
+    }
+
+    failures.append(&mut global.check(&thresholds.global, None));
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHashMap;
+    use std::collections::BTreeMap;
+
+    fn get_results() -> Vec<(PathBuf, PathBuf, CovResult)> {
+        let mut functions: FunctionMap = FxHashMap::default();
+        functions.insert(
+            "f1".to_string(),
+            Function {
+                start: 1,
+                end: None,
+                executed: true,
+                count: 1,
+            },
+        );
+        functions.insert(
+            "f2".to_string(),
+            Function {
+                start: 5,
+                end: None,
+                executed: false,
+                count: 0,
+            },
+        );
+        vec![
+            (
+                PathBuf::from("/src/a.cpp"),
+                PathBuf::from("a.cpp"),
+                CovResult {
+                    lines: [(1, 1), (2, 1), (3, 1), (4, 1)].iter().cloned().collect(),
+                    branches: [(2, vec![1, 1])].iter().cloned().collect(),
+                    functions: FxHashMap::default(),
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
+                },
+            ),
+            (
+                PathBuf::from("/src/b.cpp"),
+                PathBuf::from("b.cpp"),
+                CovResult {
+                    lines: [(1, 1), (5, 0), (6, 0), (7, 0)].iter().cloned().collect(),
+                    branches: BTreeMap::new(),
+                    functions,
+                    regions: BTreeMap::new(),
+                    contexts: Contexts::default(),
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_no_thresholds() {
+        let thresholds = Thresholds::default();
+        assert!(thresholds.is_empty());
+        assert!(check_thresholds(&get_results(), &thresholds).is_empty());
+    }
+
+    #[test]
+    fn test_global_thresholds() {
+        let mut thresholds = Thresholds::default();
+        thresholds.global.lines = Some(62.5);
+        thresholds.global.functions = Some(50.);
+        thresholds.global.branches = Some(100.);
+        assert!(check_thresholds(&get_results(), &thresholds).is_empty());
+
+        thresholds.global.lines = Some(70.);
+        thresholds.global.functions = Some(60.);
+        let failures = check_thresholds(&get_results(), &thresholds);
+        assert_eq!(
+            failures,
+            vec![
+                ThresholdFailure {
+                    path: None,
+                    kind: ThresholdKind::Lines,
+                    coverage: 62.5,
+                    threshold: 70.,
+                },
+                ThresholdFailure {
+                    path: None,
+                    kind: ThresholdKind::Functions,
+                    coverage: 50.,
+                    threshold: 60.,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_per_file_thresholds() {
+        let mut thresholds = Thresholds::default();
+        thresholds.per_file.lines = Some(50.);
+        thresholds.per_file.branches = Some(50.);
+        let failures = check_thresholds(&get_results(), &thresholds);
+        assert_eq!(
+            failures,
+            vec![ThresholdFailure {
+                path: Some(PathBuf::from("b.cpp")),
+                kind: ThresholdKind::Lines,
+                coverage: 25.,
+                threshold: 50.,
+            }]
+        );
+        assert_eq!(
+            failures[0].to_string(),
+            "b.cpp: line coverage 25.00% is below the threshold of 50.00%"
+        );
+    }
+}